@@ -0,0 +1,82 @@
+-- nvim-cmp source completing from the selected kernel's live namespace.
+--   require("cmp").register_source("run_jupyter", require("run-jupyter.cmp_source").new())
+local kernel = require("run-jupyter.kernel")
+
+local api = vim.api
+
+local source = {}
+
+-- _jupyter_types_experimental types to lsp kinds
+local kinds = {
+	["function"] = "Function",
+	["class"] = "Class",
+	["module"] = "Module",
+	["instance"] = "Variable",
+	["statement"] = "Variable",
+	["param"] = "Variable",
+	["property"] = "Property",
+	["keyword"] = "Keyword",
+	["path"] = "File",
+	["magic"] = "Function",
+}
+
+function source.new()
+	return setmetatable({}, { __index = source })
+end
+
+function source:is_available()
+	return kernel.has_current_kernel()
+end
+
+-- textEdit ranges are byte columns
+function source:get_position_encoding_kind()
+	return "utf-8"
+end
+
+function source:get_debug_name()
+	return "run_jupyter"
+end
+
+function source:get_trigger_characters()
+	return { "." }
+end
+
+function source:complete(params, callback)
+	local cursor = params.context.cursor
+	-- the whole buffer up to the cursor line, so that completions see the names defined above it
+	local lines = api.nvim_buf_get_lines(params.context.bufnr, 0, cursor.row, false)
+	local code = table.concat(lines, "\n")
+	local line_start = #code - #lines[#lines]
+	local cursor_pos = line_start + cursor.col - 1
+
+	local result = kernel.complete(code, cursor_pos)
+	if result["error"] ~= nil then
+		callback({ items = {}, isIncomplete = false })
+		return
+	end
+
+	local completion = result["data"]
+	local types = {}
+	for _, typed in ipairs(completion.types or {}) do
+		types[typed.text] = typed
+	end
+	local cmp_kinds = require("cmp").lsp.CompletionItemKind
+	local range = {
+		start = { line = cursor.row - 1, character = math.max(completion.cursor_start - line_start, 0) },
+		["end"] = { line = cursor.row - 1, character = math.max(completion.cursor_end - line_start, 0) },
+	}
+
+	local items = {}
+	for _, match in ipairs(completion.matches) do
+		local typed = types[match] or {}
+		table.insert(items, {
+			label = match,
+			kind = cmp_kinds[kinds[typed.type] or "Text"],
+			detail = typed.signature ~= "" and typed.signature or nil,
+			textEdit = { newText = match, range = range },
+		})
+	end
+	callback({ items = items, isIncomplete = false })
+end
+
+return source
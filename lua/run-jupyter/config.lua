@@ -1,10 +1,70 @@
 local M = {}
 local config = {
 	jupyter = {
-		endpoint = "http://localhost:8888",
+		-- nil picks the running local server whose root_dir contains the buffer, then a server started by the plugin.
+		-- "local" needs no server: kernels are launched from their kernelspecs and driven over zmq
+		endpoint = nil,
+		-- nil falls back to a ?token= in the endpoint, the discovered server's token and then $JUPYTER_TOKEN
+		token = nil,
+		password = nil,
+		-- the server's root directory. buffers under it are bound to sessions by their relative path.
+		-- nil uses the discovered server's root_dir when endpoint is nil
+		root_dir = nil,
+		-- launched when endpoint is nil and no running server was discovered. stopped when neovim exits
+		server = {
+			auto_start = true,
+			executable = "jupyter",
+			-- "server" or "lab"
+			subcommand = "server",
+			-- nil picks a free port
+			port = nil,
+		},
+	},
+	execution = {
+		poll_interval_ms = 50,
+		-- how far below the cursor a statement sent from normal mode may reach
+		max_statement_lines = 200,
+		-- an execution running longer is interrupted. nil lets it run forever
+		timeout_ms = nil,
+		-- how long the interrupted code gets to stop before on_timeout applies
+		interrupt_grace_ms = 5000,
+		-- when the kernel is still busy after the interrupt: "ask" to restart it, "restart", or "interrupt" to leave it
+		on_timeout = "ask",
+		-- cancel the code queued on the kernel when an execution fails or raises
+		stop_on_error = false,
+	},
+	-- what becomes of the kernels started by the plugin. kernels of buffer sessions are started by the server
+	-- with the session, and none of these apply to them
+	kernel = {
+		-- when neovim exits: "shutdown" them, "leave" them running, or "keep" them, which also spares them the idle timeout
+		on_exit = "shutdown",
+		-- a kernel that has run nothing for this long is shut down, which is told also with monitor disabled.
+		-- nil keeps idle kernels running
+		idle_timeout_ms = nil,
+		-- the working directory of started kernels. nil uses the buffer's project root, found by root_markers
+		cwd = nil,
+		root_markers = { ".git", "pyproject.toml", "setup.py", "Cargo.toml" },
+		-- environment variables of started kernels, set over those of env_file.
+		-- servers may leave them out, which is checked and warned of in python kernels
+		env = {},
+		-- read from the kernel's working directory when it exists. nil reads none
+		env_file = ".env",
+		-- run before the kernel is used, by the language it reports, e.g.
+		-- { python = "%load_ext autoreload\n%autoreload 2", rust = ':dep serde = "1"' }
+		startup_code = {},
+	},
+	-- checks the kernels in use in the background, telling when one dies, is restarted or its server is unreachable.
+	-- a closed connection to a living kernel is made again
+	monitor = {
+		enabled = true,
+		interval_ms = 5000,
 	},
 	output = {
 		image_view_cmd = nil,
+		-- MIME types to pick the shown representation from, preferred first. nil keeps the built-in order:
+		-- { "text/markdown", "text/plain", "application/json", "text/latex", "text/html", "image/png", "image/svg+xml", "image/jpeg" }
+		-- e.g. put "image/png" first to have figures opened with image_view_cmd
+		mime_priority = nil,
 	},
 }
 
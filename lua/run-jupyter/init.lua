@@ -1,3 +1,4 @@
+local jupyter_client = require("librun_jupyter")
 local config = require("run-jupyter.config")
 local window = require("run-jupyter.window")
 local kernel = require("run-jupyter.kernel")
@@ -5,12 +6,53 @@ local kernel = require("run-jupyter.kernel")
 local M = {}
 function M.setup(user_config)
 	config.build(user_config)
+	local jupyter = config.get().jupyter
+	if jupyter.endpoint then
+		jupyter_client.set_credentials(jupyter.endpoint, jupyter.token, jupyter.password)
+	end
+	local kernel_config = config.get().kernel
+	jupyter_client.set_kernel_lifecycle({
+		on_exit = kernel_config.on_exit,
+		idle_timeout_ms = kernel_config.idle_timeout_ms or 0,
+	})
+	vim.api.nvim_create_autocmd("VimLeavePre", {
+		group = vim.api.nvim_create_augroup("run_jupyter_servers", { clear = true }),
+		-- the kernels go first, while their server still runs
+		callback = function()
+			jupyter_client.shutdown_started_kernels()
+			kernel.stop_spawned_servers()
+		end,
+	})
+	local execution = config.get().execution
+	jupyter_client.set_execution_timeout({
+		timeout_ms = execution.timeout_ms or 0,
+		interrupt_grace_ms = execution.interrupt_grace_ms,
+		restart = execution.on_timeout == "restart",
+	})
+	kernel.start_monitoring()
+	local mime_priority = config.get().output.mime_priority
+	if mime_priority then
+		jupyter_client.set_mime_priority(mime_priority)
+	end
 end
 
 M.close_result_window = window.close_result_window
 M.open_start_kernel_selection = kernel.open_start_kernel_selection
+M.open_start_buffer_kernel_selection = kernel.open_start_buffer_kernel_selection
+M.start_buffer_kernel = kernel.start_buffer_kernel
+M.attach_buffer_kernel = kernel.attach_buffer_kernel
+M.bind_current_kernel_to_buffer = kernel.bind_current_kernel_to_buffer
 M.open_kill_kernel_selection = kernel.open_kill_kernel_selection
 M.open_switch_kernel_selection = kernel.open_switch_kernel_selection
+M.open_attach_local_kernel_selection = kernel.open_attach_local_kernel_selection
+M.restart_current_kernel = kernel.restart_current_kernel
+M.set_current_kernel_shutdown_policy = kernel.set_current_kernel_shutdown_policy
+M.current_kernel_status = kernel.current_kernel_status
+M.current_kernel_info = kernel.current_kernel_info
 M.run_selecting_code = kernel.run_selecting_code
+M.cancel_running_code = kernel.cancel_running_code
+M.clear_execution_queue = kernel.clear_execution_queue
+M.inspect_under_cursor = kernel.inspect_under_cursor
+M.open_history_selection = kernel.open_history_selection
 
 return M
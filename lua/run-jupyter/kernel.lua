@@ -7,90 +7,260 @@ local finders = require("telescope.finders")
 local conf = require("telescope.config").values
 local actions = require("telescope.actions")
 local action_state = require("telescope.actions.state")
-local status = { current_kernel_id = nil }
+-- current_kernel_endpoint is set when the current kernel is not on the configured endpoint, e.g. an attached one
+local status = {
+	current_kernel_id = nil,
+	current_kernel_endpoint = nil,
+	current_session_id = nil,
+}
 
 local M = {}
 local running_kernel_surffix = " <running>"
 
 local fn = vim.fn
 local api = vim.api
-local schedule = vim.schedule
+local uv = vim.loop
+local schedule_wrap = vim.schedule_wrap
+
+local default_endpoint = "http://localhost:8888"
+-- url -> server found in the jupyter runtime dir, so each one's credentials are registered once
+local discovered_servers = {}
+
+-- the local server whose root_dir contains the current buffer, or the working directory for unnamed buffers
+local function discover_server()
+	local path = fn.expand("%:p")
+	if path == "" then
+		path = fn.getcwd()
+	end
+	local result = jupyter_client.discover_servers(path)
+	if result["error"] ~= nil or result["data"][1] == nil then
+		return nil
+	end
+
+	local server = result["data"][1]
+	if not discovered_servers[server.url] then
+		local jupyter = config.get().jupyter
+		local token = jupyter.token
+		if not token and server.token ~= "" then
+			token = server.token
+		end
+		jupyter_client.set_credentials(server.url, token, jupyter.password)
+		discovered_servers[server.url] = server
+	end
+	return server
+end
+
+-- the server started by the plugin when none was running
+local spawned_server = nil
+local server_start_timer = nil
+local server_start_poll_interval_ms = 200
+
+local function stop_server_start_timer()
+	if server_start_timer then
+		server_start_timer:stop()
+		server_start_timer:close()
+		server_start_timer = nil
+	end
+end
+
+-- the server is started in the background, so the user is told here once it listens or failed
+local function wait_for_spawned_server()
+	stop_server_start_timer()
+	server_start_timer = uv.new_timer()
+	server_start_timer:start(
+		server_start_poll_interval_ms,
+		server_start_poll_interval_ms,
+		schedule_wrap(function()
+			for _, server_event in ipairs(jupyter_client.poll_server_events()["data"]) do
+				if spawned_server and server_event.url == spawned_server.url then
+					stop_server_start_timer()
+					if server_event.event == "ready" then
+						spawned_server.ready = true
+						vim.notify("jupyter server started at " .. server_event.url, vim.log.levels.INFO)
+					else
+						spawned_server = nil
+						window.output_result("Error:\n" .. server_event["error"])
+					end
+				end
+			end
+		end)
+	)
+end
+
+local function spawn_server()
+	if spawned_server then
+		return spawned_server
+	end
+	local jupyter = config.get().jupyter
+	if not jupyter.server.auto_start then
+		return nil
+	end
+	local result = jupyter_client.start_server({
+		executable = jupyter.server.executable,
+		subcommand = jupyter.server.subcommand,
+		port = jupyter.server.port,
+		root_dir = fn.fnamemodify(jupyter.root_dir or fn.getcwd(), ":p"),
+	})
+	if result["error"] ~= nil then
+		window.output_result("Error:\n" .. result["error"])
+		return nil
+	end
+	spawned_server = result["data"]
+	vim.notify("starting jupyter server at " .. spawned_server.url, vim.log.levels.INFO)
+	wait_for_spawned_server()
+	return spawned_server
+end
+
+local function endpoint()
+	local configured = config.get().jupyter.endpoint
+	if configured then
+		return configured
+	end
+	local server = discover_server() or spawn_server()
+	if server then
+		return server.url
+	end
+	return default_endpoint
+end
+
+-- the endpoint the current kernel is on
+local function kernel_endpoint()
+	return status.current_kernel_endpoint or endpoint()
+end
+
+local function root_dir()
+	local jupyter = config.get().jupyter
+	if jupyter.root_dir or jupyter.endpoint then
+		return jupyter.root_dir
+	end
+	local server = discover_server() or spawned_server
+	return server and server.root_dir
+end
+
+function M.stop_spawned_servers()
+	stop_server_start_timer()
+	jupyter_client.stop_servers()
+	spawned_server = nil
+end
 
 local function get_running_kernels()
-	return jupyter_client.list_running_kernels(config.get().jupyter.endpoint)
+	return jupyter_client.list_running_kernels(endpoint())
 end
 
-local function get_all_kernel_names()
-	return jupyter_client.list_kernel_names(config.get().jupyter.endpoint)
+-- { data = { default, kernel_specs } } of the language if given
+local function get_kernel_specs(language)
+	return jupyter_client.list_kernel_specs(endpoint(), { language = language })
 end
 
-local function start_kernel(kernel_name)
-	return jupyter_client.start_kernel(config.get().jupyter.endpoint, kernel_name)
+-- the display_name, with the name kernels are started by when the display_name does not tell it
+local function kernel_spec_label(kernel_spec)
+	if string.find(string.lower(kernel_spec.display_name), string.lower(kernel_spec.name), 1, true) then
+		return kernel_spec.display_name
+	end
+	return kernel_spec.display_name .. " [" .. kernel_spec.name .. "]"
 end
 
-local function send_code_to_kernel(kernel_id, code)
-	return jupyter_client.run_code(config.get().jupyter.endpoint, kernel_id, code)
+local function kernel_spec_finder(kernel_specs, suffix)
+	return finders.new_table({
+		results = kernel_specs,
+		entry_maker = function(kernel_spec)
+			local display = kernel_spec_label(kernel_spec) .. (suffix and suffix(kernel_spec) or "")
+			return {
+				value = kernel_spec,
+				display = display,
+				ordinal = display .. " " .. kernel_spec.name,
+			}
+		end,
+	})
 end
 
-local function delete_kernel(kernel_id)
-	return jupyter_client.delete_kernel(config.get().jupyter.endpoint, kernel_id)
+-- the directory of the nearest root marker above the buffer, or the working directory, ending with a separator
+local function project_root()
+	local kernel_config = config.get().kernel
+	if kernel_config.cwd then
+		return fn.fnamemodify(kernel_config.cwd, ":p")
+	end
+	local path = fn.expand("%:p:h")
+	if path == "" then
+		path = fn.getcwd()
+	end
+	local marker = vim.fs.find(kernel_config.root_markers, { upward = true, path = path })[1]
+	return fn.fnamemodify(marker and vim.fs.dirname(marker) or fn.getcwd(), ":p")
 end
 
-local function get_running_kernels_or_error()
-	local running_kernel_table = {}
-	local running_kernel_data = get_running_kernels()
-	for k, v in pairs(running_kernel_data) do
-		if k == "error" then
-			window.output_result("Error:\n" .. v)
-			return nil
-		elseif k == "data" then
-			for kernel_id, kernel_name in pairs(v) do
-				running_kernel_table[kernel_id] = kernel_name
+local function kernel_start_options(kernel_endpoint)
+	local kernel_config = config.get().kernel
+	local cwd = project_root()
+	local options = {
+		env = kernel_config.env,
+		startup_code = kernel_config.startup_code,
+	}
+	if kernel_config.env_file then
+		options.env_file = cwd .. kernel_config.env_file
+	end
+	if kernel_endpoint == "local" then
+		options.cwd = cwd
+	else
+		-- a server takes the directory relative to its root, and one outside of it is left to the server
+		local dir = root_dir()
+		if dir then
+			dir = fn.fnamemodify(dir, ":p")
+			if string.sub(cwd, 1, #dir) == dir then
+				options.cwd = string.sub(cwd, #dir + 1)
 			end
 		end
 	end
+	return options
+end
 
-	return running_kernel_table
+local function start_kernel(kernel_name)
+	local kernel_endpoint = endpoint()
+	local result = jupyter_client.start_kernel(kernel_endpoint, kernel_name, kernel_start_options(kernel_endpoint))
+	if result["env_error"] ~= nil then
+		vim.notify(result["env_error"], vim.log.levels.WARN)
+	end
+	if result["startup_error"] ~= nil then
+		vim.notify("the startup code failed: " .. result["startup_error"], vim.log.levels.WARN)
+	end
+	return result
 end
 
-local function run_kernel_candidates()
-	local result = {}
+local function delete_kernel(kernel_id)
+	return jupyter_client.delete_kernel(endpoint(), kernel_id)
+end
 
-	local running_kernel_name_table = {}
-	local running_kernel_table = get_running_kernels_or_error()
-	if not running_kernel_table then
+-- { { id, name, execution_state, last_activity, connections, session_paths, started_at } }
+local function get_running_kernels_or_error()
+	local result = get_running_kernels()
+	if result["error"] ~= nil then
+		window.output_result("Error:\n" .. result["error"])
 		return nil
 	end
-	if running_kernel_table["error"] ~= nil then
-		return running_kernel_table
-	end
+	return result["data"]
+end
 
-	for _, name in pairs(running_kernel_table) do
-		running_kernel_name_table[name] = name
+local function run_kernel_candidates()
+	local running_kernel_name_table = {}
+	local running_kernels = get_running_kernels_or_error()
+	if not running_kernels then
+		return nil
 	end
 
-	local all_kernels = get_all_kernel_names()
-	for k, v in pairs(all_kernels) do
-		if k == "error" then
-			window.output_result("Error:\n" .. v)
-			return
-		elseif k == "data" then
-			for _, kernel_name in pairs(v) do
-				if running_kernel_name_table[kernel_name] ~= nil then
-					table.insert(result, kernel_name .. running_kernel_surffix)
-				else
-					table.insert(result, kernel_name)
-				end
-			end
-		end
+	for _, kernel in ipairs(running_kernels) do
+		running_kernel_name_table[kernel.name] = kernel.name
 	end
 
-	return result
+	local kernel_specs = get_kernel_specs()
+	if kernel_specs["error"] ~= nil then
+		window.output_result("Error:\n" .. kernel_specs["error"])
+		return nil
+	end
+	return kernel_specs["data"]["kernel_specs"], running_kernel_name_table
 end
 
 function M.open_start_kernel_selection()
-	local kernel_names = run_kernel_candidates()
-	if not kernel_names then
+	local kernel_specs, running_kernel_name_table = run_kernel_candidates()
+	if not kernel_specs then
 		return
 	end
 
@@ -98,18 +268,20 @@ function M.open_start_kernel_selection()
 		opts = opts or {}
 		pickers.new(opts, {
 			prompt_title = "start kernel",
-			finder = finders.new_table({
-				results = kernel_names,
-			}),
+			finder = kernel_spec_finder(kernel_specs, function(kernel_spec)
+				if running_kernel_name_table[kernel_spec.name] ~= nil then
+					return running_kernel_surffix
+				end
+				return ""
+			end),
 			sorter = conf.generic_sorter(opts),
 			attach_mappings = function(prompt_bufnr, map)
 				actions.select_default:replace(function()
 					actions.close(prompt_bufnr)
-					local selection = action_state.get_selected_entry()
-					local selected_kernel = selection[1]
+					local selected_kernel = action_state.get_selected_entry().value
 
-					if not string.find(selected_kernel, running_kernel_surffix) then
-						local kernel_result = start_kernel(selected_kernel)
+					if running_kernel_name_table[selected_kernel.name] == nil then
+						local kernel_result = start_kernel(selected_kernel.name)
 						for k, v in pairs(kernel_result) do
 							if k == "error" then
 								window.output_result("Error:\n" .. v)
@@ -117,6 +289,7 @@ function M.open_start_kernel_selection()
 							elseif k == "data" then
 								local kernel_id = v
 								status.current_kernel_id = kernel_id
+								status.current_kernel_endpoint = nil
 							end
 						end
 					end
@@ -129,29 +302,171 @@ function M.open_start_kernel_selection()
 	selector()
 end
 
-local function get_running_kernel_array()
-	local running_kernel_table = get_running_kernels_or_error()
-	if not running_kernel_table then
+-- the buffer's path relative to the server root when it is under it, as the sessions API expects
+local function buffer_session_path()
+	local path = fn.expand("%:p")
+	if path == "" then
 		return nil
 	end
-	if running_kernel_table["error"] ~= nil then
-		return running_kernel_table
+	local dir = root_dir()
+	if dir then
+		dir = fn.fnamemodify(dir, ":p")
+		if string.sub(path, 1, #dir) == dir then
+			return string.sub(path, #dir + 1)
+		end
 	end
-	local running_kernel_array = {}
-	for id, name in pairs(running_kernel_table) do
-		local selecting_sign = ""
-		if id == status.current_kernel_id then
-			selecting_sign = "*"
+	return path
+end
+
+local function use_session(session_result)
+	if session_result["error"] ~= nil then
+		window.output_result("Error:\n" .. session_result["error"])
+		return
+	end
+	local session = session_result["data"]
+	-- a session whose kernel was deleted has none
+	if session == nil or session["kernel"] == nil or session["kernel"]["id"] == nil then
+		vim.notify("the session has no kernel, start one for the buffer", vim.log.levels.WARN)
+		return
+	end
+	status.current_session_id = session["id"]
+	status.current_kernel_id = session["kernel"]["id"]
+	status.current_kernel_endpoint = nil
+end
+
+-- the kernelspecs of the buffer's language, the default kernel first. all of them when none is of that language
+local function buffer_kernel_specs()
+	local result = get_kernel_specs(vim.bo.filetype ~= "" and vim.bo.filetype or nil)
+	if result["error"] == nil and #result["data"]["kernel_specs"] == 0 then
+		result = get_kernel_specs()
+	end
+	if result["error"] ~= nil then
+		window.output_result("Error:\n" .. result["error"])
+		return nil
+	end
+	local default = result["data"]["default"]
+	local kernel_specs = result["data"]["kernel_specs"]
+	table.sort(kernel_specs, function(a, b)
+		if (a.name == default) ~= (b.name == default) then
+			return a.name == default
 		end
-		table.insert(running_kernel_array, id .. "<" .. name .. ">" .. " " .. selecting_sign)
+		return a.name < b.name
+	end)
+	return kernel_specs
+end
+
+-- starts a kernel bound to the current buffer, or reuses the one already bound to it.
+-- the server starts the kernel with the session, in the buffer's directory and without the kernel options
+-- cwd, env, env_file and startup_code. it is not shut down by on_exit or idle_timeout_ms either,
+-- as the session may be shared with e.g. JupyterLab
+function M.open_start_buffer_kernel_selection()
+	local path = buffer_session_path()
+	if not path then
+		window.output_result("Error:\nbuffer has no file")
+		return
+	end
+
+	local kernel_specs = buffer_kernel_specs()
+	if not kernel_specs then
+		return
+	end
+
+	local selector = function(opts)
+		opts = opts or {}
+		pickers.new(opts, {
+			prompt_title = "start kernel for " .. path,
+			finder = kernel_spec_finder(kernel_specs),
+			sorter = conf.generic_sorter(opts),
+			attach_mappings = function(prompt_bufnr, map)
+				actions.select_default:replace(function()
+					actions.close(prompt_bufnr)
+					local selection = action_state.get_selected_entry()
+					use_session(jupyter_client.session_for_path(endpoint(), path, selection.value.name))
+				end)
+				return true
+			end,
+		}):find()
+	end
+
+	selector()
+end
+
+-- like open_start_buffer_kernel_selection, picking the kernel of the buffer's language without asking
+function M.start_buffer_kernel()
+	local path = buffer_session_path()
+	if not path then
+		window.output_result("Error:\nbuffer has no file")
+		return
+	end
+	local kernel_specs = buffer_kernel_specs()
+	if not kernel_specs or not kernel_specs[1] then
+		return
 	end
-	return running_kernel_array
+	use_session(jupyter_client.session_for_path(endpoint(), path, kernel_specs[1].name))
+end
+
+-- selects the kernel already bound to the current buffer, e.g. after restarting neovim
+function M.attach_buffer_kernel()
+	local path = buffer_session_path()
+	if not path then
+		window.output_result("Error:\nbuffer has no file")
+		return
+	end
+	use_session(jupyter_client.session_for_path(endpoint(), path))
+end
+
+-- binds the selected kernel to the current buffer so that it can be found from JupyterLab
+function M.bind_current_kernel_to_buffer()
+	local path = buffer_session_path()
+	if not path then
+		window.output_result("Error:\nbuffer has no file")
+		return
+	end
+	if not status.current_kernel_id then
+		window.output_result("Error:\nkernel not selected")
+		return
+	end
+	use_session(jupyter_client.bind_session(endpoint(), path, status.current_kernel_id))
+end
+
+-- e.g. "* python3  idle  analysis.ipynb  active 2022-06-30T00:00:00Z  1 conn  8a5b6c1e", * marking the current kernel
+local function running_kernel_label(kernel)
+	local fields = { kernel.id == status.current_kernel_id and "*" or " ", kernel.name }
+	table.insert(fields, kernel.execution_state or "unknown")
+	if kernel.session_paths[1] then
+		table.insert(fields, table.concat(kernel.session_paths, ","))
+	end
+	if kernel.started_at then
+		table.insert(fields, "started " .. string.sub(kernel.started_at, 1, 19))
+	end
+	if kernel.last_activity then
+		table.insert(fields, "active " .. string.sub(kernel.last_activity, 1, 19))
+	end
+	if kernel.connections then
+		table.insert(fields, kernel.connections .. " conn")
+	end
+	table.insert(fields, string.sub(kernel.id, 1, 8))
+	return table.concat(fields, "  ")
+end
+
+local function running_kernel_finder(running_kernels)
+	return finders.new_table({
+		results = running_kernels,
+		entry_maker = function(kernel)
+			local display = running_kernel_label(kernel)
+			return {
+				value = kernel,
+				display = display,
+				ordinal = display .. " " .. kernel.id,
+			}
+		end,
+	})
 end
 
 function M.open_kill_kernel_selection()
-	local running_kernel_array = get_running_kernel_array()
+	local running_kernels = get_running_kernels_or_error()
 
-	if not running_kernel_array then
+	if not running_kernels then
 		return nil
 	end
 
@@ -159,16 +474,12 @@ function M.open_kill_kernel_selection()
 		opts = opts or {}
 		pickers.new(opts, {
 			prompt_title = "kernel to kill",
-			finder = finders.new_table({
-				results = running_kernel_array,
-			}),
+			finder = running_kernel_finder(running_kernels),
 			sorter = conf.generic_sorter(opts),
 			attach_mappings = function(prompt_bufnr, map)
 				actions.select_default:replace(function()
 					actions.close(prompt_bufnr)
-					local selection = action_state.get_selected_entry()
-					local selected_kernel = selection[1]
-					local selected_kernel_id = string.gsub(selected_kernel, "<.*", "") -- TODO(tacogips) too ugly
+					local selected_kernel_id = action_state.get_selected_entry().value.id
 					delete_kernel(selected_kernel_id)
 					if status.current_kernel_id == selected_kernel_id then
 						status.current_kernel_id = nil
@@ -183,9 +494,9 @@ function M.open_kill_kernel_selection()
 end
 
 function M.open_switch_kernel_selection()
-	local running_kernel_array = get_running_kernel_array()
+	local running_kernels = get_running_kernels_or_error()
 
-	if not running_kernel_array then
+	if not running_kernels then
 		return nil
 	end
 
@@ -193,17 +504,67 @@ function M.open_switch_kernel_selection()
 		opts = opts or {}
 		pickers.new(opts, {
 			prompt_title = "switch kernel",
+			finder = running_kernel_finder(running_kernels),
+			sorter = conf.generic_sorter(opts),
+			attach_mappings = function(prompt_bufnr, map)
+				actions.select_default:replace(function()
+					actions.close(prompt_bufnr)
+					status.current_kernel_id = action_state.get_selected_entry().value.id
+					status.current_kernel_endpoint = nil
+				end)
+				return true
+			end,
+		}):find()
+	end
+
+	selector()
+end
+
+-- attaches a kernel started outside of neovim (VS Code, Spyder, a plain ipykernel...) by its connection file
+function M.open_attach_local_kernel_selection()
+	local result = jupyter_client.list_local_kernels()
+	if result["error"] ~= nil then
+		window.output_result("Error:\n" .. result["error"])
+		return
+	end
+	local kernels = {}
+	for _, kernel in ipairs(result["data"]) do
+		if kernel.alive then
+			table.insert(kernels, kernel)
+		end
+	end
+	if #kernels == 0 then
+		window.output_result("Error:\nno running kernel found in the jupyter runtime dir")
+		return
+	end
+
+	local selector = function(opts)
+		opts = opts or {}
+		pickers.new(opts, {
+			prompt_title = "attach kernel",
 			finder = finders.new_table({
-				results = running_kernel_array,
+				results = kernels,
+				entry_maker = function(kernel)
+					local display = kernel.id .. " " .. kernel.kernel_name
+					if kernel.id == status.current_kernel_id then
+						display = display .. " *"
+					end
+					return { value = kernel, display = display, ordinal = display }
+				end,
 			}),
 			sorter = conf.generic_sorter(opts),
 			attach_mappings = function(prompt_bufnr, map)
 				actions.select_default:replace(function()
 					actions.close(prompt_bufnr)
-					local selection = action_state.get_selected_entry()
-					local selected_kernel = selection[1]
-					local selected_kernel_id = string.gsub(selected_kernel, "<.*", "") -- TODO(tacogips) too ugly
-					status.current_kernel_id = selected_kernel_id
+					local kernel = action_state.get_selected_entry().value
+					local attach_result = jupyter_client.attach_kernel(kernel.connection_file)
+					if attach_result["error"] ~= nil then
+						window.output_result("Error:\n" .. attach_result["error"])
+						return
+					end
+					status.current_kernel_id = attach_result["data"]
+					status.current_kernel_endpoint = "local"
+					status.current_session_id = nil
 				end)
 				return true
 			end,
@@ -213,14 +574,212 @@ function M.open_switch_kernel_selection()
 	selector()
 end
 
-local function run_code(code)
+function M.restart_current_kernel()
+	if not status.current_kernel_id then
+		window.output_result("Error:\nkernel not selected")
+		return
+	end
+
+	print("restarting the kernel...")
+	local result = jupyter_client.restart_kernel(kernel_endpoint(), status.current_kernel_id)
+	if result["error"] ~= nil then
+		window.output_result("Error:\n" .. result["error"])
+		return
+	end
+	local kernel_info = result["data"]["kernel_info"]
+	print("kernel restarted: " .. kernel_info["implementation"] .. " (" .. kernel_info["language_info"]["name"] .. ")")
+end
+
+-- "shutdown", "leave" or "keep" the current kernel when neovim exits, overriding kernel.on_exit
+function M.set_current_kernel_shutdown_policy(policy)
+	if not status.current_kernel_id then
+		window.output_result("Error:\nkernel not selected")
+		return
+	end
+	local result = jupyter_client.set_shutdown_policy(kernel_endpoint(), status.current_kernel_id, policy)
+	if result["error"] ~= nil then
+		window.output_result("Error:\n" .. result["error"])
+	elseif not result["data"] then
+		print("the kernel was not started by the plugin, so it is left running")
+	end
+end
+
+function M.has_current_kernel()
+	return status.current_kernel_id ~= nil
+end
+
+local function query_current_kernel(query)
+	if not status.current_kernel_id then
+		return { error = "kernel not selected" }
+	end
+	return query(kernel_endpoint(), status.current_kernel_id)
+end
+
+-- { data = { execution_state, last_activity, connections, ... } } of the selected kernel, for statuslines
+function M.current_kernel_status()
+	return query_current_kernel(jupyter_client.kernel_status)
+end
+
+-- { data = { language_info = { name, version, ... }, implementation, protocol_version, ... } }
+function M.current_kernel_info()
+	return query_current_kernel(jupyter_client.kernel_info)
+end
+
+-- { data = { matches, cursor_start, cursor_end, types } } at the 0-based byte offset cursor_pos of code
+function M.complete(code, cursor_pos)
+	return query_current_kernel(function(kernel_endpoint, kernel_id)
+		return jupyter_client.complete(kernel_endpoint, kernel_id, code, cursor_pos)
+	end)
+end
+
+-- { data = { found, text, highlights, markdown } } for the object at the 0-based byte offset cursor_pos of code
+function M.inspect(code, cursor_pos, detail_level)
+	return query_current_kernel(function(kernel_endpoint, kernel_id)
+		return jupyter_client.inspect(kernel_endpoint, kernel_id, code, cursor_pos, detail_level)
+	end)
+end
+
+-- shows the kernel's documentation of the object under the cursor, its source with detail_level 1
+function M.inspect_under_cursor(detail_level)
+	local _, col = unpack(api.nvim_win_get_cursor(0))
+	local result = M.inspect(api.nvim_get_current_line(), col, detail_level)
+	if result["error"] ~= nil then
+		window.output_result("Error:\n" .. result["error"])
+		return
+	end
+	local inspection = result["data"]
+	if not inspection["found"] then
+		print("no information found")
+	elseif inspection["markdown"] then
+		window.output_hover(inspection["markdown"], "markdown")
+	else
+		window.output_hover(inspection["text"] or "", "plaintext", inspection["highlights"])
+	end
+end
+
+local poll_timer = nil
+
+local function stop_polling()
+	if poll_timer then
+		poll_timer:stop()
+		poll_timer:close()
+		poll_timer = nil
+	end
+end
+
+-- the callbacks passed to run_code_async are only invoked from poll_events,
+-- so it is polled on the main loop until no execution is left running.
+local function start_polling()
+	if poll_timer then
+		return
+	end
+	local interval = config.get().execution.poll_interval_ms
+	poll_timer = uv.new_timer()
+	poll_timer:start(
+		interval,
+		interval,
+		schedule_wrap(function()
+			if not poll_timer then
+				return
+			end
+			-- the events after a failing callback are still delivered, and its error is raised afterwards
+			local ok, running = pcall(jupyter_client.poll_events)
+			if not ok then
+				vim.notify("an execution callback failed: " .. tostring(running), vim.log.levels.ERROR)
+			elseif running == 0 then
+				stop_polling()
+			end
+		end)
+	)
+end
+
+local kernel_event_messages = {
+	died = { "the kernel %s died", vim.log.levels.WARN },
+	restarted = { "the kernel %s was restarted, its variables are lost", vim.log.levels.WARN },
+	reconnected = { "reconnected to the kernel %s", vim.log.levels.INFO },
+	unreachable = { "the server of the kernel %s does not answer", vim.log.levels.WARN },
+	culled = { "the kernel %s was shut down after being idle", vim.log.levels.INFO },
+}
+
+local function on_kernel_event(kernel_event)
+	local kernel_id = kernel_event["kernel_id"]
+	local gone = kernel_event["event"] == "died" or kernel_event["event"] == "culled"
+	if gone and kernel_id == status.current_kernel_id then
+		status.current_kernel_id = nil
+		status.current_kernel_endpoint = nil
+		status.current_session_id = nil
+	end
+	local message = kernel_event_messages[kernel_event["event"]]
+	if message then
+		vim.notify(string.format(message[1], kernel_id), message[2])
+	end
+end
+
+local monitor_timer = nil
+
+-- the rust module checks the kernels in use in the background, and its findings are polled here
+-- so the user hears of a dead kernel before running the next code on it.
+-- the idle culler reports through the same events, so they are polled while it runs even without the monitor.
+function M.start_monitoring()
+	local monitor = config.get().monitor
+	local idle_timeout_ms = config.get().kernel.idle_timeout_ms
+	local polling = monitor.enabled or (idle_timeout_ms ~= nil and idle_timeout_ms > 0)
+	jupyter_client.monitor_kernels({ enabled = monitor.enabled, interval_ms = monitor.interval_ms })
+	if monitor_timer and not polling then
+		monitor_timer:stop()
+		monitor_timer:close()
+		monitor_timer = nil
+	end
+	if monitor_timer or not polling then
+		return
+	end
+	monitor_timer = uv.new_timer()
+	monitor_timer:start(
+		monitor.interval_ms,
+		monitor.interval_ms,
+		schedule_wrap(function()
+			for _, kernel_event in ipairs(jupyter_client.poll_kernel_events()["data"]) do
+				on_kernel_event(kernel_event)
+			end
+		end)
+	)
+end
+
+local function run_code_async(code, on_output, on_done, on_input, opts)
 	local result = {}
 	if not status.current_kernel_id then
 		result["error"] = "kernel not selected"
 		return result
 	end
 
-	return send_code_to_kernel(status.current_kernel_id, code)
+	result = jupyter_client.run_code_async(
+		kernel_endpoint(),
+		status.current_kernel_id,
+		code,
+		on_output,
+		on_done,
+		on_input,
+		opts
+	)
+	if result["data"] ~= nil then
+		start_polling()
+	end
+	return result
+end
+
+-- the lines of the statement starting at row, as decided by the kernel's is_complete_request.
+-- 1 when there is no kernel to ask
+local function statement_line_count(row)
+	if not status.current_kernel_id then
+		return 1
+	end
+	local last_row = math.min(api.nvim_buf_line_count(0), row + config.get().execution.max_statement_lines - 1)
+	local lines = api.nvim_buf_get_lines(0, row - 1, last_row, false)
+	local result = jupyter_client.statement_range(kernel_endpoint(), status.current_kernel_id, lines)
+	if result["error"] ~= nil then
+		return 1
+	end
+	return math.max(result["data"], 1)
 end
 
 -- thanks to  https://github.com/ibhagwan/nvim-lua/blob/main/lua/utils.lua
@@ -232,9 +791,9 @@ local function get_selection_lines()
 		_, column_end_row, _, _ = unpack(fn.getpos("v"))
 		api.nvim_feedkeys(api.nvim_replace_termcodes("<Esc>", true, false, true), "n", true)
 	else
-		-- currnet row
+		-- the statement starting at the current row
 		column_start_row, _ = unpack(api.nvim_win_get_cursor(0))
-		column_end_row = column_start_row
+		column_end_row = column_start_row + statement_line_count(column_start_row) - 1
 	end
 
 	if column_end_row < column_start_row then
@@ -249,31 +808,192 @@ local function get_selection_lines()
 	return table.concat(lines, "\n")
 end
 
-function M.run_selecting_code()
-	local selection_code = get_selection_lines()
+local function output_to_text(output)
+	local output_type = output["output_type"]
+	if output_type == "stream" then
+		return output["text"]
+	elseif output_type == "execute_result" or output_type == "display_data" then
+		local data = output["data"] or {}
+		local mime_type = output["mime_type"]
+		if mime_type == nil then
+			return nil
+		elseif mime_type == "application/json" then
+			return fn.json_encode(data[mime_type])
+		elseif string.find(mime_type, "^image/") then
+			return "<" .. mime_type .. ">"
+		end
+		return data[mime_type]
+	end
+	-- errors are shown from the kernel_error of the finished execution
+	return nil
+end
+
+local function kernel_error_to_text(kernel_error)
+	local text = kernel_error["ename"] .. ": " .. kernel_error["evalue"]
+	if #kernel_error["traceback"] > 0 then
+		text = text .. "\n" .. table.concat(kernel_error["traceback"], "\n")
+	end
+	return text
+end
 
+-- asks the user the prompt of input() or getpass() in the running code. cancelling the prompt interrupts the code
+local function read_input(execution_id, input_request)
+	local answer = function(value)
+		if value == nil then
+			jupyter_client.cancel_execution(execution_id)
+			return
+		end
+		jupyter_client.send_input(execution_id, value)
+	end
+	if input_request["password"] then
+		-- inputsecret returns "" when cancelled
+		local ok, value = pcall(fn.inputsecret, input_request["prompt"])
+		answer(ok and value or nil)
+		return
+	end
+	vim.ui.input({ prompt = input_request["prompt"] }, answer)
+end
+
+-- the code kept running through the interrupt of its timeout
+local function offer_restart()
+	vim.ui.select({ "restart", "leave running" }, {
+		prompt = "the kernel is still busy after the interrupt",
+	}, function(choice)
+		if choice == "restart" then
+			M.restart_current_kernel()
+		end
+	end)
+end
+
+-- runs code, showing its outputs in the result window at the cursor row
+local function run_code(code)
 	local row_pos, _ = unpack(api.nvim_win_get_cursor(0))
 	print("running the code...")
-	schedule(function()
-		local result = run_code(selection_code)
-		for k, v in pairs(result) do
-			if k == "error" then
-				window.output_result_with_position("Error:\n" .. v, row_pos)
-			elseif k == "text" then
-				window.output_result_with_position("Ok: " .. v, row_pos)
-			elseif k == "png" then
-				--window.output_result_with_position(v, row_pos)
+	local execution_id = nil
+	local output_texts = {}
+	local result = run_code_async(code, function(output)
+		local text = output_to_text(output)
+		if text then
+			table.insert(output_texts, text)
+			window.output_result_with_position(table.concat(output_texts, "\n"), row_pos)
+		end
+	end, function(done)
+		if done["timeout"] ~= nil then
+			-- keep what the code output before it timed out
+			table.insert(output_texts, "Error:\n" .. done["error"])
+			window.output_result_with_position(table.concat(output_texts, "\n"), row_pos)
+			if done["timeout"] == "busy" and config.get().execution.on_timeout == "ask" then
+				offer_restart()
 			end
+		elseif done["error"] ~= nil then
+			window.output_result_with_position("Error:\n" .. done["error"], row_pos)
+		elseif done["kernel_error"] ~= nil then
+			table.insert(output_texts, kernel_error_to_text(done["kernel_error"]))
+			window.output_result_with_position(table.concat(output_texts, "\n"), row_pos)
 		end
-	end)
+	end, function(input_request)
+		read_input(execution_id, input_request)
+	end, { stop_on_error = config.get().execution.stop_on_error })
+
+	if result["error"] ~= nil then
+		window.output_result_with_position("Error:\n" .. result["error"], row_pos)
+		return
+	end
+	execution_id = result["data"]
+	if jupyter_client.execution_state(execution_id)["data"] == "pending" then
+		print("queued behind the running code...")
+	end
+end
 
-	--for k, v in pairs(result) do
-	--	if k == "error" then
-	--		window.output_result("Error:\n" .. v)
-	--	elseif k == "text" then
-	--		window.output_result(v)
-	--	end
-	--end
+function M.run_selecting_code()
+	run_code(get_selection_lines())
+end
+
+-- picks from the inputs the current kernel ran in its current session, from any client such as JupyterLab.
+-- <CR> runs the input again, <C-y> puts it below the cursor line
+function M.open_history_selection()
+	if not status.current_kernel_id then
+		window.output_result("Error:\nkernel not selected")
+		return
+	end
+	local result = jupyter_client.history(
+		kernel_endpoint(),
+		status.current_kernel_id,
+		{ access = "range", session = 0, start = 1 }
+	)
+	if result["error"] ~= nil then
+		window.output_result("Error:\n" .. result["error"])
+		return
+	end
+	local entries = {}
+	-- latest first
+	for i = #result["data"], 1, -1 do
+		table.insert(entries, result["data"][i])
+	end
+	if #entries == 0 then
+		print("no history in the kernel")
+		return
+	end
+
+	local selector = function(opts)
+		opts = opts or {}
+		pickers.new(opts, {
+			prompt_title = "kernel history",
+			finder = finders.new_table({
+				results = entries,
+				entry_maker = function(entry)
+					local display = entry.line .. ": " .. string.gsub(entry.input, "\n", " ⏎ ")
+					return { value = entry, display = display, ordinal = entry.input }
+				end,
+			}),
+			sorter = conf.generic_sorter(opts),
+			attach_mappings = function(prompt_bufnr, map)
+				actions.select_default:replace(function()
+					actions.close(prompt_bufnr)
+					run_code(action_state.get_selected_entry().value.input)
+				end)
+				local put_input = function()
+					actions.close(prompt_bufnr)
+					local input = action_state.get_selected_entry().value.input
+					api.nvim_put(vim.split(input, "\n", { plain = true }), "l", true, true)
+				end
+				map("i", "<C-y>", put_input)
+				map("n", "<C-y>", put_input)
+				return true
+			end,
+		}):find()
+	end
+
+	selector()
+end
+
+-- the executions of the current kernel that have not ended, { id, state } with the running one first
+local function execution_queue()
+	if not status.current_kernel_id then
+		return {}
+	end
+	return jupyter_client.execution_queue(kernel_endpoint(), status.current_kernel_id)["data"]
+end
+
+-- interrupts the code running on the current kernel. the queued code then runs in turn
+function M.cancel_running_code()
+	local running = execution_queue()[1]
+	if not running or running.state ~= "running" then
+		return
+	end
+	local result = jupyter_client.cancel_execution(running.id)
+	if result["error"] ~= nil then
+		window.output_result("Error:\n" .. result["error"])
+	end
+end
+
+-- drops the code queued on the current kernel, leaving the running one alone
+function M.clear_execution_queue()
+	if not status.current_kernel_id then
+		return
+	end
+	local result = jupyter_client.clear_execution_queue(kernel_endpoint(), status.current_kernel_id)
+	print(result["data"] .. " queued executions cancelled")
 end
 
 return M
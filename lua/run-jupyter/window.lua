@@ -103,4 +103,58 @@ function M.output_result_with_position(contents, row_pos)
 	output_contents(bufnr, contents_table)
 end
 
+-- "#rrggbb" colors are used as they are. For palette indexes, the GUI uses the terminal's colors when the colorscheme sets them.
+local function ansi_color(color)
+	if type(color) == "string" then
+		return color, nil
+	end
+	return vim.g["terminal_color_" .. color], color
+end
+
+local function ansi_highlight_group(highlight)
+	local name = "RunJupyterAnsi"
+		.. "_"
+		.. tostring(highlight.fg or "")
+		.. "_"
+		.. tostring(highlight.bg or "")
+		.. (highlight.bold and "_b" or "")
+		.. (highlight.italic and "_i" or "")
+		.. (highlight.underline and "_u" or "")
+	name = name:gsub("#", "")
+	local fg, ctermfg, bg, ctermbg
+	if highlight.fg then
+		fg, ctermfg = ansi_color(highlight.fg)
+	end
+	if highlight.bg then
+		bg, ctermbg = ansi_color(highlight.bg)
+	end
+	api.nvim_set_hl(0, name, {
+		fg = fg,
+		bg = bg,
+		ctermfg = ctermfg,
+		ctermbg = ctermbg,
+		bold = highlight.bold,
+		italic = highlight.italic,
+		underline = highlight.underline,
+	})
+	return name
+end
+
+-- shows contents in a floating window at the cursor, closed when the cursor moves like an LSP hover.
+-- highlights are { line, start, end, fg, bg, bold, italic, underline } in 0-based lines and byte columns of contents.
+function M.output_hover(contents, filetype, highlights)
+	local lines = vim.split(contents, "\n", { plain = true })
+	local bufnr, _ = vim.lsp.util.open_floating_preview(lines, filetype, { focus_id = "run_jupyter_hover" })
+	for _, highlight in ipairs(highlights or {}) do
+		api.nvim_buf_add_highlight(
+			bufnr,
+			-1,
+			ansi_highlight_group(highlight),
+			highlight.line,
+			highlight.start,
+			highlight["end"]
+		)
+	end
+end
+
 return M
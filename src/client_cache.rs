@@ -0,0 +1,151 @@
+use super::error::JupyterRunnerError;
+use super::kernel::{self, KernelConnection, KernelInfo};
+use super::kernel_monitor;
+use super::local;
+use super::server::{self, Credentials, ServerClient};
+use once_cell::sync::{Lazy, OnceCell};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+type Result<T> = std::result::Result<T, JupyterRunnerError>;
+
+pub struct CachedKernelClient {
+    pub kernel_name: String,
+    pub connection: KernelConnection,
+    kernel_info: OnceCell<KernelInfo>,
+}
+
+impl CachedKernelClient {
+    /// Asks the kernel on the first call only. The info can not change without a restart, which drops the cached client.
+    pub async fn kernel_info(&self) -> Result<KernelInfo> {
+        if let Some(kernel_info) = self.kernel_info.get() {
+            return Ok(kernel_info.clone());
+        }
+        let kernel_info = kernel::kernel_info(&self.connection).await?;
+        Ok(self.kernel_info.get_or_init(|| kernel_info).clone())
+    }
+}
+
+static CONFIGURED_CREDENTIALS: Lazy<Mutex<HashMap<String, Credentials>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+static SERVER_CLIENTS: Lazy<Mutex<HashMap<String, Arc<ServerClient>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+static KERNEL_CLIENTS: Lazy<Mutex<HashMap<String, Arc<CachedKernelClient>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Sets the token and password of the server. Clients created with the previous ones are dropped.
+pub fn set_credentials(jupyter_base_url: &str, credentials: Credentials) {
+    CONFIGURED_CREDENTIALS
+        .lock()
+        .unwrap()
+        .insert(jupyter_base_url.to_string(), credentials);
+    SERVER_CLIENTS.lock().unwrap().remove(jupyter_base_url);
+}
+
+fn credentials(jupyter_base_url: &str) -> Credentials {
+    let configured = CONFIGURED_CREDENTIALS
+        .lock()
+        .unwrap()
+        .get(jupyter_base_url)
+        .cloned();
+    Credentials::resolve(jupyter_base_url, configured)
+}
+
+// a spawned server refuses connections until it listens, which would be reported as an unreachable server.
+fn ensure_not_starting(jupyter_base_url: &str) -> Result<()> {
+    if server::is_starting(jupyter_base_url) {
+        return Err(JupyterRunnerError::ServerStarting(
+            jupyter_base_url.to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Returns the client for `jupyter_base_url`, creating it on the first call.
+/// Every request to the server goes through it, for its login and XSRF handling.
+pub fn server_client(jupyter_base_url: &str) -> Result<Arc<ServerClient>> {
+    ensure_not_starting(jupyter_base_url)?;
+    let mut clients = SERVER_CLIENTS.lock().unwrap();
+    if let Some(client) = clients.get(jupyter_base_url) {
+        return Ok(client.clone());
+    }
+
+    let client = Arc::new(ServerClient::new(
+        jupyter_base_url,
+        credentials(jupyter_base_url),
+    )?);
+    clients.insert(jupyter_base_url.to_string(), client.clone());
+    Ok(client)
+}
+
+/// Returns the kernel client for `kernel_id`, asking the server for the kernel only on a cache miss
+/// or when the cached connection has been closed. Kernels of the local endpoint are connected over zmq.
+pub async fn kernel_client(
+    jupyter_base_url: &str,
+    kernel_id: &str,
+) -> Result<Arc<CachedKernelClient>> {
+    if let Some(cached) = KERNEL_CLIENTS.lock().unwrap().get(kernel_id) {
+        if !cached.connection.is_closed() {
+            return Ok(cached.clone());
+        }
+    }
+
+    let (kernel_name, connection) = if local::is_local_endpoint(jupyter_base_url) {
+        let kernel = match local::get_kernel(kernel_id) {
+            Some(kernel) => kernel,
+            None => {
+                invalidate_kernel(kernel_id);
+                return Err(JupyterRunnerError::KernelNotFound(kernel_id.to_string()));
+            }
+        };
+        (
+            kernel.kernel_name.clone(),
+            KernelConnection::connect_zmq(&kernel.connection_info)?,
+        )
+    } else {
+        let server_client = server_client(jupyter_base_url)?;
+        let kernel = match server_client.get_kernel(kernel_id).await? {
+            Some(kernel) => kernel,
+            None => {
+                invalidate_kernel(kernel_id);
+                return Err(JupyterRunnerError::KernelNotFound(kernel_id.to_string()));
+            }
+        };
+
+        let connection =
+            KernelConnection::connect(jupyter_base_url, &kernel.id, &server_client.auth_headers())
+                .await?;
+        (kernel.name, connection)
+    };
+
+    let cached = Arc::new(CachedKernelClient {
+        kernel_name,
+        connection,
+        kernel_info: OnceCell::new(),
+    });
+    KERNEL_CLIENTS
+        .lock()
+        .unwrap()
+        .insert(kernel_id.to_string(), cached.clone());
+    kernel_monitor::watch(jupyter_base_url, kernel_id);
+    Ok(cached)
+}
+
+/// The cached kernel client, whether its connection is still open or not.
+pub fn cached_kernel_client(kernel_id: &str) -> Option<Arc<CachedKernelClient>> {
+    KERNEL_CLIENTS.lock().unwrap().get(kernel_id).cloned()
+}
+
+pub fn invalidate_kernel(kernel_id: &str) {
+    KERNEL_CLIENTS.lock().unwrap().remove(kernel_id);
+}
+
+/// Drops every cached kernel client whose id is not in `running_kernel_ids`.
+pub fn retain_kernels(running_kernel_ids: &HashSet<String>) {
+    KERNEL_CLIENTS
+        .lock()
+        .unwrap()
+        .retain(|kernel_id, _| running_kernel_ids.contains(kernel_id));
+}
@@ -0,0 +1,72 @@
+use super::error::JupyterRunnerError;
+use std::path::Path;
+
+type Result<T> = std::result::Result<T, JupyterRunnerError>;
+
+/// The `KEY=value` lines of a `.env` file, in order. Blank lines, `#` comments and lines without `=` are skipped,
+/// an `export ` prefix is dropped and a value in matching quotes is taken as it is inside them.
+pub fn parse_dotenv(content: &str) -> Vec<(String, String)> {
+    content
+        .lines()
+        .filter_map(|line| {
+            let line = line.trim();
+            if line.starts_with('#') {
+                return None;
+            }
+            let line = line.strip_prefix("export ").unwrap_or(line);
+            let (key, value) = line.split_once('=')?;
+            let key = key.trim();
+            if key.is_empty() {
+                return None;
+            }
+            Some((key.to_string(), unquote(value.trim()).to_string()))
+        })
+        .collect()
+}
+
+fn unquote(value: &str) -> &str {
+    for quote in ['"', '\''] {
+        if let Some(inner) = value
+            .strip_prefix(quote)
+            .and_then(|rest| rest.strip_suffix(quote))
+        {
+            return inner;
+        }
+    }
+    // an unquoted value ends at a comment.
+    match value.find(" #") {
+        Some(comment_start) => value[..comment_start].trim_end(),
+        None => value,
+    }
+}
+
+/// Reads the `.env` file at `path`. A missing file has no variables.
+pub fn read_dotenv(path: &Path) -> Result<Vec<(String, String)>> {
+    match std::fs::read_to_string(path) {
+        Ok(content) => Ok(parse_dotenv(&content)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(vec![]),
+        Err(e) => Err(JupyterRunnerError::InvalidOption(format!(
+            "{} :{e}",
+            path.display()
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_dotenv() {
+        let content = "# secrets\nexport API_TOKEN=\"a b#c\"\nDB_URL = postgres://localhost/db # local\n\nEMPTY=\nQUOTED='x'\nnot a variable\n";
+        assert_eq!(
+            vec![
+                ("API_TOKEN".to_string(), "a b#c".to_string()),
+                ("DB_URL".to_string(), "postgres://localhost/db".to_string()),
+                ("EMPTY".to_string(), "".to_string()),
+                ("QUOTED".to_string(), "x".to_string()),
+            ],
+            parse_dotenv(content)
+        );
+    }
+}
@@ -0,0 +1,98 @@
+use super::execution::{ExecutionId, TimeoutOutcome};
+use super::kernel::ExecutionOutput;
+use super::parser::ParserError;
+use jupyter_client::JupyterApiError;
+use std::time::Duration;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum JupyterRunnerError {
+    #[error("kernel not found {0}")]
+    KernelNotFound(String),
+
+    #[error("api error :{0}")]
+    JupyterApiError(#[from] JupyterApiError),
+
+    #[error("parse error :{0}")]
+    ParserError(#[from] ParserError),
+
+    #[error("failed to start async runtime :{0}")]
+    RuntimeError(std::io::Error),
+
+    #[error("io error :{0}")]
+    IoError(#[from] std::io::Error),
+
+    #[error("websocket error :{0}")]
+    WebSocketError(Box<tokio_tungstenite::tungstenite::Error>),
+
+    #[error("invalid kernel message :{0}")]
+    MessageError(#[from] serde_json::Error),
+
+    #[error("invalid url :{0}")]
+    UrlError(#[from] url::ParseError),
+
+    #[error("kernel connection closed before the reply to {0}")]
+    ConnectionClosed(String),
+
+    #[error("http error :{0}")]
+    HttpError(#[from] reqwest::Error),
+
+    #[error("authentication failed :{0}")]
+    AuthenticationFailed(String),
+
+    #[error("session not found {0}")]
+    SessionNotFound(String),
+
+    #[error("kernel did not become ready {0}")]
+    KernelNotReady(String),
+
+    #[error("failed to start jupyter server :{0}")]
+    ServerStartFailed(String),
+
+    #[error("jupyter server {0} is still starting")]
+    ServerStarting(String),
+
+    #[error("server not started by run-jupyter {0}")]
+    ServerNotSpawned(String),
+
+    #[error("zmq error :{0}")]
+    ZmqError(#[from] zmq::Error),
+
+    #[error("invalid kernel message signature :{0}")]
+    SignatureError(String),
+
+    #[error("kernel spec not found {0}")]
+    KernelSpecNotFound(String),
+
+    #[error("failed to start kernel :{0}")]
+    KernelStartFailed(String),
+
+    #[error("kernel not started by run-jupyter {0}")]
+    KernelNotOwned(String),
+
+    #[error("request failed {0}")]
+    RequestFailed(String),
+
+    #[error("no reply in time to {0}")]
+    RequestTimeout(String),
+
+    #[error("execution cancelled {0}")]
+    ExecutionCancelled(ExecutionId),
+
+    #[error("invalid option :{0}")]
+    InvalidOption(String),
+
+    #[error("execution timed out after {}s, kernel {}", timeout.as_secs_f64(), outcome.as_str())]
+    Timeout {
+        timeout: Duration,
+        outcome: TimeoutOutcome,
+        /// What the execution had output until then.
+        outputs: Vec<ExecutionOutput>,
+    },
+}
+
+impl From<tokio_tungstenite::tungstenite::Error> for JupyterRunnerError {
+    fn from(e: tokio_tungstenite::tungstenite::Error) -> Self {
+        Self::WebSocketError(Box::new(e))
+    }
+}
@@ -0,0 +1,794 @@
+use super::client_cache;
+use super::error::JupyterRunnerError;
+use super::kernel::{self, ExecuteReply, ExecutionOutput, InputHandler, InputRequest, KernelError};
+use super::kernel_manager;
+use super::parser::*;
+use super::runtime::runtime;
+use super::started_kernels;
+use once_cell::sync::Lazy;
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{oneshot, watch};
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, timeout, Instant};
+
+type Result<T> = std::result::Result<T, JupyterRunnerError>;
+
+pub type ExecutionId = u64;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExecutionState {
+    /// Queued behind another execution on the same kernel.
+    Pending,
+    Running,
+    Done,
+    Failed,
+    Cancelled,
+    TimedOut,
+}
+
+impl ExecutionState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::Running => "running",
+            Self::Done => "done",
+            Self::Failed => "failed",
+            Self::Cancelled => "cancelled",
+            Self::TimedOut => "timed_out",
+        }
+    }
+}
+
+/// What became of the kernel after an execution ran out of time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TimeoutOutcome {
+    /// The code stopped on the interrupt.
+    Interrupted,
+    /// The code kept running through the interrupt, and the kernel was left as it is.
+    Busy,
+    Restarted,
+}
+
+impl TimeoutOutcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Interrupted => "interrupted",
+            Self::Busy => "busy",
+            Self::Restarted => "restarted",
+        }
+    }
+}
+
+/// How long executions may run. Once `timeout` is over the kernel is interrupted, and if it is still busy
+/// after `interrupt_grace`, restarted when `restart` is set.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TimeoutOptions {
+    /// None lets executions run forever.
+    pub timeout: Option<Duration>,
+    pub interrupt_grace: Duration,
+    pub restart: bool,
+}
+
+impl Default for TimeoutOptions {
+    fn default() -> Self {
+        Self {
+            timeout: None,
+            interrupt_grace: Duration::from_secs(5),
+            restart: false,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ExecutionEvent {
+    Output {
+        execution_id: ExecutionId,
+        output: ExecutionOutput,
+    },
+    /// The code waits for a line of input, to be given with [`send_input`].
+    InputRequest {
+        execution_id: ExecutionId,
+        input_request: InputRequest,
+    },
+    Done {
+        execution_id: ExecutionId,
+        state: ExecutionState,
+        /// Why the kernel could not be reached or stopped answering.
+        error: Option<String>,
+        /// The exception the code raised, if any. The execution itself still counts as done.
+        kernel_error: Option<KernelError>,
+        /// Set when the execution timed out.
+        timeout_outcome: Option<TimeoutOutcome>,
+    },
+}
+
+/// The options of one submission.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ExecutionOptions {
+    pub timeout_options: TimeoutOptions,
+    /// Cancel the executions queued after this one if it fails or raises.
+    pub stop_on_error: bool,
+}
+
+/// How an execution run by [`run_queued`] ended, with every output it produced.
+type QueuedRun = (Result<Option<ExecuteReply>>, Vec<ExecutionOutput>);
+
+struct Execution {
+    jupyter_base_url: String,
+    kernel_id: String,
+    state: ExecutionState,
+    /// Taken when the execution starts.
+    code: Option<String>,
+    options: ExecutionOptions,
+    task: Option<JoinHandle<()>>,
+    /// Set for executions run by [`run_queued`]. Their outputs and end go to it instead of the event queue.
+    /// Dropped when the execution is cancelled.
+    waiter: Option<oneshot::Sender<QueuedRun>>,
+}
+
+/// The jupyter_base_url and id of a kernel.
+type KernelKey = (String, String);
+
+static NEXT_EXECUTION_ID: AtomicU64 = AtomicU64::new(1);
+
+static EXECUTIONS: Lazy<Mutex<HashMap<ExecutionId, Execution>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// The executions of each kernel that have not ended, in submission order. Only the first one runs.
+/// Locked after EXECUTIONS when both are needed.
+static QUEUES: Lazy<Mutex<HashMap<KernelKey, VecDeque<ExecutionId>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+static PENDING_INPUTS: Lazy<Mutex<HashMap<ExecutionId, oneshot::Sender<String>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+static TIMEOUT_OPTIONS: Lazy<Mutex<TimeoutOptions>> =
+    Lazy::new(|| Mutex::new(TimeoutOptions::default()));
+
+static EVENTS: Lazy<Mutex<VecDeque<ExecutionEvent>>> = Lazy::new(|| Mutex::new(VecDeque::new()));
+
+/// Picks the parser by the language the kernel reports, falling back to guessing from the kernel name.
+fn parse_code(kernel_name: &str, language: Option<&str>, code: String) -> Result<Option<String>> {
+    let parsable_kernel = match language
+        .and_then(|language| ParsableKernel::try_from_language(language).ok())
+        .or_else(|| ParsableKernel::try_from_str(kernel_name).ok())
+    {
+        Some(parsable_kernel) => parsable_kernel,
+        None => return Ok(Some(code)),
+    };
+
+    let parsed_code = match parsable_kernel {
+        ParsableKernel::Rust => RustParser.parse(&code)?,
+        ParsableKernel::Python3 => {
+            return Err(ParserError::UnsuppotedKernel("python".to_string()).into())
+        }
+    };
+    Ok(parsed_code.map(|cell_sources| cell_sources.as_one_line_code()))
+}
+
+/// The options of executions that do not set their own.
+pub fn set_timeout_options(timeout_options: TimeoutOptions) {
+    *TIMEOUT_OPTIONS.lock().unwrap() = timeout_options;
+}
+
+pub fn timeout_options() -> TimeoutOptions {
+    *TIMEOUT_OPTIONS.lock().unwrap()
+}
+
+/// Wraps `on_input` so that `input_pending` is true while one of its requests is unanswered.
+fn tracking_input<'a>(
+    on_input: InputHandler<'a>,
+    input_pending: Arc<watch::Sender<bool>>,
+) -> impl Fn(InputRequest) -> oneshot::Receiver<String> + Sync + 'a {
+    move |input_request| {
+        let answer = on_input(input_request);
+        let (sender, receiver) = oneshot::channel();
+        input_pending.send_replace(true);
+        let input_pending = input_pending.clone();
+        tokio::spawn(async move {
+            let answer = answer.await;
+            input_pending.send_replace(false);
+            // an unanswered request leaves the kernel an empty line, as the sender is dropped.
+            if let Ok(answer) = answer {
+                let _ = sender.send(answer);
+            }
+        });
+        receiver
+    }
+}
+
+/// Awaits `running` for at most `limit`, not counting the time an input request is pending.
+/// Returns None once the limit is over.
+async fn with_timeout<F>(
+    limit: Duration,
+    running: &mut F,
+    mut input_pending: watch::Receiver<bool>,
+) -> Option<F::Output>
+where
+    F: Future + Unpin,
+{
+    let mut remaining = limit;
+    loop {
+        if *input_pending.borrow_and_update() {
+            tokio::select! {
+                result = &mut *running => return Some(result),
+                _ = input_pending.changed() => continue,
+            }
+        }
+        let started = Instant::now();
+        tokio::select! {
+            result = &mut *running => return Some(result),
+            _ = sleep(remaining) => return None,
+            _ = input_pending.changed() => {
+                remaining = remaining.saturating_sub(started.elapsed());
+            }
+        }
+    }
+}
+
+/// Runs `code` on the kernel, handing each output to `on_output` as it arrives.
+/// Returns None if there was nothing to run after parsing. An execution running past its timeout
+/// ends with [`JupyterRunnerError::Timeout`], carrying the outputs it had produced.
+/// The time the code waits for input does not count towards the timeout.
+pub async fn execute<F>(
+    jupyter_base_url: &str,
+    kernel_id: &str,
+    code: String,
+    mut on_output: F,
+    on_input: Option<InputHandler<'_>>,
+    timeout_options: TimeoutOptions,
+) -> Result<Option<ExecuteReply>>
+where
+    F: FnMut(ExecutionOutput),
+{
+    started_kernels::touch(jupyter_base_url, kernel_id);
+    let kernel_client = client_cache::kernel_client(jupyter_base_url, kernel_id).await?;
+    let language = kernel_client
+        .kernel_info()
+        .await
+        .ok()
+        .map(|kernel_info| kernel_info.language_info.name);
+    let code = match parse_code(&kernel_client.kernel_name, language.as_deref(), code)? {
+        Some(code) => code,
+        None => return Ok(None),
+    };
+
+    // the sender lives as long as the execution, so `changed` only returns on a change.
+    let (input_pending_sender, input_pending) = watch::channel(false);
+    let input_pending_sender = Arc::new(input_pending_sender);
+    let on_input = on_input.map(|on_input| tracking_input(on_input, input_pending_sender.clone()));
+    let on_input = on_input
+        .as_ref()
+        .map(|on_input| on_input as InputHandler<'_>);
+    let mut outputs = vec![];
+    let mut running = Box::pin(kernel::execute(
+        &kernel_client.connection,
+        &code,
+        |output| {
+            if timeout_options.timeout.is_some() {
+                outputs.push(output.clone());
+            }
+            on_output(output)
+        },
+        on_input,
+    ));
+    let result = match timeout_options.timeout {
+        Some(limit) => match with_timeout(limit, &mut running, input_pending).await {
+            Some(result) => result,
+            None => {
+                let _ = kernel_manager::interrupt_kernel(jupyter_base_url, kernel_id).await;
+                // the outputs up to the interrupt, e.g. the KeyboardInterrupt traceback, are still collected.
+                let interrupted = timeout(timeout_options.interrupt_grace, &mut running)
+                    .await
+                    .is_ok();
+                drop(running);
+                let outcome = if interrupted {
+                    TimeoutOutcome::Interrupted
+                } else if timeout_options.restart
+                    && kernel_manager::restart_kernel(jupyter_base_url, kernel_id)
+                        .await
+                        .is_ok()
+                {
+                    TimeoutOutcome::Restarted
+                } else {
+                    TimeoutOutcome::Busy
+                };
+                return Err(JupyterRunnerError::Timeout {
+                    timeout: limit,
+                    outcome,
+                    outputs,
+                });
+            }
+        },
+        None => running.await,
+    };
+    // the idle time counts from the end of the execution.
+    started_kernels::touch(jupyter_base_url, kernel_id);
+
+    match result {
+        Ok(reply) => Ok(Some(reply)),
+        Err(e) => {
+            // the connection may be stale, so the next call reconnects from scratch.
+            client_cache::invalidate_kernel(kernel_id);
+            Err(e)
+        }
+    }
+}
+
+fn push_event(event: ExecutionEvent) {
+    EVENTS.lock().unwrap().push_back(event)
+}
+
+fn dequeue(key: &KernelKey, execution_id: ExecutionId) {
+    let mut queues = QUEUES.lock().unwrap();
+    if let Some(queue) = queues.get_mut(key) {
+        queue.retain(|queued_id| *queued_id != execution_id);
+        if queue.is_empty() {
+            queues.remove(key);
+        }
+    }
+}
+
+/// `outputs` are those of an execution with a waiter, the others having been reported as they came.
+fn finish(
+    execution_id: ExecutionId,
+    result: Result<Option<ExecuteReply>>,
+    outputs: Vec<ExecutionOutput>,
+) {
+    PENDING_INPUTS.lock().unwrap().remove(&execution_id);
+    let state = match &result {
+        Ok(_) => ExecutionState::Done,
+        Err(JupyterRunnerError::Timeout { .. }) => ExecutionState::TimedOut,
+        Err(_) => ExecutionState::Failed,
+    };
+    let raised = matches!(&result, Ok(Some(reply)) if reply.error.is_some());
+
+    let (key, stop_queue, waiter) = {
+        let mut executions = EXECUTIONS.lock().unwrap();
+        let execution = match executions.get_mut(&execution_id) {
+            // a cancelled execution has already reported its end.
+            Some(execution) if execution.state == ExecutionState::Running => execution,
+            _ => return,
+        };
+        execution.state = state;
+        execution.task = None;
+        let key = (
+            execution.jupyter_base_url.clone(),
+            execution.kernel_id.clone(),
+        );
+        dequeue(&key, execution_id);
+        let failed = state != ExecutionState::Done || raised;
+        let stop_queue = failed && execution.options.stop_on_error;
+        let waiter = execution.waiter.take();
+        // the waiter is the only one to ask for it.
+        if waiter.is_some() {
+            executions.remove(&execution_id);
+        }
+        (key, stop_queue, waiter)
+    };
+
+    match waiter {
+        Some(waiter) => {
+            let _ = waiter.send((result, outputs));
+        }
+        None => {
+            let (error, kernel_error, timeout_outcome) = match result {
+                Ok(reply) => (None, reply.and_then(|reply| reply.error), None),
+                Err(e @ JupyterRunnerError::Timeout { outcome, .. }) => {
+                    (Some(e.to_string()), None, Some(outcome))
+                }
+                Err(e) => (Some(e.to_string()), None, None),
+            };
+            push_event(ExecutionEvent::Done {
+                execution_id,
+                state,
+                error,
+                kernel_error,
+                timeout_outcome,
+            });
+        }
+    }
+
+    if stop_queue {
+        clear_queue(&key.0, &key.1);
+    }
+    let _ = start_next(&key);
+}
+
+/// Starts the first execution queued on the kernel, unless it is already running.
+fn start_next(key: &KernelKey) -> Result<()> {
+    let mut executions = EXECUTIONS.lock().unwrap();
+    let execution_id = match QUEUES
+        .lock()
+        .unwrap()
+        .get(key)
+        .and_then(|queue| queue.front())
+    {
+        Some(execution_id) => *execution_id,
+        None => return Ok(()),
+    };
+    let execution = match executions.get_mut(&execution_id) {
+        Some(execution) if execution.state == ExecutionState::Pending => execution,
+        _ => return Ok(()),
+    };
+
+    let (jupyter_base_url, kernel_id) = key.clone();
+    let code = execution.code.take().unwrap_or_default();
+    let timeout_options = execution.options.timeout_options;
+    let waited = execution.waiter.is_some();
+    // `finish` waits for the lock, so it always sees the task and the running state.
+    execution.task = Some(runtime()?.spawn(async move {
+        let on_input = move |input_request: InputRequest| {
+            let (sender, receiver) = oneshot::channel();
+            PENDING_INPUTS.lock().unwrap().insert(execution_id, sender);
+            push_event(ExecutionEvent::InputRequest {
+                execution_id,
+                input_request,
+            });
+            receiver
+        };
+        let mut outputs = vec![];
+        let result = execute(
+            &jupyter_base_url,
+            &kernel_id,
+            code,
+            |output| {
+                if waited {
+                    outputs.push(output);
+                } else {
+                    push_event(ExecutionEvent::Output {
+                        execution_id,
+                        output,
+                    })
+                }
+            },
+            // the waiter blocks, so the prompt could not be answered.
+            if waited { None } else { Some(&on_input) },
+            timeout_options,
+        )
+        .await;
+        finish(execution_id, result, outputs);
+    }));
+    execution.state = ExecutionState::Running;
+    Ok(())
+}
+
+/// Queues `code` on the kernel and returns immediately. Executions of a kernel run one at a time,
+/// in the order they were submitted.
+/// Progress is reported through the event queue drained by [`drain_events`].
+pub fn spawn_execution(
+    jupyter_base_url: String,
+    kernel_id: String,
+    code: String,
+    options: ExecutionOptions,
+) -> Result<ExecutionId> {
+    queue_execution(jupyter_base_url, kernel_id, code, options, None)
+}
+
+/// Queues `code` on the kernel like [`spawn_execution`], and returns its outputs once it has run.
+/// The code can not read input. A cancelled execution ends with [`JupyterRunnerError::ExecutionCancelled`].
+pub async fn run_queued(
+    jupyter_base_url: String,
+    kernel_id: String,
+    code: String,
+    options: ExecutionOptions,
+) -> Result<(Option<ExecuteReply>, Vec<ExecutionOutput>)> {
+    let (sender, receiver) = oneshot::channel();
+    let execution_id = queue_execution(jupyter_base_url, kernel_id, code, options, Some(sender))?;
+    let (result, outputs) = receiver
+        .await
+        .map_err(|_| JupyterRunnerError::ExecutionCancelled(execution_id))?;
+    Ok((result?, outputs))
+}
+
+fn queue_execution(
+    jupyter_base_url: String,
+    kernel_id: String,
+    code: String,
+    options: ExecutionOptions,
+    waiter: Option<oneshot::Sender<QueuedRun>>,
+) -> Result<ExecutionId> {
+    let execution_id = NEXT_EXECUTION_ID.fetch_add(1, Ordering::SeqCst);
+    let key = (jupyter_base_url.clone(), kernel_id.clone());
+
+    {
+        let mut executions = EXECUTIONS.lock().unwrap();
+        executions.insert(
+            execution_id,
+            Execution {
+                jupyter_base_url,
+                kernel_id,
+                state: ExecutionState::Pending,
+                code: Some(code),
+                options,
+                task: None,
+                waiter,
+            },
+        );
+        QUEUES
+            .lock()
+            .unwrap()
+            .entry(key.clone())
+            .or_default()
+            .push_back(execution_id);
+    }
+
+    if let Err(e) = start_next(&key) {
+        EXECUTIONS.lock().unwrap().remove(&execution_id);
+        dequeue(&key, execution_id);
+        return Err(e);
+    }
+    Ok(execution_id)
+}
+
+/// Answers the input request the execution is waiting on. Returns false if it is not waiting.
+pub fn send_input(execution_id: ExecutionId, value: String) -> bool {
+    match PENDING_INPUTS.lock().unwrap().remove(&execution_id) {
+        Some(sender) => sender.send(value).is_ok(),
+        None => false,
+    }
+}
+
+/// None once the execution's end has been drained.
+pub fn execution_state(execution_id: ExecutionId) -> Option<ExecutionState> {
+    EXECUTIONS
+        .lock()
+        .unwrap()
+        .get(&execution_id)
+        .map(|execution| execution.state)
+}
+
+/// The executions of the kernel that have not ended, the running one first.
+pub fn queued_executions(
+    jupyter_base_url: &str,
+    kernel_id: &str,
+) -> Vec<(ExecutionId, ExecutionState)> {
+    let executions = EXECUTIONS.lock().unwrap();
+    let key = (jupyter_base_url.to_string(), kernel_id.to_string());
+    match QUEUES.lock().unwrap().get(&key) {
+        Some(queue) => queue
+            .iter()
+            .filter_map(|execution_id| {
+                executions
+                    .get(execution_id)
+                    .map(|execution| (*execution_id, execution.state))
+            })
+            .collect(),
+        None => vec![],
+    }
+}
+
+/// Cancels the executions waiting on the kernel, leaving the running one alone.
+/// Returns how many were cancelled.
+pub fn clear_queue(jupyter_base_url: &str, kernel_id: &str) -> usize {
+    let cancelled: Vec<ExecutionId> = {
+        let mut executions = EXECUTIONS.lock().unwrap();
+        let key = (jupyter_base_url.to_string(), kernel_id.to_string());
+        let mut queues = QUEUES.lock().unwrap();
+        let queue = match queues.get_mut(&key) {
+            Some(queue) => queue,
+            None => return 0,
+        };
+        let mut cancelled = vec![];
+        queue.retain(|execution_id| match executions.get_mut(execution_id) {
+            Some(execution) if execution.state == ExecutionState::Pending => {
+                execution.state = ExecutionState::Cancelled;
+                execution.code = None;
+                execution.waiter = None;
+                cancelled.push(*execution_id);
+                false
+            }
+            _ => true,
+        });
+        if queue.is_empty() {
+            queues.remove(&key);
+        }
+        cancelled
+    };
+
+    for execution_id in &cancelled {
+        push_event(ExecutionEvent::Done {
+            execution_id: *execution_id,
+            state: ExecutionState::Cancelled,
+            error: None,
+            kernel_error: None,
+            timeout_outcome: None,
+        });
+    }
+    cancelled.len()
+}
+
+/// Cancels the execution. A pending one is only taken off the queue. For a running one,
+/// the kernel is interrupted and the next execution starts once the interrupt has been sent.
+/// Returns false if the execution is unknown or has already ended.
+pub fn cancel_execution(execution_id: ExecutionId) -> Result<bool> {
+    let (key, was_running) = {
+        let mut executions = EXECUTIONS.lock().unwrap();
+        let execution = match executions.get_mut(&execution_id) {
+            Some(execution)
+                if matches!(
+                    execution.state,
+                    ExecutionState::Pending | ExecutionState::Running
+                ) =>
+            {
+                execution
+            }
+            _ => return Ok(false),
+        };
+        let was_running = execution.state == ExecutionState::Running;
+        if let Some(task) = execution.task.take() {
+            task.abort();
+        }
+        execution.state = ExecutionState::Cancelled;
+        execution.code = None;
+        execution.waiter = None;
+        PENDING_INPUTS.lock().unwrap().remove(&execution_id);
+        let key = (
+            execution.jupyter_base_url.clone(),
+            execution.kernel_id.clone(),
+        );
+        dequeue(&key, execution_id);
+        (key, was_running)
+    };
+
+    push_event(ExecutionEvent::Done {
+        execution_id,
+        state: ExecutionState::Cancelled,
+        error: None,
+        kernel_error: None,
+        timeout_outcome: None,
+    });
+
+    if was_running {
+        spawn_detached(async move {
+            let interrupted = kernel_manager::interrupt_kernel(&key.0, &key.1).await;
+            start_next(&key)?;
+            interrupted
+        })?;
+    }
+    Ok(true)
+}
+
+fn spawn_detached<F>(future: F) -> Result<()>
+where
+    F: Future<Output = Result<()>> + Send + 'static,
+{
+    runtime()?.spawn(async move {
+        let _ = future.await;
+    });
+    Ok(())
+}
+
+/// Forgets the executions whose end is among `events`, as nothing asks for them once it has been reported.
+fn forget_ended(events: &[ExecutionEvent]) {
+    let mut executions = EXECUTIONS.lock().unwrap();
+    for event in events {
+        if let ExecutionEvent::Done { execution_id, .. } = event {
+            executions.remove(execution_id);
+        }
+    }
+}
+
+/// Takes the queued events. The executions that ended are forgotten with their `Done` event.
+pub fn drain_events() -> Vec<ExecutionEvent> {
+    let events: Vec<ExecutionEvent> = EVENTS.lock().unwrap().drain(..).collect();
+    forget_ended(&events);
+    events
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn enqueue(key: &KernelKey, state: ExecutionState) -> ExecutionId {
+        let execution_id = NEXT_EXECUTION_ID.fetch_add(1, Ordering::SeqCst);
+        EXECUTIONS.lock().unwrap().insert(
+            execution_id,
+            Execution {
+                jupyter_base_url: key.0.clone(),
+                kernel_id: key.1.clone(),
+                state,
+                code: Some("1".to_string()),
+                options: ExecutionOptions::default(),
+                task: None,
+                waiter: None,
+            },
+        );
+        QUEUES
+            .lock()
+            .unwrap()
+            .entry(key.clone())
+            .or_default()
+            .push_back(execution_id);
+        execution_id
+    }
+
+    #[test]
+    fn test_clear_queue_keeps_running_execution() {
+        let key = ("http://queue-test".to_string(), "kernel".to_string());
+        let running = enqueue(&key, ExecutionState::Running);
+        let pending_1 = enqueue(&key, ExecutionState::Pending);
+        let pending_2 = enqueue(&key, ExecutionState::Pending);
+
+        assert!(cancel_execution(pending_1).unwrap());
+        assert_eq!(
+            vec![
+                (running, ExecutionState::Running),
+                (pending_2, ExecutionState::Pending)
+            ],
+            queued_executions(&key.0, &key.1)
+        );
+
+        assert_eq!(1, clear_queue(&key.0, &key.1));
+        assert_eq!(
+            vec![(running, ExecutionState::Running)],
+            queued_executions(&key.0, &key.1)
+        );
+        assert_eq!(Some(ExecutionState::Cancelled), execution_state(pending_2));
+        assert!(!cancel_execution(pending_2).unwrap());
+    }
+
+    #[test]
+    fn test_ended_execution_forgotten() {
+        let key = ("http://forget-test".to_string(), "kernel".to_string());
+        let execution_id = enqueue(&key, ExecutionState::Running);
+        finish(execution_id, Ok(None), vec![]);
+        assert!(queued_executions(&key.0, &key.1).is_empty());
+        assert_eq!(Some(ExecutionState::Done), execution_state(execution_id));
+
+        forget_ended(&[ExecutionEvent::Done {
+            execution_id,
+            state: ExecutionState::Done,
+            error: None,
+            kernel_error: None,
+            timeout_outcome: None,
+        }]);
+        assert_eq!(None, execution_state(execution_id));
+    }
+
+    #[test]
+    fn test_queued_run_cancelled() {
+        let key = ("http://wait-test".to_string(), "kernel".to_string());
+        let running = enqueue(&key, ExecutionState::Running);
+        let run = runtime().unwrap().spawn(run_queued(
+            key.0.clone(),
+            key.1.clone(),
+            "1".to_string(),
+            ExecutionOptions::default(),
+        ));
+        while queued_executions(&key.0, &key.1).len() < 2 {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(1, clear_queue(&key.0, &key.1));
+        assert!(matches!(
+            runtime().unwrap().block_on(run).unwrap(),
+            Err(JupyterRunnerError::ExecutionCancelled(_))
+        ));
+        assert_eq!(
+            vec![(running, ExecutionState::Running)],
+            queued_executions(&key.0, &key.1)
+        );
+    }
+
+    #[test]
+    fn test_timeout_paused_by_input() {
+        runtime().unwrap().block_on(async {
+            let limit = Duration::from_millis(20);
+            let mut running = Box::pin(sleep(Duration::from_millis(100)));
+            let (_input_pending_sender, input_pending) = watch::channel(false);
+            assert!(with_timeout(limit, &mut running, input_pending)
+                .await
+                .is_none());
+
+            let mut running = Box::pin(sleep(Duration::from_millis(100)));
+            let (_input_pending_sender, input_pending) = watch::channel(true);
+            assert!(with_timeout(limit, &mut running, input_pending)
+                .await
+                .is_some());
+        });
+    }
+}
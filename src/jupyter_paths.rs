@@ -0,0 +1,98 @@
+use std::path::{Path, PathBuf};
+
+fn env_path(key: &str) -> Option<PathBuf> {
+    std::env::var_os(key)
+        .filter(|value| !value.is_empty())
+        .map(PathBuf::from)
+}
+
+/// The per-user data dir, the way jupyter_core picks it on each platform.
+pub fn user_data_dir() -> Option<PathBuf> {
+    if let Some(data_dir) = env_path("JUPYTER_DATA_DIR") {
+        return Some(data_dir);
+    }
+    if cfg!(target_os = "macos") {
+        env_path("HOME").map(|home| home.join("Library/Jupyter"))
+    } else if cfg!(windows) {
+        env_path("APPDATA").map(|app_data| app_data.join("jupyter"))
+    } else {
+        env_path("XDG_DATA_HOME")
+            .or_else(|| env_path("HOME").map(|home| home.join(".local/share")))
+            .map(|data_home| data_home.join("jupyter"))
+    }
+}
+
+/// Where servers and kernels write their `jpserver-*.json` and `kernel-*.json` files.
+pub fn runtime_dir() -> Option<PathBuf> {
+    env_path("JUPYTER_RUNTIME_DIR").or_else(|| user_data_dir().map(|dir| dir.join("runtime")))
+}
+
+/// The data dirs in priority order: `$JUPYTER_PATH`, the user's, the active environment's and the system ones.
+pub fn data_dirs() -> Vec<PathBuf> {
+    let mut dirs: Vec<PathBuf> = match std::env::var_os("JUPYTER_PATH") {
+        Some(jupyter_path) => std::env::split_paths(&jupyter_path)
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .collect(),
+        None => vec![],
+    };
+    dirs.extend(user_data_dir());
+    for prefix in ["VIRTUAL_ENV", "CONDA_PREFIX"] {
+        if let Some(prefix) = env_path(prefix) {
+            dirs.push(prefix.join("share").join("jupyter"));
+        }
+    }
+    if cfg!(windows) {
+        dirs.extend(env_path("PROGRAMDATA").map(|program_data| program_data.join("jupyter")));
+    } else {
+        dirs.push(PathBuf::from("/usr/local/share/jupyter"));
+        dirs.push(PathBuf::from("/usr/share/jupyter"));
+    }
+    dirs
+}
+
+/// Files in `dir` whose name starts with one of `prefixes` and ends with `.json`.
+pub fn runtime_files(dir: &Path, prefixes: &[&str]) -> Vec<PathBuf> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+    let mut files: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(
+            |path| match path.file_name().and_then(|name| name.to_str()) {
+                Some(name) => {
+                    name.ends_with(".json")
+                        && prefixes.iter().any(|prefix| name.starts_with(prefix))
+                }
+                None => false,
+            },
+        )
+        .collect();
+    files.sort();
+    files
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_runtime_files() {
+        let dir = std::env::temp_dir().join(format!("run-jupyter-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for name in [
+            "jpserver-1.json",
+            "nbserver-2.json",
+            "kernel-3.json",
+            "jpserver-1-open.html",
+        ] {
+            std::fs::write(dir.join(name), "{}").unwrap();
+        }
+        let files: Vec<String> = runtime_files(&dir, &["jpserver-", "nbserver-"])
+            .iter()
+            .map(|path| path.file_name().unwrap().to_str().unwrap().to_string())
+            .collect();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(vec!["jpserver-1.json", "nbserver-2.json"], files);
+    }
+}
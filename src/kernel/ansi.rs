@@ -0,0 +1,228 @@
+const ESC: char = '\u{1b}';
+
+/// A color of an SGR sequence: an index of the 256 color palette (0-15 being the terminal's own colors) or RGB.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AnsiColor {
+    Indexed(u8),
+    Rgb(u8, u8, u8),
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct AnsiStyle {
+    pub fg: Option<AnsiColor>,
+    pub bg: Option<AnsiColor>,
+    pub bold: bool,
+    pub italic: bool,
+    pub underline: bool,
+}
+
+impl AnsiStyle {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    fn apply_sgr(&mut self, params: &str) {
+        let mut codes = params
+            .split(';')
+            .map(|code| code.parse::<u16>().unwrap_or(0));
+        while let Some(code) = codes.next() {
+            match code {
+                0 => *self = Self::default(),
+                1 => self.bold = true,
+                3 => self.italic = true,
+                4 => self.underline = true,
+                22 => self.bold = false,
+                23 => self.italic = false,
+                24 => self.underline = false,
+                30..=37 => self.fg = Some(AnsiColor::Indexed((code - 30) as u8)),
+                38 => self.fg = extended_color(&mut codes),
+                39 => self.fg = None,
+                40..=47 => self.bg = Some(AnsiColor::Indexed((code - 40) as u8)),
+                48 => self.bg = extended_color(&mut codes),
+                49 => self.bg = None,
+                90..=97 => self.fg = Some(AnsiColor::Indexed((code - 90 + 8) as u8)),
+                100..=107 => self.bg = Some(AnsiColor::Indexed((code - 100 + 8) as u8)),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// `5;n` or `2;r;g;b` after 38 or 48.
+fn extended_color(codes: &mut impl Iterator<Item = u16>) -> Option<AnsiColor> {
+    let mut next = || codes.next().map(|code| code.min(255) as u8);
+    match next()? {
+        5 => Some(AnsiColor::Indexed(next()?)),
+        2 => Some(AnsiColor::Rgb(next()?, next()?, next()?)),
+        _ => None,
+    }
+}
+
+/// A styled range of one line of the stripped text, in byte columns.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnsiSpan {
+    pub line: usize,
+    pub start: usize,
+    pub end: usize,
+    pub style: AnsiStyle,
+}
+
+/// Text with its ANSI escape sequences removed, and the ranges their colors applied to.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StyledText {
+    pub text: String,
+    pub spans: Vec<AnsiSpan>,
+}
+
+impl StyledText {
+    fn push(&mut self, c: char, style: &AnsiStyle, line: &mut usize, line_start: &mut usize) {
+        if c == '\n' {
+            self.text.push(c);
+            *line += 1;
+            *line_start = self.text.len();
+            return;
+        }
+        let start = self.text.len() - *line_start;
+        self.text.push(c);
+        if style.is_default() {
+            return;
+        }
+        let end = self.text.len() - *line_start;
+        match self.spans.last_mut() {
+            Some(span) if span.line == *line && span.end == start && span.style == *style => {
+                span.end = end;
+            }
+            _ => self.spans.push(AnsiSpan {
+                line: *line,
+                start,
+                end,
+                style: *style,
+            }),
+        }
+    }
+}
+
+/// Removes ANSI escape sequences (colors, cursor movement) from kernel text such as tracebacks,
+/// keeping where the SGR (color) sequences applied.
+pub fn parse_ansi(s: &str) -> StyledText {
+    let mut styled = StyledText {
+        text: String::with_capacity(s.len()),
+        spans: vec![],
+    };
+    let mut style = AnsiStyle::default();
+    let (mut line, mut line_start) = (0, 0);
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != ESC {
+            styled.push(c, &style, &mut line, &mut line_start);
+            continue;
+        }
+        match chars.peek() {
+            // CSI: parameters and intermediates until a final byte in '@'..='~'
+            Some('[') => {
+                chars.next();
+                let mut params = String::new();
+                for each in chars.by_ref() {
+                    if ('@'..='~').contains(&each) {
+                        if each == 'm' {
+                            style.apply_sgr(&params);
+                        }
+                        break;
+                    }
+                    params.push(each);
+                }
+            }
+            // OSC: until BEL or ST
+            Some(']') => {
+                chars.next();
+                while let Some(each) = chars.next() {
+                    if each == '\u{7}' {
+                        break;
+                    }
+                    if each == ESC && chars.peek() == Some(&'\\') {
+                        chars.next();
+                        break;
+                    }
+                }
+            }
+            // two character sequences such as ESC c
+            Some(_) => {
+                chars.next();
+            }
+            None => {}
+        }
+    }
+    styled
+}
+
+/// Removes ANSI escape sequences (colors, cursor movement) from kernel text such as tracebacks.
+pub fn strip_ansi(s: &str) -> String {
+    parse_ansi(s).text
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_strip_ansi_colors() {
+        assert_eq!(
+            "ZeroDivisionError: division by zero",
+            strip_ansi("\u{1b}[0;31mZeroDivisionError\u{1b}[0m: division by zero")
+        );
+    }
+
+    #[test]
+    fn test_strip_ansi_plain_text() {
+        assert_eq!("no escapes [0m here", strip_ansi("no escapes [0m here"));
+    }
+
+    #[test]
+    fn test_strip_ansi_osc_and_multibyte() {
+        assert_eq!(
+            "link 日本語",
+            strip_ansi("\u{1b}]8;;http://x\u{7}link\u{1b}[1;32m 日本語\u{1b}[39m")
+        );
+    }
+
+    #[test]
+    fn test_parse_ansi_spans() {
+        let styled = parse_ansi(
+            "\u{1b}[0;31mZeroDivisionError\u{1b}[0m: x\n\u{1b}[1;38;5;208mbold\u{1b}[22m plain",
+        );
+        assert_eq!("ZeroDivisionError: x\nbold plain", styled.text);
+        assert_eq!(
+            vec![
+                AnsiSpan {
+                    line: 0,
+                    start: 0,
+                    end: 17,
+                    style: AnsiStyle {
+                        fg: Some(AnsiColor::Indexed(1)),
+                        ..Default::default()
+                    },
+                },
+                AnsiSpan {
+                    line: 1,
+                    start: 0,
+                    end: 4,
+                    style: AnsiStyle {
+                        fg: Some(AnsiColor::Indexed(208)),
+                        bold: true,
+                        ..Default::default()
+                    },
+                },
+                AnsiSpan {
+                    line: 1,
+                    start: 4,
+                    end: 10,
+                    style: AnsiStyle {
+                        fg: Some(AnsiColor::Indexed(208)),
+                        ..Default::default()
+                    },
+                },
+            ],
+            styled.spans
+        );
+    }
+}
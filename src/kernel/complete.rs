@@ -0,0 +1,158 @@
+use super::connection::KernelConnection;
+use super::cursor::{byte_to_code_point, code_point_to_byte};
+use super::message::Channel;
+use crate::error::JupyterRunnerError;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+
+type Result<T> = std::result::Result<T, JupyterRunnerError>;
+
+const TYPES_METADATA_KEY: &str = "_jupyter_types_experimental";
+
+/// The content of `complete_reply`. Offsets are in code points.
+#[derive(Debug, Clone, Deserialize)]
+struct CompleteReplyContent {
+    #[serde(default)]
+    status: String,
+    #[serde(default)]
+    matches: Vec<String>,
+    #[serde(default)]
+    cursor_start: usize,
+    #[serde(default)]
+    cursor_end: usize,
+    #[serde(default)]
+    metadata: Map<String, Value>,
+    #[serde(default)]
+    evalue: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct TypedMatchContent {
+    text: String,
+    #[serde(default)]
+    start: usize,
+    #[serde(default)]
+    end: usize,
+    #[serde(default, rename = "type")]
+    match_type: Option<String>,
+    #[serde(default)]
+    signature: Option<String>,
+}
+
+/// A match of IPython's `_jupyter_types_experimental` metadata, e.g. type `function` or `instance`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypedMatch {
+    pub text: String,
+    pub start: usize,
+    pub end: usize,
+    pub match_type: Option<String>,
+    pub signature: Option<String>,
+}
+
+/// Completion matches. `cursor_start` and `cursor_end` are byte offsets in the code, the range the matches replace.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Completion {
+    pub matches: Vec<String>,
+    pub cursor_start: usize,
+    pub cursor_end: usize,
+    pub typed_matches: Option<Vec<TypedMatch>>,
+}
+
+impl Completion {
+    fn from_content(code: &str, content: CompleteReplyContent) -> Result<Self> {
+        if content.status == "error" {
+            return Err(JupyterRunnerError::RequestFailed(format!(
+                "complete_request :{}",
+                content.evalue
+            )));
+        }
+        let typed_matches = content
+            .metadata
+            .get(TYPES_METADATA_KEY)
+            .cloned()
+            .and_then(|types| serde_json::from_value::<Vec<TypedMatchContent>>(types).ok())
+            .map(|types| {
+                types
+                    .into_iter()
+                    .map(|typed| TypedMatch {
+                        text: typed.text,
+                        start: code_point_to_byte(code, typed.start),
+                        end: code_point_to_byte(code, typed.end),
+                        match_type: typed.match_type,
+                        signature: typed.signature,
+                    })
+                    .collect()
+            });
+        Ok(Self {
+            matches: content.matches,
+            cursor_start: code_point_to_byte(code, content.cursor_start),
+            cursor_end: code_point_to_byte(code, content.cursor_end),
+            typed_matches,
+        })
+    }
+}
+
+/// Asks the kernel for the completions at byte offset `cursor_pos` of `code`.
+pub async fn complete(
+    connection: &KernelConnection,
+    code: &str,
+    cursor_pos: usize,
+) -> Result<Completion> {
+    let reply = connection
+        .request_reply(
+            Channel::Shell,
+            "complete_request",
+            json!({
+                "code": code,
+                "cursor_pos": byte_to_code_point(code, cursor_pos),
+            }),
+        )
+        .await?;
+    Completion::from_content(code, serde_json::from_value(reply.content)?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_completion_from_ipython_reply() {
+        let code = "données = df; données.co";
+        let content: CompleteReplyContent = serde_json::from_value(json!({
+            "status": "ok",
+            "matches": ["columns", "copy"],
+            "cursor_start": 22,
+            "cursor_end": 24,
+            "metadata": {
+                "_jupyter_types_experimental": [
+                    {"start": 22, "end": 24, "text": "columns", "type": "instance", "signature": ""},
+                    {"start": 22, "end": 24, "text": "copy", "type": "function", "signature": "(deep=True)"}
+                ]
+            }
+        }))
+        .unwrap();
+        let completion = Completion::from_content(code, content).unwrap();
+
+        assert_eq!(code.len() - 2, completion.cursor_start);
+        assert_eq!(code.len(), completion.cursor_end);
+        let typed_matches = completion.typed_matches.unwrap();
+        assert_eq!(Some("function".to_string()), typed_matches[1].match_type);
+        assert_eq!(Some("(deep=True)".to_string()), typed_matches[1].signature);
+        assert_eq!(code.len() - 2, typed_matches[1].start);
+    }
+
+    #[test]
+    fn test_completion_without_types() {
+        let content: CompleteReplyContent = serde_json::from_value(json!({
+            "status": "ok",
+            "matches": ["println!"],
+            "cursor_start": 0,
+            "cursor_end": 4,
+            "metadata": {}
+        }))
+        .unwrap();
+        let completion = Completion::from_content("prin", content).unwrap();
+        assert_eq!(None, completion.typed_matches);
+        assert_eq!(vec!["println!".to_string()], completion.matches);
+    }
+}
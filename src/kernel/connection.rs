@@ -0,0 +1,293 @@
+use super::connection_file::ConnectionInfo;
+use super::message::{Channel, Message};
+use super::zmq_channels;
+use crate::error::JupyterRunnerError;
+use futures_util::stream::SplitSink;
+use futures_util::{SinkExt, StreamExt};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::task::JoinHandle;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::http::{HeaderName, HeaderValue, StatusCode};
+use tokio_tungstenite::tungstenite::Message as WsMessage;
+use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
+use url::Url;
+
+type Result<T> = std::result::Result<T, JupyterRunnerError>;
+type WsSink = SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, WsMessage>;
+type PendingRequests = Arc<Mutex<HashMap<String, UnboundedSender<Message>>>>;
+
+/// The websocket url of the kernel's channels, e.g. `ws://localhost:8888/api/kernels/<id>/channels?session_id=<session>`.
+pub fn channels_url(jupyter_base_url: &str, kernel_id: &str, session: &str) -> Result<Url> {
+    let mut url = Url::parse(jupyter_base_url)?;
+    let scheme = if url.scheme() == "https" { "wss" } else { "ws" };
+    // `set_scheme` refuses to turn a special scheme into a non special one, so the url is rebuilt.
+    let mut url_str = url.as_str().replacen(url.scheme(), scheme, 1);
+    if !url_str.ends_with('/') {
+        url_str.push('/');
+    }
+    url = Url::parse(&url_str)?.join(&format!("api/kernels/{kernel_id}/channels"))?;
+    url.query_pairs_mut()
+        .clear()
+        .append_pair("session_id", session);
+    Ok(url)
+}
+
+/// What the reader of a connection shares with the requests sent over it.
+#[derive(Clone, Default)]
+pub struct ConnectionState {
+    pub(super) pending_requests: PendingRequests,
+    pub(super) closed: Arc<AtomicBool>,
+    /// The execution_state of the last status message the kernel published.
+    execution_state: Arc<Mutex<Option<String>>>,
+    /// How many times the server has announced that it restarted the kernel after it died.
+    restarts: Arc<AtomicUsize>,
+}
+
+enum Transport {
+    /// Through the server's websocket. A task reads the stream.
+    WebSocket(tokio::sync::Mutex<WsSink>, JoinHandle<()>),
+    /// Directly to the kernel's zmq sockets, owned by a thread that stops when the sender is dropped.
+    Zmq(Mutex<zmq_channels::ZmqSender>),
+}
+
+/// A connection to one kernel, through the server's websocket or directly over zmq.
+/// Replies are routed to the request they answer by their parent msg_id,
+/// so several requests can be in flight over the same connection.
+pub struct KernelConnection {
+    session: String,
+    transport: Transport,
+    state: ConnectionState,
+}
+
+impl Drop for KernelConnection {
+    fn drop(&mut self) {
+        if let Transport::WebSocket(_, reader) = &self.transport {
+            reader.abort();
+        }
+    }
+}
+
+/// The messages a kernel sends in reply to one request, on any channel.
+pub struct PendingRequest {
+    pub msg_id: String,
+    receiver: UnboundedReceiver<Message>,
+    pending_requests: PendingRequests,
+}
+
+impl PendingRequest {
+    pub async fn recv(&mut self) -> Result<Message> {
+        self.receiver
+            .recv()
+            .await
+            .ok_or_else(|| JupyterRunnerError::ConnectionClosed(self.msg_id.clone()))
+    }
+}
+
+impl Drop for PendingRequest {
+    fn drop(&mut self) {
+        self.pending_requests.lock().unwrap().remove(&self.msg_id);
+    }
+}
+
+impl KernelConnection {
+    /// `headers` are added to the handshake, e.g. the `Authorization` header of token auth.
+    pub async fn connect(
+        jupyter_base_url: &str,
+        kernel_id: &str,
+        headers: &[(String, String)],
+    ) -> Result<Self> {
+        let session = uuid::Uuid::new_v4().to_string();
+        let mut request =
+            channels_url(jupyter_base_url, kernel_id, &session)?.into_client_request()?;
+        for (name, value) in headers {
+            if let (Ok(name), Ok(value)) = (
+                HeaderName::from_bytes(name.as_bytes()),
+                HeaderValue::from_str(value),
+            ) {
+                request.headers_mut().insert(name, value);
+            }
+        }
+
+        let stream = match connect_async(request).await {
+            Ok((stream, _)) => stream,
+            Err(tokio_tungstenite::tungstenite::Error::Http(response))
+                if response.status() == StatusCode::UNAUTHORIZED
+                    || response.status() == StatusCode::FORBIDDEN =>
+            {
+                return Err(JupyterRunnerError::AuthenticationFailed(format!(
+                    "{} kernel channels of {kernel_id}",
+                    response.status()
+                )))
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let (sink, mut stream) = stream.split();
+
+        let state = ConnectionState::default();
+        let reader = {
+            let state = state.clone();
+            tokio::spawn(async move {
+                while let Some(frame) = stream.next().await {
+                    let text = match frame {
+                        Ok(WsMessage::Text(text)) => text,
+                        Ok(WsMessage::Close(_)) | Err(_) => break,
+                        Ok(_) => continue,
+                    };
+                    let message: Message = match serde_json::from_str(&text) {
+                        Ok(message) => message,
+                        Err(_) => continue,
+                    };
+                    route_message(&state, message);
+                }
+                state.closed.store(true, Ordering::SeqCst);
+                // dropping the senders wakes every request still waiting for a reply.
+                state.pending_requests.lock().unwrap().clear();
+            })
+        };
+
+        Ok(Self {
+            session,
+            transport: Transport::WebSocket(tokio::sync::Mutex::new(sink), reader),
+            state,
+        })
+    }
+
+    /// Talks to a kernel without a server, over the ports of its connection file.
+    pub fn connect_zmq(connection_info: &ConnectionInfo) -> Result<Self> {
+        let session = uuid::Uuid::new_v4().to_string();
+        let state = ConnectionState::default();
+        let sender = zmq_channels::spawn(connection_info, &session, state.clone())?;
+        Ok(Self {
+            session,
+            transport: Transport::Zmq(Mutex::new(sender)),
+            state,
+        })
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.state.closed.load(Ordering::SeqCst)
+    }
+
+    /// The kernel's execution_state (`busy`, `idle`...) as last published on iopub, if seen yet.
+    pub fn execution_state(&self) -> Option<String> {
+        self.state.execution_state.lock().unwrap().clone()
+    }
+
+    /// The number of `restarting` statuses the server has sent since the connection was made.
+    pub fn restarts(&self) -> usize {
+        self.state.restarts.load(Ordering::SeqCst)
+    }
+
+    pub fn new_message(&self, channel: Channel, msg_type: &str, content: Value) -> Message {
+        Message::new_request(&self.session, channel, msg_type, content)
+    }
+
+    pub async fn send(&self, message: &Message) -> Result<()> {
+        if self.is_closed() {
+            return Err(JupyterRunnerError::ConnectionClosed(
+                message.header.msg_id.clone(),
+            ));
+        }
+        match &self.transport {
+            Transport::WebSocket(sink, _) => {
+                let text = serde_json::to_string(message)?;
+                sink.lock().await.send(WsMessage::Text(text)).await?;
+            }
+            Transport::Zmq(sender) => {
+                if !sender.lock().unwrap().send(message.clone()) {
+                    return Err(JupyterRunnerError::ConnectionClosed(
+                        message.header.msg_id.clone(),
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends a request and returns the handle its replies arrive on.
+    pub async fn request(
+        &self,
+        channel: Channel,
+        msg_type: &str,
+        content: Value,
+    ) -> Result<PendingRequest> {
+        let message = self.new_message(channel, msg_type, content);
+        let (sender, receiver) = unbounded_channel();
+        // registered before sending so that no reply can arrive unrouted.
+        self.state
+            .pending_requests
+            .lock()
+            .unwrap()
+            .insert(message.header.msg_id.clone(), sender);
+        let pending_request = PendingRequest {
+            msg_id: message.header.msg_id.clone(),
+            receiver,
+            pending_requests: self.state.pending_requests.clone(),
+        };
+
+        self.send(&message).await?;
+        Ok(pending_request)
+    }
+
+    /// Sends a request and waits for its reply on `channel`, ignoring everything else.
+    pub async fn request_reply(
+        &self,
+        channel: Channel,
+        msg_type: &str,
+        content: Value,
+    ) -> Result<Message> {
+        let reply_type = msg_type.replace("_request", "_reply");
+        let mut pending_request = self.request(channel, msg_type, content).await?;
+        loop {
+            let message = pending_request.recv().await?;
+            if message.channel() == Some(channel) && message.msg_type() == reply_type {
+                return Ok(message);
+            }
+        }
+    }
+}
+
+pub(super) fn route_message(state: &ConnectionState, message: Message) {
+    if message.channel() == Some(Channel::IOPub) && message.msg_type() == "status" {
+        if let Some(execution_state) = message.content_str("execution_state") {
+            // sent by the server alone, when its restarter has brought a dead kernel back.
+            if execution_state == "restarting" {
+                state.restarts.fetch_add(1, Ordering::SeqCst);
+            }
+            *state.execution_state.lock().unwrap() = Some(execution_state.to_string());
+        }
+    }
+    let pending_requests = state.pending_requests.lock().unwrap();
+    if let Some(sender) = message
+        .parent_msg_id()
+        .and_then(|msg_id| pending_requests.get(msg_id))
+    {
+        let _ = sender.send(message);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_channels_url() {
+        assert_eq!(
+            "ws://localhost:8888/api/kernels/abc/channels?session_id=s",
+            channels_url("http://localhost:8888", "abc", "s")
+                .unwrap()
+                .as_str()
+        );
+        assert_eq!(
+            "wss://example.com/jupyter/api/kernels/abc/channels?session_id=s",
+            channels_url("https://example.com/jupyter/?token=x", "abc", "s")
+                .unwrap()
+                .as_str()
+        );
+    }
+}
@@ -0,0 +1,152 @@
+use super::message::Channel;
+use crate::error::JupyterRunnerError;
+use serde::{Deserialize, Serialize};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::net::TcpListener;
+use std::path::Path;
+
+type Result<T> = std::result::Result<T, JupyterRunnerError>;
+
+pub const SIGNATURE_SCHEME: &str = "hmac-sha256";
+
+/// The content of a kernel's `kernel-*.json` connection file.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ConnectionInfo {
+    pub shell_port: u16,
+    pub iopub_port: u16,
+    pub stdin_port: u16,
+    pub control_port: u16,
+    pub hb_port: u16,
+    pub ip: String,
+    #[serde(default)]
+    pub key: String,
+    /// `tcp` or `ipc`.
+    #[serde(default = "default_transport")]
+    pub transport: String,
+    #[serde(default = "default_signature_scheme")]
+    pub signature_scheme: String,
+    #[serde(default)]
+    pub kernel_name: String,
+}
+
+fn default_transport() -> String {
+    "tcp".to_string()
+}
+
+fn default_signature_scheme() -> String {
+    SIGNATURE_SCHEME.to_string()
+}
+
+fn free_port(ip: &str) -> Result<u16> {
+    Ok(TcpListener::bind((ip, 0))?.local_addr()?.port())
+}
+
+impl ConnectionInfo {
+    /// Free ports on localhost and a random key, for a kernel this plugin launches.
+    pub fn allocate(kernel_name: &str) -> Result<Self> {
+        let ip = "127.0.0.1";
+        // the listeners are dropped before the kernel binds, so another process could take a port in between.
+        // jupyter_client accepts the same race.
+        Ok(Self {
+            shell_port: free_port(ip)?,
+            iopub_port: free_port(ip)?,
+            stdin_port: free_port(ip)?,
+            control_port: free_port(ip)?,
+            hb_port: free_port(ip)?,
+            ip: ip.to_string(),
+            key: uuid::Uuid::new_v4().to_string(),
+            transport: default_transport(),
+            signature_scheme: default_signature_scheme(),
+            kernel_name: kernel_name.to_string(),
+        })
+    }
+
+    pub fn read(path: &Path) -> Result<Self> {
+        Ok(serde_json::from_str(&std::fs::read_to_string(path)?)?)
+    }
+
+    /// Creates the file readable by the user only, as anyone holding the key can run code in the kernel.
+    /// Fails if the file exists.
+    pub fn write(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let mut file = options.open(path)?;
+        file.write_all(serde_json::to_string_pretty(self)?.as_bytes())?;
+        Ok(())
+    }
+
+    pub fn port(&self, channel: Channel) -> u16 {
+        match channel {
+            Channel::Shell => self.shell_port,
+            Channel::IOPub => self.iopub_port,
+            Channel::Stdin => self.stdin_port,
+            Channel::Control => self.control_port,
+        }
+    }
+
+    /// The zmq endpoint of a port, e.g. `tcp://127.0.0.1:5555`. ipc endpoints are `<ip>-<port>` files.
+    pub fn endpoint(&self, port: u16) -> String {
+        if self.transport == "ipc" {
+            format!("ipc://{}-{}", self.ip, port)
+        } else {
+            format!("{}://{}:{}", self.transport, self.ip, port)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_read_connection_file() {
+        let info: ConnectionInfo = serde_json::from_str(
+            r#"{
+                "shell_port": 53001, "iopub_port": 53002, "stdin_port": 53003, "control_port": 53004, "hb_port": 53005,
+                "ip": "127.0.0.1", "key": "a0436f6c-1916-498b-8eb9-e81ab9368e84",
+                "transport": "tcp", "signature_scheme": "hmac-sha256", "kernel_name": ""
+            }"#,
+        )
+        .unwrap();
+        assert_eq!("tcp://127.0.0.1:53002", info.endpoint(info.iopub_port));
+        assert_eq!(53004, info.port(Channel::Control));
+    }
+
+    #[test]
+    fn test_ipc_endpoint() {
+        let info = ConnectionInfo {
+            transport: "ipc".to_string(),
+            ip: "/tmp/kernel-1".to_string(),
+            ..ConnectionInfo::allocate("python3").unwrap()
+        };
+        assert_eq!("ipc:///tmp/kernel-1-5555", info.endpoint(5555));
+    }
+
+    #[test]
+    fn test_write_connection_file_private() {
+        let path = std::env::temp_dir().join(format!(
+            "run-jupyter-test-kernel-{}.json",
+            uuid::Uuid::new_v4()
+        ));
+        let info = ConnectionInfo::allocate("python3").unwrap();
+        info.write(&path).unwrap();
+        assert!(info.write(&path).is_err());
+        assert_eq!(info, ConnectionInfo::read(&path).unwrap());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(0o600, mode & 0o777);
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+}
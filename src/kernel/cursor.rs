@@ -0,0 +1,35 @@
+//! Neovim counts columns in bytes, the protocol (since 5.2) counts cursor positions in unicode code points.
+
+/// The code point offset of byte `byte_offset` in `code`. Offsets inside a character count as its start.
+pub fn byte_to_code_point(code: &str, byte_offset: usize) -> usize {
+    let mut byte_offset = byte_offset.min(code.len());
+    while !code.is_char_boundary(byte_offset) {
+        byte_offset -= 1;
+    }
+    code[..byte_offset].chars().count()
+}
+
+/// The byte offset of code point `code_point_offset` in `code`. Offsets past the end give `code.len()`.
+pub fn code_point_to_byte(code: &str, code_point_offset: usize) -> usize {
+    code.char_indices()
+        .nth(code_point_offset)
+        .map(|(byte_offset, _)| byte_offset)
+        .unwrap_or(code.len())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_offsets() {
+        let code = "s = \"日本\"; s.";
+        assert_eq!(10, byte_to_code_point(code, code.len() - 2));
+        assert_eq!(code.len() - 2, code_point_to_byte(code, 10));
+
+        // inside 日
+        assert_eq!(5, byte_to_code_point(code, 6));
+        assert_eq!(code.len(), code_point_to_byte(code, 100));
+        assert_eq!(code.chars().count(), byte_to_code_point(code, 100));
+    }
+}
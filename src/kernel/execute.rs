@@ -0,0 +1,182 @@
+use super::connection::KernelConnection;
+use super::message::{Channel, Message};
+use super::output::{ExecutionOutput, KernelError};
+use crate::error::JupyterRunnerError;
+use serde_json::{json, Value};
+use tokio::sync::oneshot;
+
+type Result<T> = std::result::Result<T, JupyterRunnerError>;
+
+/// The content of `execute_reply`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExecuteReply {
+    /// `ok`, `error` or `aborted`.
+    pub status: String,
+    pub execution_count: Option<i64>,
+    /// Set when the code raised, i.e. the status is `error`.
+    pub error: Option<KernelError>,
+}
+
+/// A prompt of the running code, e.g. Python's `input()`, `getpass()` or pdb's.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InputRequest {
+    pub prompt: String,
+    /// The input must not be echoed.
+    pub password: bool,
+}
+
+/// Answers the input requests of an execution. The kernel waits until the receiver resolves,
+/// and gets an empty line if its sender is dropped. The receiver is dropped if the execution ends first, e.g. on an interrupt.
+pub type InputHandler<'a> = &'a (dyn Fn(InputRequest) -> oneshot::Receiver<String> + Sync);
+
+/// An input request waiting for the user, with the header of the request to reply to.
+struct PendingInput {
+    value: oneshot::Receiver<String>,
+    request_header: Value,
+}
+
+enum Received {
+    Message(Box<Message>),
+    Input(String),
+}
+
+/// Runs `code` and hands every output to `on_output` as it arrives.
+/// Returns once the kernel has replied and gone back to idle, so no output of this execution is left behind.
+/// Without `on_input`, the kernel is told that stdin is not available and code reading it fails.
+pub async fn execute<F>(
+    connection: &KernelConnection,
+    code: &str,
+    on_output: F,
+    on_input: Option<InputHandler<'_>>,
+) -> Result<ExecuteReply>
+where
+    F: FnMut(ExecutionOutput),
+{
+    execute_request(connection, code, false, on_output, on_input).await
+}
+
+/// Runs `code` without it counting as an execution: it is kept out of the history and the execution count,
+/// and publishes no output. The reply still tells if it raised.
+pub async fn execute_silently(connection: &KernelConnection, code: &str) -> Result<ExecuteReply> {
+    execute_request(connection, code, true, |_| {}, None).await
+}
+
+async fn execute_request<F>(
+    connection: &KernelConnection,
+    code: &str,
+    silent: bool,
+    mut on_output: F,
+    on_input: Option<InputHandler<'_>>,
+) -> Result<ExecuteReply>
+where
+    F: FnMut(ExecutionOutput),
+{
+    let content = json!({
+        "code": code,
+        "silent": silent,
+        "store_history": !silent,
+        "user_expressions": {},
+        "allow_stdin": on_input.is_some(),
+        "stop_on_error": true,
+    });
+    let mut pending_request = connection
+        .request(Channel::Shell, "execute_request", content)
+        .await?;
+
+    let mut reply = None;
+    let mut idle = false;
+    // some kernels leave the traceback out of execute_reply, so the one published on IOPub is kept as a fallback.
+    let mut published_error = None;
+    // the messages keep being read while the user answers, so that e.g. an interrupt still ends the execution.
+    let mut pending_input: Option<PendingInput> = None;
+    while reply.is_none() || !idle {
+        let received = match pending_input.as_mut() {
+            Some(input) => tokio::select! {
+                value = &mut input.value => Received::Input(value.unwrap_or_default()),
+                message = pending_request.recv() => Received::Message(Box::new(message?)),
+            },
+            None => Received::Message(Box::new(pending_request.recv().await?)),
+        };
+        let message = match received {
+            Received::Message(message) => *message,
+            Received::Input(value) => {
+                let input = pending_input.take().unwrap();
+                let mut input_reply = connection.new_message(
+                    Channel::Stdin,
+                    "input_reply",
+                    json!({ "value": value }),
+                );
+                input_reply.parent_header = input.request_header;
+                connection.send(&input_reply).await?;
+                continue;
+            }
+        };
+        match (message.channel(), message.msg_type()) {
+            (Some(Channel::Shell), "execute_reply") => {
+                // the kernel no longer reads the answer, e.g. after an interrupt.
+                pending_input = None;
+                reply = Some(message);
+            }
+            (Some(Channel::Stdin), "input_request") => {
+                if let Some(on_input) = on_input {
+                    let value = on_input(InputRequest {
+                        prompt: message
+                            .content_str("prompt")
+                            .unwrap_or_default()
+                            .to_string(),
+                        password: message
+                            .content
+                            .get("password")
+                            .and_then(Value::as_bool)
+                            .unwrap_or(false),
+                    });
+                    pending_input = Some(PendingInput {
+                        value,
+                        request_header: serde_json::to_value(&message.header)?,
+                    });
+                }
+            }
+            (Some(Channel::IOPub), "status") => {
+                idle = message.content_str("execution_state") == Some("idle");
+                if idle {
+                    pending_input = None;
+                }
+            }
+            (Some(Channel::IOPub), _) => {
+                if let Some(output) = ExecutionOutput::from_message(&message) {
+                    if let ExecutionOutput::Error(error) = &output {
+                        published_error = Some(error.clone());
+                    }
+                    on_output(output);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let reply = reply.unwrap();
+    let status = reply.content_str("status").unwrap_or("ok").to_string();
+    let execution_count = reply.content.get("execution_count").and_then(Value::as_i64);
+    let error = if status == "error" {
+        let mut error = KernelError::from_content(&reply.content);
+        if let Some(published_error) = published_error {
+            if error.ename.is_empty() {
+                error.ename = published_error.ename;
+                error.evalue = published_error.evalue;
+            }
+            if error.traceback.is_empty() {
+                error.traceback = published_error.traceback;
+            }
+        }
+        error.execution_count = execution_count;
+        Some(error)
+    } else {
+        None
+    };
+
+    Ok(ExecuteReply {
+        status,
+        execution_count,
+        error,
+    })
+}
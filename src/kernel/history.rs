@@ -0,0 +1,205 @@
+use super::connection::KernelConnection;
+use super::message::Channel;
+use crate::error::JupyterRunnerError;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+type Result<T> = std::result::Result<T, JupyterRunnerError>;
+
+/// Which entries to get. Sessions count from the current one, 0, backwards: -1 is the previous session.
+#[derive(Debug, Clone, PartialEq)]
+pub enum HistoryAccess {
+    /// The last `n` entries.
+    Tail { n: usize },
+    /// Lines `start` up to `stop` (exclusive, to the end if None) of `session`.
+    Range {
+        session: i64,
+        start: usize,
+        stop: Option<usize>,
+    },
+    /// The entries matching the glob `pattern`, the last `n` of them if set.
+    Search {
+        pattern: String,
+        n: Option<usize>,
+        unique: bool,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistoryRequest {
+    pub access: HistoryAccess,
+    /// Get the outputs too. IPython keeps those only with `HistoryManager.db_log_output`.
+    pub output: bool,
+    /// The input as typed, before magics are transformed.
+    pub raw: bool,
+}
+
+impl HistoryRequest {
+    fn content(&self) -> Value {
+        let mut content = json!({
+            "output": self.output,
+            "raw": self.raw,
+        });
+        let access = match &self.access {
+            HistoryAccess::Tail { n } => json!({
+                "hist_access_type": "tail",
+                "n": n,
+            }),
+            HistoryAccess::Range {
+                session,
+                start,
+                stop,
+            } => json!({
+                "hist_access_type": "range",
+                "session": session,
+                "start": start,
+                "stop": stop,
+            }),
+            HistoryAccess::Search { pattern, n, unique } => json!({
+                "hist_access_type": "search",
+                "pattern": pattern,
+                "n": n,
+                "unique": unique,
+            }),
+        };
+        if let (Some(content), Value::Object(access)) = (content.as_object_mut(), access) {
+            content.extend(access);
+        }
+        content
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistoryEntry {
+    pub session: i64,
+    pub line: i64,
+    pub input: String,
+    pub output: Option<String>,
+}
+
+/// `[session, line, input]`, or `[session, line, [input, output]]` when outputs were asked for.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+enum HistoryItem {
+    Input(i64, i64, String),
+    InputOutput(i64, i64, (String, Option<String>)),
+}
+
+impl From<HistoryItem> for HistoryEntry {
+    fn from(item: HistoryItem) -> Self {
+        match item {
+            HistoryItem::Input(session, line, input) => Self {
+                session,
+                line,
+                input,
+                output: None,
+            },
+            HistoryItem::InputOutput(session, line, (input, output)) => Self {
+                session,
+                line,
+                input,
+                output,
+            },
+        }
+    }
+}
+
+/// The content of `history_reply`.
+#[derive(Debug, Clone, Deserialize)]
+struct HistoryReplyContent {
+    #[serde(default)]
+    status: String,
+    #[serde(default)]
+    history: Vec<HistoryItem>,
+    #[serde(default)]
+    evalue: String,
+}
+
+impl HistoryReplyContent {
+    fn into_entries(self) -> Result<Vec<HistoryEntry>> {
+        if self.status == "error" {
+            return Err(JupyterRunnerError::RequestFailed(format!(
+                "history_request :{}",
+                self.evalue
+            )));
+        }
+        Ok(self.history.into_iter().map(HistoryEntry::from).collect())
+    }
+}
+
+/// The kernel's input history, of all its clients. Kernels without one reply an empty list.
+pub async fn history(
+    connection: &KernelConnection,
+    request: &HistoryRequest,
+) -> Result<Vec<HistoryEntry>> {
+    let reply = connection
+        .request_reply(Channel::Shell, "history_request", request.content())
+        .await?;
+    serde_json::from_value::<HistoryReplyContent>(reply.content)?.into_entries()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_history_request_content() {
+        let request = HistoryRequest {
+            access: HistoryAccess::Search {
+                pattern: "*df*".to_string(),
+                n: Some(10),
+                unique: true,
+            },
+            output: false,
+            raw: true,
+        };
+        assert_eq!(
+            json!({
+                "output": false,
+                "raw": true,
+                "hist_access_type": "search",
+                "pattern": "*df*",
+                "n": 10,
+                "unique": true,
+            }),
+            request.content()
+        );
+    }
+
+    #[test]
+    fn test_history_entries_from_reply() {
+        let content: HistoryReplyContent = serde_json::from_value(json!({
+            "status": "ok",
+            "history": [
+                [12, 1, "import pandas as pd"],
+                [12, 2, ["df = pd.DataFrame()", null]],
+                [12, 3, ["len(df)", "0"]]
+            ]
+        }))
+        .unwrap();
+        let entries = content.into_entries().unwrap();
+        assert_eq!(
+            vec![
+                HistoryEntry {
+                    session: 12,
+                    line: 1,
+                    input: "import pandas as pd".to_string(),
+                    output: None,
+                },
+                HistoryEntry {
+                    session: 12,
+                    line: 2,
+                    input: "df = pd.DataFrame()".to_string(),
+                    output: None,
+                },
+                HistoryEntry {
+                    session: 12,
+                    line: 3,
+                    input: "len(df)".to_string(),
+                    output: Some("0".to_string()),
+                },
+            ],
+            entries
+        );
+    }
+}
@@ -0,0 +1,75 @@
+use super::connection::KernelConnection;
+use super::message::Channel;
+use crate::error::JupyterRunnerError;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+type Result<T> = std::result::Result<T, JupyterRunnerError>;
+
+#[derive(Debug, Clone, Default, Deserialize, PartialEq)]
+pub struct LanguageInfo {
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub version: String,
+    #[serde(default)]
+    pub file_extension: Option<String>,
+    #[serde(default)]
+    pub mimetype: Option<String>,
+    /// Either a mode name or an object such as `{"name": "ipython", "version": 3}`.
+    #[serde(default)]
+    pub codemirror_mode: Option<Value>,
+}
+
+/// The content of `kernel_info_reply`.
+#[derive(Debug, Clone, Default, Deserialize, PartialEq)]
+pub struct KernelInfo {
+    #[serde(default)]
+    pub protocol_version: String,
+    #[serde(default)]
+    pub implementation: String,
+    #[serde(default)]
+    pub implementation_version: String,
+    #[serde(default)]
+    pub language_info: LanguageInfo,
+    #[serde(default)]
+    pub banner: String,
+}
+
+pub async fn kernel_info(connection: &KernelConnection) -> Result<KernelInfo> {
+    let reply = connection
+        .request_reply(Channel::Shell, "kernel_info_request", json!({}))
+        .await?;
+    Ok(serde_json::from_value(reply.content)?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_deserialize_evcxr_kernel_info() {
+        let info: KernelInfo = serde_json::from_value(json!({
+            "status": "ok",
+            "protocol_version": "5.3",
+            "implementation": "evcxr",
+            "implementation_version": "0.13.0",
+            "language_info": {
+                "name": "Rust",
+                "version": "",
+                "mimetype": "text/rust",
+                "file_extension": ".rs",
+                "pygment_lexer": "rust",
+                "codemirror_mode": "rust",
+                "nbconvert_exporter": "rust"
+            },
+            "banner": "EvCxR 0.13.0 - Evaluation Context for Rust",
+            "help_links": []
+        }))
+        .unwrap();
+        assert_eq!("evcxr", info.implementation);
+        assert_eq!("Rust", info.language_info.name);
+        assert_eq!(Some(".rs".to_string()), info.language_info.file_extension);
+        assert_eq!(Some(json!("rust")), info.language_info.codemirror_mode);
+    }
+}
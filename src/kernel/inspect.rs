@@ -0,0 +1,140 @@
+use super::ansi::{parse_ansi, StyledText};
+use super::connection::KernelConnection;
+use super::cursor::byte_to_code_point;
+use super::message::Channel;
+use super::mime::mime_text;
+use super::output::MimeBundle;
+use crate::error::JupyterRunnerError;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+
+type Result<T> = std::result::Result<T, JupyterRunnerError>;
+
+/// The content of `inspect_reply`.
+#[derive(Debug, Clone, Deserialize)]
+struct InspectReplyContent {
+    #[serde(default)]
+    status: String,
+    #[serde(default)]
+    found: bool,
+    #[serde(default)]
+    data: MimeBundle,
+    #[serde(default)]
+    metadata: Map<String, Value>,
+    #[serde(default)]
+    evalue: String,
+}
+
+/// What the kernel knows of the object at the cursor, e.g. IPython's `obj?` (detail_level 0) or `obj??` (1).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Inspection {
+    pub found: bool,
+    pub data: MimeBundle,
+    pub metadata: Map<String, Value>,
+}
+
+impl Inspection {
+    fn from_content(content: InspectReplyContent) -> Result<Self> {
+        if content.status == "error" {
+            return Err(JupyterRunnerError::RequestFailed(format!(
+                "inspect_request :{}",
+                content.evalue
+            )));
+        }
+        Ok(Self {
+            found: content.found,
+            data: content.data,
+            metadata: content.metadata,
+        })
+    }
+
+    /// `text/plain`, whose ANSI colors are kept as spans. IPython colors the field names of its docstrings.
+    pub fn plain_text(&self) -> Option<StyledText> {
+        self.data
+            .get("text/plain")
+            .and_then(mime_text)
+            .map(|text| parse_ansi(&text))
+    }
+
+    pub fn markdown(&self) -> Option<String> {
+        self.data.get("text/markdown").and_then(mime_text)
+    }
+}
+
+/// Asks the kernel about the object at byte offset `cursor_pos` of `code`. Nothing is executed.
+pub async fn inspect(
+    connection: &KernelConnection,
+    code: &str,
+    cursor_pos: usize,
+    detail_level: u8,
+) -> Result<Inspection> {
+    let reply = connection
+        .request_reply(
+            Channel::Shell,
+            "inspect_request",
+            json!({
+                "code": code,
+                "cursor_pos": byte_to_code_point(code, cursor_pos),
+                "detail_level": detail_level,
+            }),
+        )
+        .await?;
+    Inspection::from_content(serde_json::from_value(reply.content)?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_inspection_from_ipython_reply() {
+        let content: InspectReplyContent = serde_json::from_value(json!({
+            "status": "ok",
+            "found": true,
+            "data": {
+                "text/plain": "\u{1b}[0;31mSignature:\u{1b}[0m \u{1b}[0mlen\u{1b}[0m\u{1b}[0;34m(\u{1b}[0m\u{1b}[0mobj\u{1b}[0m\u{1b}[0;34m,\u{1b}[0m \u{1b}[0;34m/\u{1b}[0m\u{1b}[0;34m)\u{1b}[0m\u{1b}[0;34m\u{1b}[0m\u{1b}[0;34m\u{1b}[0m\n\u{1b}[0;31mDocstring:\u{1b}[0m Return the number of items in a container.\n\u{1b}[0;31mType:\u{1b}[0m      builtin_function_or_method"
+            },
+            "metadata": {}
+        }))
+        .unwrap();
+        let inspection = Inspection::from_content(content).unwrap();
+
+        assert!(inspection.found);
+        let plain_text = inspection.plain_text().unwrap();
+        assert_eq!(
+            "Signature: len(obj, /)\nDocstring: Return the number of items in a container.\nType:      builtin_function_or_method",
+            plain_text.text
+        );
+        assert_eq!((0, 0, 10), {
+            let span = &plain_text.spans[0];
+            (span.line, span.start, span.end)
+        });
+        assert_eq!(None, inspection.markdown());
+    }
+
+    #[test]
+    fn test_inspection_not_found() {
+        let content: InspectReplyContent = serde_json::from_value(json!({
+            "status": "ok",
+            "found": false,
+            "data": {},
+            "metadata": {}
+        }))
+        .unwrap();
+        let inspection = Inspection::from_content(content).unwrap();
+        assert!(!inspection.found);
+        assert_eq!(None, inspection.plain_text());
+    }
+
+    #[test]
+    fn test_inspection_error() {
+        let content: InspectReplyContent = serde_json::from_value(json!({
+            "status": "error",
+            "ename": "NameError",
+            "evalue": "name 'x' is not defined",
+            "traceback": []
+        }))
+        .unwrap();
+        assert!(Inspection::from_content(content).is_err());
+    }
+}
@@ -0,0 +1,89 @@
+use super::connection::KernelConnection;
+use super::message::Channel;
+use crate::error::JupyterRunnerError;
+use serde::Deserialize;
+use serde_json::json;
+
+type Result<T> = std::result::Result<T, JupyterRunnerError>;
+
+/// The content of `is_complete_reply`.
+#[derive(Debug, Clone, Deserialize)]
+struct IsCompleteReplyContent {
+    #[serde(default)]
+    status: String,
+    #[serde(default)]
+    indent: String,
+}
+
+/// Whether the kernel would run the code as it is, as a console decides whether Enter submits.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CodeCompleteness {
+    Complete,
+    /// More lines are needed. `indent` is what the console would put at the start of the next one.
+    Incomplete {
+        indent: String,
+    },
+    /// The code can not become valid by adding lines.
+    Invalid,
+    /// The kernel can not tell, the reply of kernels that do not implement the request.
+    Unknown,
+}
+
+impl From<IsCompleteReplyContent> for CodeCompleteness {
+    fn from(content: IsCompleteReplyContent) -> Self {
+        match content.status.as_str() {
+            "complete" => Self::Complete,
+            "incomplete" => Self::Incomplete {
+                indent: content.indent,
+            },
+            "invalid" => Self::Invalid,
+            _ => Self::Unknown,
+        }
+    }
+}
+
+pub async fn is_complete(connection: &KernelConnection, code: &str) -> Result<CodeCompleteness> {
+    let reply = connection
+        .request_reply(
+            Channel::Shell,
+            "is_complete_request",
+            json!({ "code": code }),
+        )
+        .await?;
+    let content: IsCompleteReplyContent = serde_json::from_value(reply.content)?;
+    Ok(content.into())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn completeness(content: serde_json::Value) -> CodeCompleteness {
+        serde_json::from_value::<IsCompleteReplyContent>(content)
+            .unwrap()
+            .into()
+    }
+
+    #[test]
+    fn test_completeness_from_reply() {
+        assert_eq!(
+            CodeCompleteness::Incomplete {
+                indent: "    ".to_string()
+            },
+            completeness(json!({"status": "incomplete", "indent": "    "}))
+        );
+        assert_eq!(
+            CodeCompleteness::Complete,
+            completeness(json!({"status": "complete"}))
+        );
+        assert_eq!(
+            CodeCompleteness::Invalid,
+            completeness(json!({"status": "invalid"}))
+        );
+        // a kernel replying with an error instead of the status
+        assert_eq!(
+            CodeCompleteness::Unknown,
+            completeness(json!({"status": "error", "ename": "NotImplementedError"}))
+        );
+    }
+}
@@ -0,0 +1,188 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const PROTOCOL_VERSION: &str = "5.3";
+const USERNAME: &str = "run-jupyter";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Channel {
+    Shell,
+    IOPub,
+    Stdin,
+    Control,
+}
+
+impl Channel {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Shell => "shell",
+            Self::IOPub => "iopub",
+            Self::Stdin => "stdin",
+            Self::Control => "control",
+        }
+    }
+
+    pub fn from_str(channel: &str) -> Option<Self> {
+        match channel {
+            "shell" => Some(Self::Shell),
+            "iopub" => Some(Self::IOPub),
+            "stdin" => Some(Self::Stdin),
+            "control" => Some(Self::Control),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Header {
+    pub msg_id: String,
+    pub msg_type: String,
+    #[serde(default)]
+    pub session: String,
+    #[serde(default)]
+    pub username: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub date: Option<String>,
+    #[serde(default)]
+    pub version: String,
+}
+
+/// A message of the Jupyter messaging protocol, in the JSON form the server's websocket speaks.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Message {
+    pub header: Header,
+    /// Empty (`{}`) for messages that are not a reply to anything.
+    #[serde(default)]
+    pub parent_header: Value,
+    #[serde(default)]
+    pub metadata: Map<String, Value>,
+    #[serde(default)]
+    pub content: Value,
+    #[serde(default)]
+    pub buffers: Vec<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub channel: Option<String>,
+}
+
+impl Message {
+    pub fn new_request(session: &str, channel: Channel, msg_type: &str, content: Value) -> Self {
+        Self {
+            header: Header {
+                msg_id: uuid::Uuid::new_v4().to_string(),
+                msg_type: msg_type.to_string(),
+                session: session.to_string(),
+                username: USERNAME.to_string(),
+                date: Some(now_iso8601()),
+                version: PROTOCOL_VERSION.to_string(),
+            },
+            parent_header: Value::Object(Map::new()),
+            metadata: Map::new(),
+            content,
+            buffers: vec![],
+            channel: Some(channel.as_str().to_string()),
+        }
+    }
+
+    pub fn msg_type(&self) -> &str {
+        &self.header.msg_type
+    }
+
+    pub fn channel(&self) -> Option<Channel> {
+        self.channel.as_deref().and_then(Channel::from_str)
+    }
+
+    pub fn parent_msg_id(&self) -> Option<&str> {
+        self.parent_header.get("msg_id").and_then(Value::as_str)
+    }
+
+    pub fn content_str(&self, key: &str) -> Option<&str> {
+        self.content.get(key).and_then(Value::as_str)
+    }
+}
+
+fn now_iso8601() -> String {
+    iso8601(SystemTime::now())
+}
+
+/// `time` in UTC, in the format of the message dates and of the server's `last_activity`.
+pub fn iso8601(time: SystemTime) -> String {
+    let elapsed = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = elapsed.as_secs();
+    let (year, month, day) = civil_from_days((secs / 86400) as i64);
+    let secs_of_day = secs % 86400;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:06}Z",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day % 3600 / 60,
+        secs_of_day % 60,
+        elapsed.subsec_micros()
+    )
+}
+
+// http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_civil_from_days() {
+        assert_eq!((1970, 1, 1), civil_from_days(0));
+        assert_eq!((2000, 2, 29), civil_from_days(11016));
+        assert_eq!((2022, 6, 30), civil_from_days(19173));
+    }
+
+    #[test]
+    fn test_iso8601() {
+        assert_eq!(
+            "2022-06-30T01:02:03.000004Z",
+            iso8601(UNIX_EPOCH + std::time::Duration::new(19173 * 86400 + 3723, 4000))
+        );
+    }
+
+    #[test]
+    fn test_deserialize_reply() {
+        let raw = json!({
+            "header": {"msg_id": "b", "msg_type": "status", "session": "s", "username": "u", "date": "2022-06-30T00:00:00.000000Z", "version": "5.3"},
+            "parent_header": {"msg_id": "a", "msg_type": "execute_request"},
+            "metadata": {},
+            "content": {"execution_state": "idle"},
+            "buffers": [],
+            "channel": "iopub"
+        });
+        let message: Message = serde_json::from_value(raw).unwrap();
+        assert_eq!("status", message.msg_type());
+        assert_eq!(Some("a"), message.parent_msg_id());
+        assert_eq!(Some(Channel::IOPub), message.channel());
+        assert_eq!(Some("idle"), message.content_str("execution_state"));
+    }
+
+    #[test]
+    fn test_unsolicited_message_has_no_parent() {
+        let raw = json!({
+            "header": {"msg_id": "b", "msg_type": "status"},
+            "parent_header": {},
+            "content": {"execution_state": "starting"},
+            "channel": "iopub"
+        });
+        let message: Message = serde_json::from_value(raw).unwrap();
+        assert_eq!(None, message.parent_msg_id());
+    }
+}
@@ -0,0 +1,150 @@
+use super::output::MimeBundle;
+use once_cell::sync::Lazy;
+use serde_json::Value;
+use std::sync::RwLock;
+
+/// The best representation for the terminal first. Markup and images, which a text window can not render,
+/// only come after `text/plain`, which kernels nearly always send.
+pub const DEFAULT_MIME_PRIORITY: &[&str] = &[
+    "text/markdown",
+    "text/plain",
+    "application/json",
+    "text/latex",
+    "text/html",
+    "image/png",
+    "image/svg+xml",
+    "image/jpeg",
+];
+
+static MIME_PRIORITY: Lazy<RwLock<Vec<String>>> = Lazy::new(|| {
+    RwLock::new(
+        DEFAULT_MIME_PRIORITY
+            .iter()
+            .map(|mime_type| mime_type.to_string())
+            .collect(),
+    )
+});
+
+pub fn set_mime_priority(mime_priority: Vec<String>) {
+    *MIME_PRIORITY.write().unwrap() = mime_priority;
+}
+
+pub fn mime_priority() -> Vec<String> {
+    MIME_PRIORITY.read().unwrap().clone()
+}
+
+/// The first type of `mime_priority` the bundle has a representation for.
+/// Falls back to any type in the bundle so that an output is never dropped for lack of a listed type.
+pub fn select_mime_type<'a>(data: &'a MimeBundle, mime_priority: &[String]) -> Option<&'a str> {
+    mime_priority
+        .iter()
+        .find_map(|mime_type| data.get_key_value(mime_type.as_str()))
+        .or_else(|| data.iter().next())
+        .map(|(mime_type, _)| mime_type.as_str())
+}
+
+/// The representation as text. Multiline values may come as a list of lines (the nbformat style) and are joined.
+pub fn mime_text(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.to_string()),
+        Value::Array(lines) => Some(
+            lines
+                .iter()
+                .filter_map(Value::as_str)
+                .collect::<Vec<&str>>()
+                .join(""),
+        ),
+        _ => None,
+    }
+}
+
+/// `application/json` and the `+json` types, whose value is the JSON data itself.
+pub fn is_json_mime_type(mime_type: &str) -> bool {
+    let essence = mime_type.split(';').next().unwrap_or_default().trim();
+    essence == "application/json" || essence.ends_with("+json")
+}
+
+/// The representation of `mime_type` as text, None for JSON types whose value is data rather than lines of text.
+pub fn representation_text(mime_type: &str, value: &Value) -> Option<String> {
+    if is_json_mime_type(mime_type) {
+        return None;
+    }
+    mime_text(value)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    fn bundle(value: Value) -> MimeBundle {
+        value.as_object().unwrap().clone()
+    }
+
+    fn default_priority() -> Vec<String> {
+        DEFAULT_MIME_PRIORITY
+            .iter()
+            .map(|s| s.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_select_dataframe_html() {
+        let data = bundle(json!({"text/plain": "   a\n0  1", "text/html": "<table></table>"}));
+        assert_eq!(
+            Some("text/plain"),
+            select_mime_type(&data, &default_priority())
+        );
+        assert_eq!(
+            Some("text/html"),
+            select_mime_type(&data, &["text/html".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_select_figure_text() {
+        let data = bundle(
+            json!({"text/plain": "<Figure size 640x480 with 1 Axes>", "image/png": "iVBORw0KGgo="}),
+        );
+        assert_eq!(
+            Some("text/plain"),
+            select_mime_type(&data, &default_priority())
+        );
+    }
+
+    #[test]
+    fn test_select_unlisted_type() {
+        let data = bundle(json!({"application/vnd.custom+json": {}}));
+        assert_eq!(
+            Some("application/vnd.custom+json"),
+            select_mime_type(&data, &default_priority())
+        );
+        assert_eq!(
+            None,
+            select_mime_type(&MimeBundle::new(), &default_priority())
+        );
+    }
+
+    #[test]
+    fn test_mime_text() {
+        assert_eq!(Some("a\nb".to_string()), mime_text(&json!(["a\n", "b"])));
+        assert_eq!(Some("<svg/>".to_string()), mime_text(&json!("<svg/>")));
+        assert_eq!(None, mime_text(&json!({"a": 1})));
+    }
+
+    #[test]
+    fn test_representation_text_keeps_json_arrays() {
+        assert_eq!(
+            None,
+            representation_text("application/json", &json!([1, {"a": 2}]))
+        );
+        assert_eq!(
+            None,
+            representation_text("application/vnd.vegalite.v4+json", &json!(["a", "b"]))
+        );
+        assert_eq!(
+            Some("a\nb".to_string()),
+            representation_text("text/plain", &json!(["a\n", "b"]))
+        );
+    }
+}
@@ -0,0 +1,26 @@
+pub mod ansi;
+pub mod complete;
+pub mod connection;
+pub mod connection_file;
+pub mod cursor;
+pub mod execute;
+pub mod history;
+pub mod info;
+pub mod inspect;
+pub mod is_complete;
+pub mod message;
+pub mod mime;
+pub mod output;
+pub mod wire;
+pub mod zmq_channels;
+
+pub use complete::*;
+pub use connection::*;
+pub use connection_file::*;
+pub use execute::*;
+pub use history::*;
+pub use info::*;
+pub use inspect::*;
+pub use is_complete::*;
+pub use mime::*;
+pub use output::*;
@@ -0,0 +1,220 @@
+use super::ansi::strip_ansi;
+use super::message::Message;
+use serde_json::{Map, Value};
+
+/// MIME type to representation, e.g. `text/plain` to the repr of the value.
+pub type MimeBundle = Map<String, Value>;
+
+/// One output of an execution, in the order the kernel published it on IOPub.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExecutionOutput {
+    Stream {
+        name: String,
+        text: String,
+    },
+    DisplayData {
+        data: MimeBundle,
+        metadata: Map<String, Value>,
+    },
+    ExecuteResult {
+        execution_count: Option<i64>,
+        data: MimeBundle,
+        metadata: Map<String, Value>,
+    },
+    Error(KernelError),
+}
+
+/// An exception raised by the executed code, as opposed to a failure to talk to the kernel.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KernelError {
+    pub ename: String,
+    pub evalue: String,
+    /// One entry per frame, with ANSI escapes removed.
+    pub traceback: Vec<String>,
+    /// Only known once the execute_reply has arrived.
+    pub execution_count: Option<i64>,
+}
+
+impl KernelError {
+    /// Reads the fields shared by the `error` IOPub message and an `execute_reply` with status `error`.
+    pub fn from_content(content: &Value) -> Self {
+        let str_field =
+            |key: &str| strip_ansi(content.get(key).and_then(Value::as_str).unwrap_or_default());
+        Self {
+            ename: str_field("ename"),
+            evalue: str_field("evalue"),
+            traceback: content
+                .get("traceback")
+                .and_then(Value::as_array)
+                .map(|lines| {
+                    lines
+                        .iter()
+                        .filter_map(|line| line.as_str().map(strip_ansi))
+                        .collect()
+                })
+                .unwrap_or_default(),
+            execution_count: content.get("execution_count").and_then(Value::as_i64),
+        }
+    }
+}
+
+impl ExecutionOutput {
+    pub fn output_type(&self) -> &'static str {
+        match self {
+            Self::Stream { .. } => "stream",
+            Self::DisplayData { .. } => "display_data",
+            Self::ExecuteResult { .. } => "execute_result",
+            Self::Error { .. } => "error",
+        }
+    }
+
+    /// Returns None for IOPub messages that are not outputs (status, execute_input, ...).
+    pub fn from_message(message: &Message) -> Option<Self> {
+        let content = &message.content;
+        let str_field = |key: &str| message.content_str(key).unwrap_or_default().to_string();
+        let object_field = |key: &str| {
+            content
+                .get(key)
+                .and_then(Value::as_object)
+                .cloned()
+                .unwrap_or_default()
+        };
+
+        match message.msg_type() {
+            "stream" => Some(Self::Stream {
+                name: str_field("name"),
+                text: str_field("text"),
+            }),
+            "display_data" | "update_display_data" => Some(Self::DisplayData {
+                data: object_field("data"),
+                metadata: object_field("metadata"),
+            }),
+            "execute_result" => Some(Self::ExecuteResult {
+                execution_count: content.get("execution_count").and_then(Value::as_i64),
+                data: object_field("data"),
+                metadata: object_field("metadata"),
+            }),
+            "error" => Some(Self::Error(KernelError::from_content(content))),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    fn iopub(msg_type: &str, content: Value) -> Message {
+        serde_json::from_value(json!({
+            "header": {"msg_id": "b", "msg_type": msg_type},
+            "parent_header": {"msg_id": "a"},
+            "content": content,
+            "channel": "iopub"
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_stream() {
+        let output = ExecutionOutput::from_message(&iopub(
+            "stream",
+            json!({"name": "stdout", "text": "hello\n"}),
+        ));
+        assert_eq!(
+            Some(ExecutionOutput::Stream {
+                name: "stdout".to_string(),
+                text: "hello\n".to_string()
+            }),
+            output
+        );
+    }
+
+    #[test]
+    fn test_execute_result() {
+        let output = ExecutionOutput::from_message(&iopub(
+            "execute_result",
+            json!({"execution_count": 3, "data": {"text/plain": "42"}, "metadata": {}}),
+        ))
+        .unwrap();
+        assert_eq!("execute_result", output.output_type());
+        match output {
+            ExecutionOutput::ExecuteResult {
+                execution_count,
+                data,
+                ..
+            } => {
+                assert_eq!(Some(3), execution_count);
+                assert_eq!(Some(&json!("42")), data.get("text/plain"));
+            }
+            other => panic!("unexpected output {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_display_data_keeps_bundle_and_metadata() {
+        let output = ExecutionOutput::from_message(&iopub(
+            "display_data",
+            json!({
+                "data": {"image/svg+xml": "<svg/>", "text/plain": "<Figure>"},
+                "metadata": {"image/svg+xml": {"isolated": true}}
+            }),
+        ));
+        assert_eq!(
+            Some(ExecutionOutput::DisplayData {
+                data: json!({"image/svg+xml": "<svg/>", "text/plain": "<Figure>"})
+                    .as_object()
+                    .unwrap()
+                    .clone(),
+                metadata: json!({"image/svg+xml": {"isolated": true}})
+                    .as_object()
+                    .unwrap()
+                    .clone(),
+            }),
+            output
+        );
+    }
+
+    #[test]
+    fn test_error() {
+        let output = ExecutionOutput::from_message(&iopub(
+            "error",
+            json!({
+                "ename": "ZeroDivisionError",
+                "evalue": "division by zero",
+                "traceback": [
+                    "\u{1b}[0;31m---------------------------------------------------------------------------\u{1b}[0m",
+                    "\u{1b}[0;31mZeroDivisionError\u{1b}[0m: division by zero"
+                ]
+            }),
+        ));
+        assert_eq!(
+            Some(ExecutionOutput::Error(KernelError {
+                ename: "ZeroDivisionError".to_string(),
+                evalue: "division by zero".to_string(),
+                traceback: vec![
+                    "---------------------------------------------------------------------------"
+                        .to_string(),
+                    "ZeroDivisionError: division by zero".to_string()
+                ],
+                execution_count: None,
+            })),
+            output
+        );
+    }
+
+    #[test]
+    fn test_non_output_messages() {
+        assert_eq!(
+            None,
+            ExecutionOutput::from_message(&iopub("status", json!({"execution_state": "busy"})))
+        );
+        assert_eq!(
+            None,
+            ExecutionOutput::from_message(&iopub(
+                "execute_input",
+                json!({"code": "1", "execution_count": 1})
+            ))
+        );
+    }
+}
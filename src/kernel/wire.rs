@@ -0,0 +1,139 @@
+use super::message::{Channel, Message};
+use crate::error::JupyterRunnerError;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+type Result<T> = std::result::Result<T, JupyterRunnerError>;
+type HmacSha256 = Hmac<Sha256>;
+
+/// Separates the zmq routing identities from the message frames.
+pub const DELIMITER: &[u8] = b"<IDS|MSG>";
+
+/// Signs the header, parent_header, metadata and content frames with the connection file's key.
+/// An empty key disables signing, as in jupyter_client.
+pub struct Signer {
+    key: Vec<u8>,
+}
+
+impl Signer {
+    pub fn new(key: &str, signature_scheme: &str) -> Result<Self> {
+        if !key.is_empty() && signature_scheme != super::SIGNATURE_SCHEME {
+            return Err(JupyterRunnerError::SignatureError(format!(
+                "unsupported signature scheme {signature_scheme}"
+            )));
+        }
+        Ok(Self {
+            key: key.as_bytes().to_vec(),
+        })
+    }
+
+    fn mac(&self, parts: &[Vec<u8>]) -> Option<HmacSha256> {
+        if self.key.is_empty() {
+            return None;
+        }
+        // hmac takes keys of any length.
+        let mut mac = HmacSha256::new_from_slice(&self.key).unwrap();
+        for part in parts {
+            mac.update(part);
+        }
+        Some(mac)
+    }
+
+    pub fn sign(&self, parts: &[Vec<u8>]) -> String {
+        match self.mac(parts) {
+            Some(mac) => hex::encode(mac.finalize().into_bytes()),
+            None => String::new(),
+        }
+    }
+
+    pub fn verify(&self, parts: &[Vec<u8>], signature: &[u8]) -> bool {
+        match (self.mac(parts), hex::decode(signature)) {
+            (None, _) => true,
+            (Some(mac), Ok(signature)) => mac.verify_slice(&signature).is_ok(),
+            (Some(_), Err(_)) => false,
+        }
+    }
+}
+
+/// The multipart frames of `message`: delimiter, signature, header, parent_header, metadata and content.
+pub fn encode(message: &Message, signer: &Signer) -> Result<Vec<Vec<u8>>> {
+    let parts = vec![
+        serde_json::to_vec(&message.header)?,
+        serde_json::to_vec(&message.parent_header)?,
+        serde_json::to_vec(&message.metadata)?,
+        serde_json::to_vec(&message.content)?,
+    ];
+    let mut frames = vec![DELIMITER.to_vec(), signer.sign(&parts).into_bytes()];
+    frames.extend(parts);
+    Ok(frames)
+}
+
+/// Parses the frames received on `channel`, rejecting messages whose signature does not match.
+/// Binary buffers after the content are dropped.
+pub fn decode(frames: Vec<Vec<u8>>, signer: &Signer, channel: Channel) -> Result<Message> {
+    let delimiter = frames
+        .iter()
+        .position(|frame| frame == DELIMITER)
+        .filter(|delimiter| delimiter + 6 <= frames.len())
+        .ok_or_else(|| {
+            JupyterRunnerError::SignatureError(format!("malformed {} message", channel.as_str()))
+        })?;
+    let parts = &frames[delimiter + 2..delimiter + 6];
+    if !signer.verify(parts, &frames[delimiter + 1]) {
+        return Err(JupyterRunnerError::SignatureError(format!(
+            "invalid signature on {}",
+            channel.as_str()
+        )));
+    }
+
+    Ok(Message {
+        header: serde_json::from_slice(&parts[0])?,
+        parent_header: serde_json::from_slice(&parts[1])?,
+        metadata: serde_json::from_slice(&parts[2])?,
+        content: serde_json::from_slice(&parts[3])?,
+        buffers: vec![],
+        channel: Some(channel.as_str().to_string()),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_sign() {
+        let signer = Signer::new("key", "hmac-sha256").unwrap();
+        assert_eq!(
+            "f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8",
+            signer.sign(&[
+                b"The quick brown fox ".to_vec(),
+                b"jumps over the lazy dog".to_vec()
+            ])
+        );
+        assert_eq!("", Signer::new("", "").unwrap().sign(&[b"a".to_vec()]));
+        assert!(Signer::new("key", "hmac-md5").is_err());
+    }
+
+    #[test]
+    fn test_encode_decode() {
+        let signer = Signer::new("secret", "hmac-sha256").unwrap();
+        let message = Message::new_request(
+            "session",
+            Channel::Shell,
+            "execute_request",
+            json!({"code": "1 + 1"}),
+        );
+        let mut frames = encode(&message, &signer).unwrap();
+        frames.insert(0, b"routing-identity".to_vec());
+
+        let decoded = decode(frames.clone(), &signer, Channel::Shell).unwrap();
+        assert_eq!(message.header, decoded.header);
+        assert_eq!(message.content, decoded.content);
+        assert_eq!(Some(Channel::Shell), decoded.channel());
+
+        let last = frames.len() - 1;
+        frames[last] = serde_json::to_vec(&json!({"code": "rm()"})).unwrap();
+        assert!(decode(frames, &signer, Channel::Shell).is_err());
+    }
+}
@@ -0,0 +1,190 @@
+use super::connection::{route_message, ConnectionState};
+use super::connection_file::ConnectionInfo;
+use super::message::{Channel, Message};
+use super::wire::{self, Signer};
+use crate::error::JupyterRunnerError;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
+use std::time::Duration;
+
+type Result<T> = std::result::Result<T, JupyterRunnerError>;
+
+// sent on the wake socket when the sender is dropped, as the thread blocks in poll and would not notice.
+const CLOSE: &[u8] = b"close";
+
+struct Sockets {
+    // sockets must be dropped before their context, which blocks until they are closed.
+    shell: zmq::Socket,
+    iopub: zmq::Socket,
+    stdin: zmq::Socket,
+    control: zmq::Socket,
+    wake: zmq::Socket,
+    _context: zmq::Context,
+}
+
+/// Queues messages for the socket thread and wakes it up to send them.
+pub struct ZmqSender {
+    messages: Sender<Message>,
+    wake: zmq::Socket,
+}
+
+impl ZmqSender {
+    /// Returns false if the thread has stopped.
+    pub fn send(&self, message: Message) -> bool {
+        if self.messages.send(message).is_err() {
+            return false;
+        }
+        // a wake still queued sends this message too, so a failed one does not matter.
+        let _ = self.wake.send("", zmq::DONTWAIT);
+        true
+    }
+}
+
+impl Drop for ZmqSender {
+    fn drop(&mut self) {
+        let _ = self.wake.send(CLOSE, zmq::DONTWAIT);
+    }
+}
+
+fn socket(
+    context: &zmq::Context,
+    socket_type: zmq::SocketType,
+    endpoint: &str,
+) -> Result<zmq::Socket> {
+    let socket = context.socket(socket_type)?;
+    socket.set_linger(0)?;
+    socket.connect(endpoint)?;
+    Ok(socket)
+}
+
+fn dealer(context: &zmq::Context, endpoint: &str, identity: &[u8]) -> Result<zmq::Socket> {
+    let socket = context.socket(zmq::DEALER)?;
+    socket.set_linger(0)?;
+    // the kernel sends input_request to the identity of the shell request, so stdin has to share it.
+    socket.set_identity(identity)?;
+    socket.connect(endpoint)?;
+    Ok(socket)
+}
+
+/// Connects the shell, iopub, stdin and control channels of the kernel and moves them to a thread
+/// that sends the messages queued on the returned sender and routes everything it receives.
+/// The thread blocks until a socket is readable or the sender wakes it over an inproc socket.
+/// zmq connects lazily, so this succeeds even if the kernel is not listening yet.
+pub fn spawn(
+    connection_info: &ConnectionInfo,
+    session: &str,
+    state: ConnectionState,
+) -> Result<ZmqSender> {
+    let signer = Signer::new(&connection_info.key, &connection_info.signature_scheme)?;
+    let context = zmq::Context::new();
+    let endpoint = |channel: Channel| connection_info.endpoint(connection_info.port(channel));
+    let iopub = socket(&context, zmq::SUB, &endpoint(Channel::IOPub))?;
+    iopub.set_subscribe(b"")?;
+    // inproc needs the bind before the connect.
+    let wake_endpoint = format!("inproc://wake-{}", uuid::Uuid::new_v4().simple());
+    let wake = context.socket(zmq::PAIR)?;
+    wake.set_linger(0)?;
+    wake.bind(&wake_endpoint)?;
+    let wake_sender = socket(&context, zmq::PAIR, &wake_endpoint)?;
+    // no high water mark, so that the close is never dropped.
+    wake_sender.set_sndhwm(0)?;
+    let sockets = Sockets {
+        shell: dealer(&context, &endpoint(Channel::Shell), session.as_bytes())?,
+        iopub,
+        stdin: dealer(&context, &endpoint(Channel::Stdin), session.as_bytes())?,
+        control: dealer(&context, &endpoint(Channel::Control), session.as_bytes())?,
+        wake,
+        _context: context,
+    };
+
+    let (sender, receiver) = channel();
+    std::thread::spawn(move || {
+        let _ = run(sockets, signer, receiver, &state);
+        state.closed.store(true, Ordering::SeqCst);
+        // dropping the senders wakes every request still waiting for a reply.
+        state.pending_requests.lock().unwrap().clear();
+    });
+    Ok(ZmqSender {
+        messages: sender,
+        wake: wake_sender,
+    })
+}
+
+fn run(
+    sockets: Sockets,
+    signer: Signer,
+    outgoing: Receiver<Message>,
+    state: &ConnectionState,
+) -> Result<()> {
+    let channels = [
+        (&sockets.shell, Channel::Shell),
+        (&sockets.iopub, Channel::IOPub),
+        (&sockets.stdin, Channel::Stdin),
+        (&sockets.control, Channel::Control),
+    ];
+    loop {
+        loop {
+            let message = match outgoing.try_recv() {
+                Ok(message) => message,
+                Err(TryRecvError::Empty) => break,
+                // the connection has been dropped.
+                Err(TryRecvError::Disconnected) => return Ok(()),
+            };
+            let socket = match message.channel() {
+                Some(Channel::Stdin) => &sockets.stdin,
+                Some(Channel::Control) => &sockets.control,
+                _ => &sockets.shell,
+            };
+            socket.send_multipart(wire::encode(&message, &signer)?, 0)?;
+        }
+
+        let (readable, woken): (Vec<bool>, bool) = {
+            let mut items: Vec<zmq::PollItem> = channels
+                .iter()
+                .map(|(socket, _)| socket.as_poll_item(zmq::POLLIN))
+                .collect();
+            items.push(sockets.wake.as_poll_item(zmq::POLLIN));
+            zmq::poll(&mut items, -1)?;
+            let readable = items.iter().map(|item| item.is_readable()).collect();
+            (readable, items[channels.len()].is_readable())
+        };
+        if woken {
+            // one round sends every queued message, so the wakes are only counted out.
+            loop {
+                match sockets.wake.recv_bytes(zmq::DONTWAIT) {
+                    Ok(wake) if wake == CLOSE => return Ok(()),
+                    Ok(_) => {}
+                    Err(zmq::Error::EAGAIN) => break,
+                    Err(e) => return Err(e.into()),
+                }
+            }
+        }
+        for ((socket, channel), readable) in channels.iter().zip(readable) {
+            if !readable {
+                continue;
+            }
+            let frames = socket.recv_multipart(0)?;
+            // messages with a bad signature are dropped, as the kernel does.
+            if let Ok(message) = wire::decode(frames, &signer, *channel) {
+                route_message(state, message);
+            }
+        }
+    }
+}
+
+/// Sends a ping on the heartbeat channel and waits up to `timeout` for the kernel to echo it.
+/// Blocks the calling thread.
+pub fn heartbeat(connection_info: &ConnectionInfo, timeout: Duration) -> Result<bool> {
+    let context = zmq::Context::new();
+    let socket = socket(
+        &context,
+        zmq::REQ,
+        &connection_info.endpoint(connection_info.hb_port),
+    )?;
+    socket.send("ping", 0)?;
+    if socket.poll(zmq::POLLIN, timeout.as_millis() as i64)? == 0 {
+        return Ok(false);
+    }
+    socket.recv_bytes(0)?;
+    Ok(true)
+}
@@ -0,0 +1,545 @@
+use super::client_cache;
+use super::dotenv;
+use super::error::JupyterRunnerError;
+use super::kernel::message::{self, Channel};
+use super::kernel::{self, KernelError, KernelInfo};
+use super::kernel_monitor;
+use super::local::{self, ConnectionFile, KernelSpec, LaunchOptions, LocalKernel};
+use super::server::KernelModel;
+use super::started_kernels;
+use futures_util::future::join_all;
+use serde_json::json;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::time::{sleep, timeout, Instant};
+
+type Result<T> = std::result::Result<T, JupyterRunnerError>;
+
+const READY_TIMEOUT: Duration = Duration::from_secs(60);
+const READY_RETRY_INTERVAL: Duration = Duration::from_millis(500);
+const KERNEL_INFO_TIMEOUT: Duration = Duration::from_secs(3);
+// how long a local kernel gets to exit after a shutdown_request before it is killed.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(1);
+// shorter, as listing pings every connection file and most stale ones never answer.
+const LIST_HEARTBEAT_TIMEOUT: Duration = Duration::from_millis(300);
+
+// jupyter_client's default when no kernel manager configures another.
+const LOCAL_DEFAULT_KERNEL_NAME: &str = "python3";
+
+/// A kernelspec with the name kernels are started by.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NamedKernelSpec {
+    pub name: String,
+    pub spec: KernelSpec,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct KernelSpecs {
+    /// The kernel started when no name is given, if it is installed.
+    pub default: Option<String>,
+    /// Sorted by name.
+    pub kernel_specs: Vec<NamedKernelSpec>,
+}
+
+impl KernelSpecs {
+    fn new(default: Option<String>, mut kernel_specs: Vec<NamedKernelSpec>) -> Self {
+        kernel_specs.sort_by(|a, b| a.name.cmp(&b.name));
+        let default = default.filter(|default| {
+            kernel_specs
+                .iter()
+                .any(|kernel_spec| &kernel_spec.name == default)
+        });
+        Self {
+            default,
+            kernel_specs,
+        }
+    }
+
+    /// Keeps the kernelspecs of `language`, compared ignoring case as in `language_info.name`.
+    /// The default is kept only if it is one of them.
+    fn filter_language(self, language: &str) -> Self {
+        let kernel_specs = self
+            .kernel_specs
+            .into_iter()
+            .filter(|kernel_spec| kernel_spec.spec.language.eq_ignore_ascii_case(language))
+            .collect();
+        Self::new(self.default, kernel_specs)
+    }
+}
+
+/// The kernelspecs of the server, or of this machine on the `local` endpoint, of `language` if given.
+pub async fn kernel_specs(jupyter_base_url: &str, language: Option<&str>) -> Result<KernelSpecs> {
+    let kernel_specs = if local::is_local_endpoint(jupyter_base_url) {
+        KernelSpecs::new(
+            Some(LOCAL_DEFAULT_KERNEL_NAME.to_string()),
+            local::find_kernel_specs()
+                .into_iter()
+                .map(|kernel_spec| NamedKernelSpec {
+                    name: kernel_spec.name,
+                    spec: kernel_spec.spec,
+                })
+                .collect(),
+        )
+    } else {
+        let server_client = client_cache::server_client(jupyter_base_url)?;
+        let kernel_specs = server_client.get_kernel_specs().await?;
+        KernelSpecs::new(
+            kernel_specs.default,
+            kernel_specs
+                .kernelspecs
+                .into_values()
+                .map(|kernel_spec| NamedKernelSpec {
+                    name: kernel_spec.name,
+                    spec: kernel_spec.spec,
+                })
+                .collect(),
+        )
+    };
+    Ok(match language {
+        Some(language) => kernel_specs.filter_language(language),
+        None => kernel_specs,
+    })
+}
+
+fn local_kernel(kernel_id: &str) -> Result<std::sync::Arc<LocalKernel>> {
+    local::get_kernel(kernel_id)
+        .ok_or_else(|| JupyterRunnerError::KernelNotFound(kernel_id.to_string()))
+}
+
+/// How to start a kernel besides its kernelspec.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct KernelStartOptions {
+    /// The working directory. On a server it is relative to the server's root dir.
+    pub cwd: Option<String>,
+    /// A `.env` file whose variables are set under those of `env`.
+    pub env_file: Option<PathBuf>,
+    pub env: Vec<(String, String)>,
+    /// Code to run once the kernel is ready, keyed by the lowercase language it reports (or the kernel name),
+    /// e.g. `%load_ext autoreload` for `python`.
+    pub startup_code: HashMap<String, String>,
+}
+
+impl KernelStartOptions {
+    fn env(&self) -> Result<Vec<(String, String)>> {
+        let mut env = match &self.env_file {
+            Some(env_file) => dotenv::read_dotenv(env_file)?,
+            None => vec![],
+        };
+        env.extend(self.env.iter().cloned());
+        Ok(env)
+    }
+}
+
+/// A kernel that was started, even if its startup code failed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KernelStart {
+    pub kernel_id: String,
+    /// Why the startup code failed, or the exception it raised.
+    pub startup_error: Option<String>,
+    /// Set when the server is known not to have passed `env` to the kernel.
+    pub env_error: Option<String>,
+}
+
+/// Starts a `kernel_name` kernel and returns its id once its startup code has run. Local kernels are launched
+/// from their kernelspec and only returned once they answer, as there is no server to queue requests until then.
+/// The kernel is tracked as started by the plugin, to be shut down by its policy.
+pub async fn start_kernel(
+    jupyter_base_url: &str,
+    kernel_name: &str,
+    options: &KernelStartOptions,
+) -> Result<KernelStart> {
+    let env = options.env()?;
+    let kernel_id = if local::is_local_endpoint(jupyter_base_url) {
+        let kernel = local::start_kernel(
+            kernel_name,
+            LaunchOptions {
+                cwd: options.cwd.as_ref().map(PathBuf::from),
+                env: env.clone(),
+            },
+        )?;
+        if let Err(e) = wait_until_ready(jupyter_base_url, &kernel.id).await {
+            local::remove_kernel(&kernel.id);
+            return Err(e);
+        }
+        kernel.id.clone()
+    } else {
+        let server_client = client_cache::server_client(jupyter_base_url)?;
+        server_client
+            .start_kernel(kernel_name, options.cwd.as_deref(), &env)
+            .await?
+            .id
+    };
+    started_kernels::track(jupyter_base_url, &kernel_id);
+
+    // the server has the last say on the environment of its kernels, and may leave env out.
+    let env_error = if local::is_local_endpoint(jupyter_base_url) || env.is_empty() {
+        None
+    } else {
+        // a check that could not run tells nothing about the variables.
+        check_env(jupyter_base_url, &kernel_id, &env)
+            .await
+            .ok()
+            .flatten()
+    };
+    let startup_error = match run_startup_code(jupyter_base_url, &kernel_id, options).await {
+        Ok(kernel_error) => kernel_error
+            .map(|kernel_error| format!("{}: {}", kernel_error.ename, kernel_error.evalue)),
+        Err(e) => Some(e.to_string()),
+    };
+    Ok(KernelStart {
+        kernel_id,
+        startup_error,
+        env_error,
+    })
+}
+
+/// Python code raising if one of `env` is not set as given in the kernel.
+fn python_env_check(env: &[(String, String)]) -> String {
+    let expected: serde_json::Map<String, serde_json::Value> = env
+        .iter()
+        .map(|(name, value)| (name.clone(), value.clone().into()))
+        .collect();
+    // a JSON object of strings is a python dict literal as well.
+    format!(
+        "import os as _run_jupyter_os\n\
+         _run_jupyter_missing = [name for name, value in {}.items() if _run_jupyter_os.environ.get(name) != value]\n\
+         del _run_jupyter_os\n\
+         if _run_jupyter_missing:\n    \
+         raise RuntimeError(', '.join(_run_jupyter_missing))",
+        serde_json::Value::Object(expected)
+    )
+}
+
+/// Checks that the server set `env` in the kernel, which can only be done for python kernels.
+/// Returns what is wrong with it, None when it is set or can not be checked.
+async fn check_env(
+    jupyter_base_url: &str,
+    kernel_id: &str,
+    env: &[(String, String)],
+) -> Result<Option<String>> {
+    let kernel_info = wait_until_ready(jupyter_base_url, kernel_id).await?;
+    let language = kernel_info.language_info.name.to_lowercase();
+    if language != "python" {
+        return Ok(None);
+    }
+    let kernel_client = client_cache::kernel_client(jupyter_base_url, kernel_id).await?;
+    let reply = kernel::execute_silently(&kernel_client.connection, &python_env_check(env)).await?;
+    Ok(reply.error.map(|kernel_error| {
+        format!(
+            "the server did not set env variables {}",
+            kernel_error.evalue
+        )
+    }))
+}
+
+/// Runs the startup code of the kernel's language as it is, without the parsing `execution::execute` does, as it may hold
+/// kernel directives such as evcxr's `:dep`. Returns the exception it raised.
+async fn run_startup_code(
+    jupyter_base_url: &str,
+    kernel_id: &str,
+    options: &KernelStartOptions,
+) -> Result<Option<KernelError>> {
+    if options.startup_code.is_empty() {
+        return Ok(None);
+    }
+    let kernel_info = wait_until_ready(jupyter_base_url, kernel_id).await?;
+    let kernel_client = client_cache::kernel_client(jupyter_base_url, kernel_id).await?;
+    let startup_code = match options
+        .startup_code
+        .get(&kernel_info.language_info.name.to_lowercase())
+        .or_else(|| options.startup_code.get(&kernel_client.kernel_name))
+    {
+        Some(startup_code) => startup_code,
+        None => return Ok(None),
+    };
+    let reply = kernel::execute(&kernel_client.connection, startup_code, |_| {}, None).await?;
+    Ok(reply.error)
+}
+
+/// Attaches the kernel of a connection file (a path, file name or kernel id in the runtime dir)
+/// to the `local` endpoint and returns its id once it answers.
+pub async fn attach_kernel(connection_file: &str) -> Result<String> {
+    let connection_file = local::resolve_connection_file(connection_file)?;
+    let kernel = local::attach_kernel(&connection_file)?;
+    if let Err(e) = wait_until_ready(local::LOCAL_ENDPOINT, &kernel.id).await {
+        local::remove_kernel(&kernel.id);
+        return Err(e);
+    }
+    Ok(kernel.id.clone())
+}
+
+/// A running kernel with what is known of its use, to tell kernels of the same name apart.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunningKernel {
+    pub kernel: KernelModel,
+    /// The paths of the sessions bound to the kernel, e.g. the notebooks open in JupyterLab.
+    pub session_paths: Vec<String>,
+    /// Set for the kernels started by the plugin, in ISO 8601 as `last_activity`.
+    pub started_at: Option<String>,
+}
+
+/// The running kernels of the server, or the living ones of the `local` endpoint, sorted by name.
+/// The execution_state of a local kernel is the last one it published, None before it was connected to.
+pub async fn running_kernels(jupyter_base_url: &str) -> Result<Vec<RunningKernel>> {
+    let (kernels, sessions) = if local::is_local_endpoint(jupyter_base_url) {
+        let kernels = local::list_kernels()
+            .iter()
+            .filter(|kernel| kernel.is_alive())
+            .map(|kernel| {
+                let execution_state = client_cache::cached_kernel_client(&kernel.id)
+                    .and_then(|cached| cached.connection.execution_state());
+                local_kernel_model(kernel, execution_state)
+            })
+            .collect();
+        (kernels, vec![])
+    } else {
+        let server_client = client_cache::server_client(jupyter_base_url)?;
+        let kernels = server_client.list_kernels().await?;
+        // only a decoration of the list, which servers without the sessions API still give.
+        let sessions = server_client.list_sessions().await.unwrap_or_default();
+        (kernels, sessions)
+    };
+
+    let mut running_kernels: Vec<RunningKernel> = kernels
+        .into_iter()
+        .map(|kernel| RunningKernel {
+            session_paths: sessions
+                .iter()
+                .filter(|session| {
+                    session
+                        .kernel
+                        .as_ref()
+                        .map(|session_kernel| &session_kernel.id)
+                        == Some(&kernel.id)
+                })
+                .map(|session| session.path.clone())
+                .collect(),
+            started_at: started_kernels::started_at(jupyter_base_url, &kernel.id)
+                .map(message::iso8601),
+            kernel,
+        })
+        .collect();
+    running_kernels
+        .sort_by(|a, b| (&a.kernel.name, &a.kernel.id).cmp(&(&b.kernel.name, &b.kernel.id)));
+    Ok(running_kernels)
+}
+
+/// The connection files in the runtime dir, with whether their kernel answers a heartbeat.
+pub async fn list_connection_files() -> Vec<(ConnectionFile, bool)> {
+    let connection_files = local::connection_files();
+    let heartbeats = join_all(connection_files.iter().map(|connection_file| {
+        let connection_info = connection_file.connection_info.clone();
+        tokio::task::spawn_blocking(move || {
+            kernel::zmq_channels::heartbeat(&connection_info, LIST_HEARTBEAT_TIMEOUT)
+                .unwrap_or(false)
+        })
+    }))
+    .await;
+    connection_files
+        .into_iter()
+        .zip(heartbeats)
+        .map(|(connection_file, alive)| (connection_file, alive.unwrap_or(false)))
+        .collect()
+}
+
+pub async fn interrupt_kernel(jupyter_base_url: &str, kernel_id: &str) -> Result<()> {
+    if local::is_local_endpoint(jupyter_base_url) {
+        let kernel = local_kernel(kernel_id)?;
+        if kernel.interrupts_by_message() {
+            let kernel_client = client_cache::kernel_client(jupyter_base_url, kernel_id).await?;
+            kernel_client
+                .connection
+                .request_reply(Channel::Control, "interrupt_request", json!({}))
+                .await?;
+        } else {
+            kernel.signal_interrupt();
+        }
+        return Ok(());
+    }
+
+    let server_client = client_cache::server_client(jupyter_base_url)?;
+    server_client.interrupt_kernel(kernel_id).await
+}
+
+/// Asks a local kernel to shut down (or restart) over control and waits for its process to exit,
+/// killing it if it does not in time.
+async fn stop_local_kernel(jupyter_base_url: &str, kernel: &LocalKernel, restart: bool) {
+    if kernel.is_alive() {
+        let shutdown_request = async {
+            let kernel_client = client_cache::kernel_client(jupyter_base_url, &kernel.id).await?;
+            kernel_client
+                .connection
+                .request_reply(
+                    Channel::Control,
+                    "shutdown_request",
+                    json!({ "restart": restart }),
+                )
+                .await
+        };
+        let _ = timeout(SHUTDOWN_TIMEOUT, shutdown_request).await;
+
+        let deadline = Instant::now() + SHUTDOWN_TIMEOUT;
+        while !kernel.is_attached() && kernel.is_alive() && Instant::now() < deadline {
+            sleep(READY_RETRY_INTERVAL).await;
+        }
+    }
+    client_cache::invalidate_kernel(&kernel.id);
+    kernel.kill();
+}
+
+pub async fn shutdown_kernel(jupyter_base_url: &str, kernel_id: &str) -> Result<()> {
+    kernel_monitor::unwatch(kernel_id);
+    started_kernels::forget(kernel_id);
+    client_cache::invalidate_kernel(kernel_id);
+    if local::is_local_endpoint(jupyter_base_url) {
+        let kernel = local_kernel(kernel_id)?;
+        stop_local_kernel(jupyter_base_url, &kernel, false).await;
+        local::remove_kernel(kernel_id);
+        return Ok(());
+    }
+
+    let server_client = client_cache::server_client(jupyter_base_url)?;
+    server_client.delete_kernel(kernel_id).await
+}
+
+/// The kernel as the server reports it. For local kernels, the state is `dead` once the process
+/// exits or stops answering heartbeats, otherwise the last published execution_state.
+pub async fn kernel_model(jupyter_base_url: &str, kernel_id: &str) -> Result<KernelModel> {
+    if local::is_local_endpoint(jupyter_base_url) {
+        let kernel = local_kernel(kernel_id)?;
+        let connection_info = kernel.connection_info.clone();
+        let beating = kernel.is_alive()
+            && tokio::task::spawn_blocking(move || {
+                kernel::zmq_channels::heartbeat(&connection_info, HEARTBEAT_TIMEOUT)
+            })
+            .await
+            .map_err(|e| JupyterRunnerError::KernelNotReady(e.to_string()))??;
+        let execution_state = if beating {
+            let kernel_client = client_cache::kernel_client(jupyter_base_url, kernel_id).await?;
+            kernel_client
+                .connection
+                .execution_state()
+                .unwrap_or_else(|| "idle".to_string())
+        } else {
+            "dead".to_string()
+        };
+        return Ok(local_kernel_model(&kernel, Some(execution_state)));
+    }
+
+    let server_client = client_cache::server_client(jupyter_base_url)?;
+    server_client
+        .get_kernel(kernel_id)
+        .await?
+        .ok_or_else(|| JupyterRunnerError::KernelNotFound(kernel_id.to_string()))
+}
+
+fn local_kernel_model(kernel: &LocalKernel, execution_state: Option<String>) -> KernelModel {
+    KernelModel {
+        id: kernel.id.clone(),
+        name: kernel.kernel_name.clone(),
+        last_activity: None,
+        execution_state,
+        connections: None,
+    }
+}
+
+/// Waits until the kernel answers a kernel_info_request, reconnecting between attempts.
+pub async fn wait_until_ready(jupyter_base_url: &str, kernel_id: &str) -> Result<KernelInfo> {
+    let deadline = Instant::now() + READY_TIMEOUT;
+    loop {
+        let attempt = async {
+            let kernel_client = client_cache::kernel_client(jupyter_base_url, kernel_id).await?;
+            timeout(KERNEL_INFO_TIMEOUT, kernel_client.kernel_info())
+                .await
+                .map_err(|_| JupyterRunnerError::KernelNotReady(kernel_id.to_string()))?
+        };
+
+        match attempt.await {
+            Ok(kernel_info) => return Ok(kernel_info),
+            Err(e) => {
+                client_cache::invalidate_kernel(kernel_id);
+                if Instant::now() + READY_RETRY_INTERVAL >= deadline {
+                    return Err(e);
+                }
+                sleep(READY_RETRY_INTERVAL).await;
+            }
+        }
+    }
+}
+
+/// Restarts the kernel in place and returns once it is ready again, so a buffer bound to the id can keep using it.
+pub async fn restart_kernel(
+    jupyter_base_url: &str,
+    kernel_id: &str,
+) -> Result<(KernelModel, KernelInfo)> {
+    if local::is_local_endpoint(jupyter_base_url) {
+        let kernel = local_kernel(kernel_id)?;
+        stop_local_kernel(jupyter_base_url, &kernel, true).await;
+        kernel.respawn()?;
+        let kernel_info = wait_until_ready(jupyter_base_url, kernel_id).await?;
+        return Ok((
+            local_kernel_model(&kernel, Some("idle".to_string())),
+            kernel_info,
+        ));
+    }
+
+    let server_client = client_cache::server_client(jupyter_base_url)?;
+    client_cache::invalidate_kernel(kernel_id);
+    let kernel = server_client.restart_kernel(kernel_id).await?;
+    let kernel_info = wait_until_ready(jupyter_base_url, kernel_id).await?;
+    Ok((kernel, kernel_info))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn named_kernel_spec(name: &str, language: &str) -> NamedKernelSpec {
+        NamedKernelSpec {
+            name: name.to_string(),
+            spec: serde_json::from_value(json!({
+                "argv": [],
+                "display_name": name,
+                "language": language,
+            }))
+            .unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_filter_kernel_specs_by_language() {
+        let kernel_specs = KernelSpecs::new(
+            Some("python3".to_string()),
+            vec![
+                named_kernel_spec("rust", "Rust"),
+                named_kernel_spec("python3", "python"),
+                named_kernel_spec("analytics", "python"),
+            ],
+        );
+        assert_eq!(Some("python3".to_string()), kernel_specs.default);
+
+        let python = kernel_specs.clone().filter_language("Python");
+        assert_eq!(Some("python3".to_string()), python.default);
+        assert_eq!(
+            vec!["analytics", "python3"],
+            python
+                .kernel_specs
+                .iter()
+                .map(|kernel_spec| kernel_spec.name.as_str())
+                .collect::<Vec<&str>>()
+        );
+
+        let rust = kernel_specs.filter_language("rust");
+        assert_eq!(None, rust.default);
+        assert_eq!(1, rust.kernel_specs.len());
+    }
+
+    #[test]
+    fn test_python_env_check() {
+        let code = python_env_check(&[("API_TOKEN".to_string(), "a\"b".to_string())]);
+        assert!(code.contains(r#"for name, value in {"API_TOKEN":"a\"b"}.items()"#));
+        assert!(code.ends_with("\n    raise RuntimeError(', '.join(_run_jupyter_missing))"));
+    }
+}
@@ -0,0 +1,290 @@
+use super::client_cache;
+use super::error::JupyterRunnerError;
+use super::kernel_manager;
+use super::runtime::runtime;
+use futures_util::future::join_all;
+use once_cell::sync::Lazy;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, timeout};
+
+// a server slower than this to report a kernel counts as unreachable for the round.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KernelHealthEvent {
+    /// The kernel is gone, e.g. its process exited or the server was restarted. It is no longer watched.
+    Died,
+    /// The server brought the kernel back after it died. Its variables are lost.
+    Restarted,
+    /// The connection was closed while the kernel lived on, and has been made again.
+    Reconnected,
+    /// The server did not answer. Reported once, until it answers again.
+    Unreachable,
+    /// A kernel the plugin started was shut down after being idle for too long.
+    Culled,
+}
+
+impl KernelHealthEvent {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Died => "died",
+            Self::Restarted => "restarted",
+            Self::Reconnected => "reconnected",
+            Self::Unreachable => "unreachable",
+            Self::Culled => "culled",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct KernelEvent {
+    pub jupyter_base_url: String,
+    pub kernel_id: String,
+    pub event: KernelHealthEvent,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MonitorOptions {
+    pub enabled: bool,
+    pub interval: Duration,
+}
+
+impl Default for MonitorOptions {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            interval: Duration::from_secs(5),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Watched {
+    /// The restarts of the connection already reported.
+    restarts: usize,
+    unreachable: bool,
+}
+
+/// What a round found out about one kernel.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Health {
+    Alive { restarts: usize, reconnected: bool },
+    Dead,
+    Unreachable,
+}
+
+/// The jupyter_base_url and id of a kernel.
+type KernelKey = (String, String);
+
+static WATCHED: Lazy<Mutex<HashMap<KernelKey, Watched>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+static MONITOR_OPTIONS: Lazy<Mutex<MonitorOptions>> =
+    Lazy::new(|| Mutex::new(MonitorOptions::default()));
+
+static MONITOR_TASK: Lazy<Mutex<Option<JoinHandle<()>>>> = Lazy::new(|| Mutex::new(None));
+
+static EVENTS: Lazy<Mutex<VecDeque<KernelEvent>>> = Lazy::new(|| Mutex::new(VecDeque::new()));
+
+/// Starts checking on the kernel, every kernel a client is made for being one in use.
+pub fn watch(jupyter_base_url: &str, kernel_id: &str) {
+    let key = (jupyter_base_url.to_string(), kernel_id.to_string());
+    WATCHED.lock().unwrap().entry(key).or_default();
+    ensure_running();
+}
+
+pub fn unwatch(kernel_id: &str) {
+    WATCHED.lock().unwrap().retain(|(_, id), _| id != kernel_id);
+}
+
+/// Applies the options from the next round on. Disabling stops the monitor, which keeps the kernels it watches.
+pub fn configure(options: MonitorOptions) {
+    *MONITOR_OPTIONS.lock().unwrap() = options;
+    if options.enabled {
+        ensure_running();
+    } else if let Some(task) = MONITOR_TASK.lock().unwrap().take() {
+        task.abort();
+    }
+}
+
+pub fn monitor_options() -> MonitorOptions {
+    *MONITOR_OPTIONS.lock().unwrap()
+}
+
+fn ensure_running() {
+    if !MONITOR_OPTIONS.lock().unwrap().enabled {
+        return;
+    }
+    let mut task = MONITOR_TASK.lock().unwrap();
+    if !matches!(task.as_ref(), Some(task) if !task.is_finished()) {
+        if let Ok(runtime) = runtime() {
+            *task = Some(runtime.spawn(monitor()));
+        }
+    }
+}
+
+async fn monitor() {
+    loop {
+        let interval = MONITOR_OPTIONS.lock().unwrap().interval;
+        sleep(interval).await;
+        let watched: Vec<KernelKey> = WATCHED.lock().unwrap().keys().cloned().collect();
+        let healths = join_all(
+            watched
+                .iter()
+                .map(|(jupyter_base_url, kernel_id)| check_health(jupyter_base_url, kernel_id)),
+        )
+        .await;
+        for (key, health) in watched.into_iter().zip(healths) {
+            record_health(key, health);
+        }
+    }
+}
+
+/// Asks the server (or the heartbeat channel, for local kernels) for the kernel's state,
+/// and makes its connection again if it was closed.
+async fn check_health(jupyter_base_url: &str, kernel_id: &str) -> Health {
+    let kernel_model = match timeout(
+        CHECK_TIMEOUT,
+        kernel_manager::kernel_model(jupyter_base_url, kernel_id),
+    )
+    .await
+    {
+        Ok(Ok(kernel_model)) => kernel_model,
+        Ok(Err(JupyterRunnerError::KernelNotFound(_))) => return Health::Dead,
+        Ok(Err(_)) | Err(_) => return Health::Unreachable,
+    };
+    if kernel_model.execution_state.as_deref() == Some("dead") {
+        return Health::Dead;
+    }
+
+    // a client dropped from the cache is made again on its next use, so only closed ones are reconnected.
+    match client_cache::cached_kernel_client(kernel_id) {
+        Some(cached) if cached.connection.is_closed() => {
+            match client_cache::kernel_client(jupyter_base_url, kernel_id).await {
+                Ok(_) => Health::Alive {
+                    restarts: 0,
+                    reconnected: true,
+                },
+                Err(JupyterRunnerError::KernelNotFound(_)) => Health::Dead,
+                Err(_) => Health::Unreachable,
+            }
+        }
+        Some(cached) => Health::Alive {
+            restarts: cached.connection.restarts(),
+            reconnected: false,
+        },
+        None => Health::Alive {
+            restarts: 0,
+            reconnected: false,
+        },
+    }
+}
+
+fn record_health(key: KernelKey, health: Health) {
+    let mut watched_kernels = WATCHED.lock().unwrap();
+    // unwatched while the round ran
+    let watched = match watched_kernels.get_mut(&key) {
+        Some(watched) => watched,
+        None => return,
+    };
+    let events = health_events(watched, health);
+    if health == Health::Dead {
+        watched_kernels.remove(&key);
+        client_cache::invalidate_kernel(&key.1);
+    }
+    drop(watched_kernels);
+
+    for event in events {
+        push_event(&key.0, &key.1, event);
+    }
+}
+
+/// Queues an event for Lua, also for what other parts of the plugin do to kernels.
+pub fn push_event(jupyter_base_url: &str, kernel_id: &str, event: KernelHealthEvent) {
+    EVENTS.lock().unwrap().push_back(KernelEvent {
+        jupyter_base_url: jupyter_base_url.to_string(),
+        kernel_id: kernel_id.to_string(),
+        event,
+    });
+}
+
+/// The events to report for `health`, updating what has been reported of the kernel.
+fn health_events(watched: &mut Watched, health: Health) -> Vec<KernelHealthEvent> {
+    let mut events = vec![];
+    match health {
+        Health::Dead => events.push(KernelHealthEvent::Died),
+        Health::Unreachable => {
+            if !watched.unreachable {
+                events.push(KernelHealthEvent::Unreachable);
+            }
+            watched.unreachable = true;
+        }
+        Health::Alive {
+            restarts,
+            reconnected,
+        } => {
+            if reconnected || watched.unreachable {
+                events.push(KernelHealthEvent::Reconnected);
+            }
+            // a new connection counts its restarts from 0 again.
+            if restarts > watched.restarts {
+                events.push(KernelHealthEvent::Restarted);
+            }
+            watched.restarts = restarts;
+            watched.unreachable = false;
+        }
+    }
+    events
+}
+
+pub fn drain_events() -> Vec<KernelEvent> {
+    EVENTS.lock().unwrap().drain(..).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_health_events() {
+        let mut watched = Watched::default();
+        let alive = |restarts| Health::Alive {
+            restarts,
+            reconnected: false,
+        };
+
+        assert!(health_events(&mut watched, alive(0)).is_empty());
+        assert_eq!(
+            vec![KernelHealthEvent::Restarted],
+            health_events(&mut watched, alive(1))
+        );
+        assert!(health_events(&mut watched, alive(1)).is_empty());
+
+        assert_eq!(
+            vec![KernelHealthEvent::Unreachable],
+            health_events(&mut watched, Health::Unreachable)
+        );
+        assert!(health_events(&mut watched, Health::Unreachable).is_empty());
+        assert_eq!(
+            vec![KernelHealthEvent::Reconnected],
+            health_events(
+                &mut watched,
+                Health::Alive {
+                    restarts: 0,
+                    reconnected: true,
+                }
+            )
+        );
+        assert_eq!(
+            vec![KernelHealthEvent::Restarted],
+            health_events(&mut watched, alive(1))
+        );
+
+        assert_eq!(
+            vec![KernelHealthEvent::Died],
+            health_events(&mut watched, Health::Dead)
+        );
+    }
+}
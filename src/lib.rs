@@ -1,4 +1,17 @@
+mod client_cache;
+mod dotenv;
+mod error;
+mod execution;
+mod jupyter_paths;
+mod kernel;
+mod kernel_manager;
+mod kernel_monitor;
+mod local;
 mod lua_entrypoint;
 mod parser;
+mod runtime;
+mod server;
+mod started_kernels;
+mod statement_range;
 
 pub use lua_entrypoint::*;
@@ -0,0 +1,169 @@
+use crate::error::JupyterRunnerError;
+use crate::jupyter_paths;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+type Result<T> = std::result::Result<T, JupyterRunnerError>;
+
+/// The `kernel.json` of a kernelspec directory.
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct KernelSpec {
+    /// May contain `{connection_file}` and `{resource_dir}` placeholders.
+    pub argv: Vec<String>,
+    pub display_name: String,
+    #[serde(default)]
+    pub language: String,
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    /// `signal` (the default) or `message`.
+    #[serde(default)]
+    pub interrupt_mode: Option<String>,
+    #[serde(default)]
+    pub metadata: Map<String, Value>,
+}
+
+/// A kernelspec found on this machine. `name` is its directory name.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LocalKernelSpec {
+    pub name: String,
+    pub resource_dir: PathBuf,
+    pub spec: KernelSpec,
+}
+
+impl LocalKernelSpec {
+    pub fn interrupts_by_message(&self) -> bool {
+        self.spec.interrupt_mode.as_deref() == Some("message")
+    }
+
+    /// The argv with its placeholders filled in.
+    pub fn command(&self, connection_file: &Path) -> Vec<String> {
+        self.spec
+            .argv
+            .iter()
+            .map(|arg| self.format(arg, connection_file))
+            .collect()
+    }
+
+    pub fn env(&self, connection_file: &Path) -> Vec<(String, String)> {
+        self.spec
+            .env
+            .iter()
+            .map(|(key, value)| (key.clone(), self.format(value, connection_file)))
+            .collect()
+    }
+
+    fn format(&self, value: &str, connection_file: &Path) -> String {
+        value
+            .replace("{connection_file}", &connection_file.to_string_lossy())
+            .replace("{resource_dir}", &self.resource_dir.to_string_lossy())
+    }
+}
+
+/// The kernelspecs in the `kernels` dir of each of `data_dirs`. A name found in an earlier dir wins.
+pub fn find_kernel_specs_in(data_dirs: &[PathBuf]) -> Vec<LocalKernelSpec> {
+    let mut kernel_specs: Vec<LocalKernelSpec> = vec![];
+    for data_dir in data_dirs {
+        let entries = match std::fs::read_dir(data_dir.join("kernels")) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        for resource_dir in entries.filter_map(|entry| entry.ok().map(|entry| entry.path())) {
+            let name = match resource_dir.file_name().and_then(|name| name.to_str()) {
+                Some(name) => name.to_lowercase(),
+                None => continue,
+            };
+            if kernel_specs.iter().any(|each| each.name == name) {
+                continue;
+            }
+            let spec = match std::fs::read_to_string(resource_dir.join("kernel.json"))
+                .ok()
+                .and_then(|content| serde_json::from_str::<KernelSpec>(&content).ok())
+            {
+                Some(spec) => spec,
+                None => continue,
+            };
+            kernel_specs.push(LocalKernelSpec {
+                name,
+                resource_dir,
+                spec,
+            });
+        }
+    }
+    kernel_specs.sort_by(|a, b| a.name.cmp(&b.name));
+    kernel_specs
+}
+
+pub fn find_kernel_specs() -> Vec<LocalKernelSpec> {
+    find_kernel_specs_in(&jupyter_paths::data_dirs())
+}
+
+pub fn find_kernel_spec(kernel_name: &str) -> Result<LocalKernelSpec> {
+    find_kernel_specs()
+        .into_iter()
+        .find(|kernel_spec| kernel_spec.name == kernel_name.to_lowercase())
+        .ok_or_else(|| JupyterRunnerError::KernelSpecNotFound(kernel_name.to_string()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const EVCXR_KERNEL_JSON: &str = r#"{
+        "argv": ["/home/me/.cargo/bin/evcxr_jupyter", "--control_file", "{connection_file}"],
+        "display_name": "Rust",
+        "language": "rust",
+        "interrupt_mode": "message"
+    }"#;
+
+    #[test]
+    fn test_command() {
+        let kernel_spec = LocalKernelSpec {
+            name: "rust".to_string(),
+            resource_dir: PathBuf::from("/home/me/.local/share/jupyter/kernels/rust"),
+            spec: serde_json::from_str(EVCXR_KERNEL_JSON).unwrap(),
+        };
+        assert!(kernel_spec.interrupts_by_message());
+        assert_eq!(
+            vec![
+                "/home/me/.cargo/bin/evcxr_jupyter",
+                "--control_file",
+                "/tmp/kernel-1.json"
+            ],
+            kernel_spec.command(Path::new("/tmp/kernel-1.json"))
+        );
+    }
+
+    #[test]
+    fn test_find_kernel_specs_in() {
+        let root = std::env::temp_dir().join(format!("run-jupyter-specs-{}", std::process::id()));
+        let user_dir = root.join("user");
+        let system_dir = root.join("system");
+        for (data_dir, name, display_name) in [
+            (&user_dir, "Rust", "Rust (user)"),
+            (&system_dir, "rust", "Rust (system)"),
+            (&system_dir, "python3", "Python 3"),
+        ] {
+            let resource_dir = data_dir.join("kernels").join(name);
+            std::fs::create_dir_all(&resource_dir).unwrap();
+            std::fs::write(
+                resource_dir.join("kernel.json"),
+                format!(
+                    r#"{{"argv": ["k", "{{connection_file}}"], "display_name": "{display_name}"}}"#
+                ),
+            )
+            .unwrap();
+        }
+        let kernel_specs = find_kernel_specs_in(&[user_dir, system_dir]);
+        std::fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(
+            vec![("python3", "Python 3"), ("rust", "Rust (user)")],
+            kernel_specs
+                .iter()
+                .map(|each| (each.name.as_str(), each.spec.display_name.as_str()))
+                .collect::<Vec<_>>()
+        );
+    }
+}
@@ -0,0 +1,294 @@
+pub mod kernelspec;
+
+pub use kernelspec::*;
+
+use crate::error::JupyterRunnerError;
+use crate::jupyter_paths;
+use crate::kernel::ConnectionInfo;
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, Mutex};
+
+type Result<T> = std::result::Result<T, JupyterRunnerError>;
+
+/// The endpoint that selects the direct backend: kernels launched from their kernelspec
+/// and driven over zmq, without a Jupyter server.
+pub const LOCAL_ENDPOINT: &str = "local";
+
+pub fn is_local_endpoint(jupyter_base_url: &str) -> bool {
+    jupyter_base_url == LOCAL_ENDPOINT
+}
+
+const CONNECTION_FILE_PREFIX: &str = "kernel-";
+
+/// How a kernel process is launched besides its kernelspec.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LaunchOptions {
+    /// None keeps the working directory of neovim.
+    pub cwd: Option<PathBuf>,
+    /// Set over the kernelspec's env.
+    pub env: Vec<(String, String)>,
+}
+
+/// A kernel launched by this plugin, or one started elsewhere and attached through its connection file.
+pub struct LocalKernel {
+    pub id: String,
+    pub kernel_name: String,
+    /// None if the kernel was attached and its kernelspec is not installed here.
+    pub kernel_spec: Option<LocalKernelSpec>,
+    pub connection_file: PathBuf,
+    pub connection_info: ConnectionInfo,
+    /// Kept to launch the kernel the same way on a restart.
+    launch_options: LaunchOptions,
+    /// None for attached kernels, whose process belongs to someone else.
+    process: Mutex<Option<Child>>,
+}
+
+/// A `kernel-*.json` file in the runtime dir, written by whoever launched the kernel.
+pub struct ConnectionFile {
+    pub path: PathBuf,
+    pub kernel_id: String,
+    pub connection_info: ConnectionInfo,
+}
+
+static LOCAL_KERNELS: Lazy<Mutex<HashMap<String, Arc<LocalKernel>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+fn spawn_process(
+    kernel_spec: &LocalKernelSpec,
+    connection_file: &Path,
+    launch_options: &LaunchOptions,
+) -> Result<Child> {
+    let command = kernel_spec.command(connection_file);
+    let (program, args) = command.split_first().ok_or_else(|| {
+        JupyterRunnerError::KernelStartFailed(format!("{} has no argv", kernel_spec.name))
+    })?;
+    let mut command = Command::new(program);
+    if let Some(cwd) = &launch_options.cwd {
+        command.current_dir(cwd);
+    }
+    command
+        .args(args)
+        .envs(kernel_spec.env(connection_file))
+        .envs(launch_options.env.iter().cloned())
+        // ipykernel exits by itself when this process is gone.
+        .env("JPY_PARENT_PID", std::process::id().to_string())
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| JupyterRunnerError::KernelStartFailed(format!("{program} :{e}")))
+}
+
+/// Writes a connection file for the `kernel_name` kernelspec and launches the kernel with it.
+/// The kernel may not be listening yet when this returns.
+pub fn start_kernel(kernel_name: &str, launch_options: LaunchOptions) -> Result<Arc<LocalKernel>> {
+    let kernel_spec = find_kernel_spec(kernel_name)?;
+    let id = uuid::Uuid::new_v4().to_string();
+    let runtime_dir = jupyter_paths::runtime_dir().unwrap_or_else(std::env::temp_dir);
+    let connection_file = runtime_dir.join(format!("kernel-{id}.json"));
+    let connection_info = ConnectionInfo::allocate(&kernel_spec.name)?;
+    connection_info.write(&connection_file)?;
+
+    let process = match spawn_process(&kernel_spec, &connection_file, &launch_options) {
+        Ok(process) => process,
+        Err(e) => {
+            let _ = std::fs::remove_file(&connection_file);
+            return Err(e);
+        }
+    };
+    let kernel = Arc::new(LocalKernel {
+        id,
+        kernel_name: kernel_spec.name.clone(),
+        kernel_spec: Some(kernel_spec),
+        connection_file,
+        connection_info,
+        launch_options,
+        process: Mutex::new(Some(process)),
+    });
+    LOCAL_KERNELS
+        .lock()
+        .unwrap()
+        .insert(kernel.id.clone(), kernel.clone());
+    Ok(kernel)
+}
+
+/// `kernel-<id>.json` gives `<id>`, the id jupyter_client gave the kernel.
+fn kernel_id_of(connection_file: &Path) -> Option<String> {
+    connection_file
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .and_then(|stem| stem.strip_prefix(CONNECTION_FILE_PREFIX))
+        .filter(|kernel_id| !kernel_id.is_empty())
+        .map(|kernel_id| kernel_id.to_string())
+}
+
+/// Finds the connection file the way `jupyter console --existing` does: a path,
+/// or a file name or kernel id in the runtime dir.
+pub fn resolve_connection_file(connection_file: &str) -> Result<PathBuf> {
+    let path = PathBuf::from(connection_file);
+    if path.is_file() {
+        return Ok(path);
+    }
+    if let Some(runtime_dir) = jupyter_paths::runtime_dir() {
+        for candidate in [
+            runtime_dir.join(connection_file),
+            runtime_dir.join(format!("{CONNECTION_FILE_PREFIX}{connection_file}.json")),
+        ] {
+            if candidate.is_file() {
+                return Ok(candidate);
+            }
+        }
+    }
+    Err(JupyterRunnerError::KernelNotFound(
+        connection_file.to_string(),
+    ))
+}
+
+/// Registers the kernel of `connection_file` so that it can be used like one launched here.
+/// Attaching the same file twice returns the kernel registered first.
+pub fn attach_kernel(connection_file: &Path) -> Result<Arc<LocalKernel>> {
+    if let Some(kernel) = list_kernels()
+        .into_iter()
+        .find(|kernel| kernel.connection_file == connection_file)
+    {
+        return Ok(kernel);
+    }
+
+    let connection_info = ConnectionInfo::read(connection_file)?;
+    let id = kernel_id_of(connection_file).unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+    let kernel_spec = find_kernel_spec(&connection_info.kernel_name).ok();
+    let kernel = Arc::new(LocalKernel {
+        id,
+        kernel_name: connection_info.kernel_name.clone(),
+        kernel_spec,
+        connection_file: connection_file.to_path_buf(),
+        connection_info,
+        launch_options: LaunchOptions::default(),
+        process: Mutex::new(None),
+    });
+    LOCAL_KERNELS
+        .lock()
+        .unwrap()
+        .insert(kernel.id.clone(), kernel.clone());
+    Ok(kernel)
+}
+
+/// The readable connection files in the runtime dir, including those of kernels that have exited
+/// without removing theirs.
+pub fn connection_files() -> Vec<ConnectionFile> {
+    let runtime_dir = match jupyter_paths::runtime_dir() {
+        Some(runtime_dir) => runtime_dir,
+        None => return vec![],
+    };
+    jupyter_paths::runtime_files(&runtime_dir, &[CONNECTION_FILE_PREFIX])
+        .into_iter()
+        .filter_map(|path| {
+            let connection_info = ConnectionInfo::read(&path).ok()?;
+            let kernel_id = kernel_id_of(&path)?;
+            Some(ConnectionFile {
+                path,
+                kernel_id,
+                connection_info,
+            })
+        })
+        .collect()
+}
+
+pub fn get_kernel(kernel_id: &str) -> Option<Arc<LocalKernel>> {
+    LOCAL_KERNELS.lock().unwrap().get(kernel_id).cloned()
+}
+
+pub fn list_kernels() -> Vec<Arc<LocalKernel>> {
+    let mut kernels: Vec<Arc<LocalKernel>> =
+        LOCAL_KERNELS.lock().unwrap().values().cloned().collect();
+    kernels.sort_by(|a, b| a.id.cmp(&b.id));
+    kernels
+}
+
+/// Unregisters the kernel. A kernel launched here is killed if it is still running
+/// and its connection file removed. Attached kernels are left to their owner.
+pub fn remove_kernel(kernel_id: &str) -> Option<Arc<LocalKernel>> {
+    let kernel = LOCAL_KERNELS.lock().unwrap().remove(kernel_id)?;
+    if !kernel.is_attached() {
+        kernel.kill();
+        let _ = std::fs::remove_file(&kernel.connection_file);
+    }
+    Some(kernel)
+}
+
+impl LocalKernel {
+    pub fn is_attached(&self) -> bool {
+        self.process.lock().unwrap().is_none()
+    }
+
+    /// Attached kernels can only be told to stop by message, and are assumed alive until they stop answering.
+    pub fn is_alive(&self) -> bool {
+        match self.process.lock().unwrap().as_mut() {
+            Some(process) => matches!(process.try_wait(), Ok(None)),
+            None => true,
+        }
+    }
+
+    /// Whether interrupt_request has to be sent instead of SIGINT: the kernelspec's interrupt_mode is
+    /// `message`, or there is no process to signal.
+    pub fn interrupts_by_message(&self) -> bool {
+        match (&self.kernel_spec, self.is_attached()) {
+            (_, true) | (None, _) => true,
+            (Some(kernel_spec), false) => kernel_spec.interrupts_by_message(),
+        }
+    }
+
+    pub fn signal_interrupt(&self) {
+        #[cfg(unix)]
+        if let Some(process) = self.process.lock().unwrap().as_ref() {
+            unsafe {
+                libc::kill(process.id() as libc::pid_t, libc::SIGINT);
+            }
+        }
+    }
+
+    pub fn kill(&self) {
+        if let Some(process) = self.process.lock().unwrap().as_mut() {
+            let _ = process.kill();
+            let _ = process.wait();
+        }
+    }
+
+    /// Launches a new process on the same connection file, so the kernel keeps its id and ports.
+    pub fn respawn(&self) -> Result<()> {
+        let kernel_spec = match (&self.kernel_spec, self.is_attached()) {
+            (Some(kernel_spec), false) => kernel_spec,
+            _ => return Err(JupyterRunnerError::KernelNotOwned(self.id.clone())),
+        };
+        self.kill();
+        *self.process.lock().unwrap() = Some(spawn_process(
+            kernel_spec,
+            &self.connection_file,
+            &self.launch_options,
+        )?);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_kernel_id_of() {
+        assert_eq!(
+            Some("8a5b6c1e-2d3f-4a5b-9c8d-7e6f5a4b3c2d".to_string()),
+            kernel_id_of(Path::new(
+                "/run/user/1000/jupyter/kernel-8a5b6c1e-2d3f-4a5b-9c8d-7e6f5a4b3c2d.json"
+            ))
+        );
+        assert_eq!(
+            Some("12345".to_string()),
+            kernel_id_of(Path::new("kernel-12345.json"))
+        );
+        assert_eq!(None, kernel_id_of(Path::new("/tmp/connection.json")));
+    }
+}
@@ -1,25 +1,34 @@
-use super::parser::*;
-use jupyter_client::*;
+use super::client_cache;
+use super::error::*;
+use super::execution::{self, ExecutionEvent, ExecutionId, ExecutionOptions, TimeoutOptions};
+use super::kernel::ansi::{AnsiColor, StyledText};
+use super::kernel::{
+    self, Completion, ExecutionOutput, HistoryAccess, HistoryEntry, HistoryRequest, Inspection,
+    KernelError, KernelInfo, MimeBundle,
+};
+use super::kernel_manager::{self, KernelStartOptions, NamedKernelSpec};
+use super::kernel_monitor;
+use super::local;
+use super::runtime::block_on;
+use super::server::{
+    self, Credentials, KernelModel, ServerInfo, ServerOptions, ServerStartEvent, SessionKernel,
+    SessionModel, SpawnedServer,
+};
+use super::started_kernels::{self, ShutdownPolicy};
+use super::statement_range;
 use mlua::prelude::*;
-use thiserror::Error;
-use tokio::runtime::Runtime; // 0.3.5
-
-#[derive(Error, Debug)]
-pub enum JupyterRunnerError {
-    #[error("kernel not found {0}")]
-    KernelNotFound(String),
-
-    #[error("api error :{0}")]
-    JupyterApiError(#[from] JupyterApiError),
-
-    #[error("parse error :{0}")]
-    ParserError(#[from] ParserError),
-}
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::time::timeout;
 
 const RESEPONSE_TABLE_KEY_ERROR: &str = "error";
 const RESEPONSE_TABLE_KEY_DATA: &str = "data";
-const RESEPONSE_TABLE_KEY_PNG: &str = "png";
-const RESEPONSE_TABLE_KEY_TEXT: &str = "text";
+const RESEPONSE_TABLE_KEY_STATE: &str = "state";
+const RESEPONSE_TABLE_KEY_KERNEL_ERROR: &str = "kernel_error";
+
+// completion, inspection and history wait at most this long, e.g. while the kernel is busy executing.
+const INTERACTIVE_REQUEST_TIMEOUT: Duration = Duration::from_secs(3);
 
 fn to_error_table(lua: &Lua, e: JupyterRunnerError) -> LuaResult<LuaTable<'_>> {
     let response_table = lua.create_table()?;
@@ -32,130 +41,810 @@ fn empty_table(lua: &Lua) -> LuaResult<LuaTable<'_>> {
     Ok(response_table)
 }
 
-fn start_kernel(
+/// Sets the token and/or password of the server. Without a token, the one in the url or `$JUPYTER_TOKEN` is used.
+fn set_credentials(
     lua: &Lua,
-    (jupyter_base_url, kernel_name): (String, String),
+    (jupyter_base_url, token, password): (String, Option<String>, Option<String>),
 ) -> LuaResult<LuaTable<'_>> {
-    match get_jupyter_client(&jupyter_base_url) {
-        Err(e) => Ok(to_error_table(&lua, e)?),
-        Ok(jupyter_client) => {
-            let kernel_req = KernelPostRequest {
-                name: kernel_name,
-                path: None,
-            };
+    client_cache::set_credentials(&jupyter_base_url, Credentials { token, password });
+    empty_table(lua)
+}
 
-            match Runtime::new()
-                .unwrap()
-                .block_on(jupyter_client.start_kernel(kernel_req))
-            {
-                Ok(kernel) => {
-                    let response_table = lua.create_table()?;
-                    response_table.set(RESEPONSE_TABLE_KEY_DATA, kernel.id)?;
-                    Ok(response_table)
-                }
-                Err(e) => Ok(to_error_table(&lua, e.into())?),
+fn server_info_table<'lua>(lua: &'lua Lua, server: &ServerInfo) -> LuaResult<LuaTable<'lua>> {
+    let server_table = lua.create_table()?;
+    server_table.set("url", server.url.as_str())?;
+    server_table.set("token", server.token.as_str())?;
+    server_table.set("root_dir", server.root_dir.as_str())?;
+    server_table.set("pid", server.pid)?;
+    server_table.set("port", server.port)?;
+    server_table.set("base_url", server.base_url.as_str())?;
+    server_table.set("secure", server.secure)?;
+    server_table.set("password", server.password)?;
+    Ok(server_table)
+}
+
+/// The local servers Jupyter has registered in its runtime dirs and that are still running.
+/// With `path`, only the server whose root_dir contains it is returned.
+fn discover_servers(lua: &Lua, path: Option<String>) -> LuaResult<LuaTable<'_>> {
+    let servers = server::discover_servers();
+    let servers: Vec<&ServerInfo> = match &path {
+        Some(path) => server::server_for_path(&servers, std::path::Path::new(path))
+            .into_iter()
+            .collect(),
+        None => servers.iter().collect(),
+    };
+
+    let servers_table = lua.create_table()?;
+    for (i, server) in servers.into_iter().enumerate() {
+        servers_table.set(i + 1, server_info_table(lua, server)?)?;
+    }
+    let response_table = lua.create_table()?;
+    response_table.set(RESEPONSE_TABLE_KEY_DATA, servers_table)?;
+    Ok(response_table)
+}
+
+fn spawned_server_table<'lua>(lua: &'lua Lua, server: &SpawnedServer) -> LuaResult<LuaTable<'lua>> {
+    let server_table = lua.create_table()?;
+    server_table.set("url", server.url.as_str())?;
+    server_table.set("token", server.token.as_str())?;
+    server_table.set("pid", server.pid)?;
+    server_table.set("root_dir", server.root_dir.as_deref())?;
+    server_table.set("ready", server.ready)?;
+    Ok(server_table)
+}
+
+/// Launches `jupyter server` (or `jupyter lab` with `subcommand = "lab"`) with a generated token and returns
+/// without waiting for it to listen, which `poll_server_events` tells. Its credentials are registered for the returned url.
+fn start_server<'lua>(lua: &'lua Lua, opts: Option<LuaTable<'lua>>) -> LuaResult<LuaTable<'lua>> {
+    let mut options = ServerOptions::default();
+    if let Some(opts) = opts {
+        let executable: Option<String> = opts.get("executable")?;
+        let subcommand: Option<String> = opts.get("subcommand")?;
+        let port: Option<u16> = opts.get("port")?;
+        let root_dir: Option<String> = opts.get("root_dir")?;
+        if let Some(executable) = executable {
+            options.executable = executable;
+        }
+        if let Some(subcommand) = subcommand {
+            options.subcommand = subcommand;
+        }
+        if let Some(port) = port {
+            options.port = port;
+        }
+        options.root_dir = root_dir;
+    }
+
+    match server::spawn_server(&options) {
+        Ok(spawned) => {
+            client_cache::set_credentials(
+                &spawned.url,
+                Credentials {
+                    token: Some(spawned.token.clone()),
+                    password: None,
+                },
+            );
+            let response_table = lua.create_table()?;
+            response_table.set(
+                RESEPONSE_TABLE_KEY_DATA,
+                spawned_server_table(lua, &spawned)?,
+            )?;
+            Ok(response_table)
+        }
+        Err(e) => to_error_table(lua, e),
+    }
+}
+
+fn list_spawned_servers(lua: &Lua, _: ()) -> LuaResult<LuaTable<'_>> {
+    let servers_table = lua.create_table()?;
+    for (i, spawned) in server::spawned_servers().iter().enumerate() {
+        servers_table.set(i + 1, spawned_server_table(lua, spawned)?)?;
+    }
+    let response_table = lua.create_table()?;
+    response_table.set(RESEPONSE_TABLE_KEY_DATA, servers_table)?;
+    Ok(response_table)
+}
+
+/// How the starts of the servers launched by `start_server` ended, as a list of `{ url, event, error }`
+/// where `event` is `ready` or `failed`.
+fn poll_server_events(lua: &Lua, _: ()) -> LuaResult<LuaTable<'_>> {
+    let events_table = lua.create_table()?;
+    for (i, server_event) in server::drain_server_events().into_iter().enumerate() {
+        let event_table = lua.create_table()?;
+        event_table.set("url", server_event.url)?;
+        match server_event.event {
+            ServerStartEvent::Ready => event_table.set("event", "ready")?,
+            ServerStartEvent::Failed(log) => {
+                event_table.set("event", "failed")?;
+                event_table.set(
+                    RESEPONSE_TABLE_KEY_ERROR,
+                    JupyterRunnerError::ServerStartFailed(log).to_string(),
+                )?;
             }
         }
+        events_table.set(i + 1, event_table)?;
+    }
+    let response_table = lua.create_table()?;
+    response_table.set(RESEPONSE_TABLE_KEY_DATA, events_table)?;
+    Ok(response_table)
+}
+
+/// Shuts down a server started by `start_server`. Other servers are left alone.
+fn stop_server(lua: &Lua, jupyter_base_url: String) -> LuaResult<LuaTable<'_>> {
+    if server::stop_server(&jupyter_base_url) {
+        empty_table(lua)
+    } else {
+        to_error_table(lua, JupyterRunnerError::ServerNotSpawned(jupyter_base_url))
+    }
+}
+
+/// Shuts down every server started by `start_server`. Called when Neovim exits.
+fn stop_servers(lua: &Lua, _: ()) -> LuaResult<LuaTable<'_>> {
+    server::stop_servers();
+    empty_table(lua)
+}
+
+const RESEPONSE_TABLE_KEY_STARTUP_ERROR: &str = "startup_error";
+const RESEPONSE_TABLE_KEY_ENV_ERROR: &str = "env_error";
+
+/// `{ cwd, env, env_file, startup_code }`, with `env` and `startup_code` (keyed by language) as string tables.
+fn kernel_start_options(opts: Option<LuaTable>) -> LuaResult<KernelStartOptions> {
+    let mut options = KernelStartOptions::default();
+    if let Some(opts) = opts {
+        let env: Option<HashMap<String, String>> = opts.get("env")?;
+        let env_file: Option<String> = opts.get("env_file")?;
+        let startup_code: Option<HashMap<String, String>> = opts.get("startup_code")?;
+        options.cwd = opts.get("cwd")?;
+        options.env_file = env_file.map(PathBuf::from);
+        if let Some(env) = env {
+            options.env = env.into_iter().collect();
+        }
+        if let Some(startup_code) = startup_code {
+            options.startup_code = startup_code
+                .into_iter()
+                .map(|(language, code)| (language.to_lowercase(), code))
+                .collect();
+        }
+    }
+    Ok(options)
+}
+
+/// Starts a `kernel_name` kernel on the server, or launches it from its kernelspec on the `local` endpoint.
+/// See `kernel_start_options` for `opts`. The id is returned once the startup code has run, with
+/// `startup_error` set if it failed and `env_error` if the server did not set `env`.
+fn start_kernel<'lua>(
+    lua: &'lua Lua,
+    (jupyter_base_url, kernel_name, opts): (String, String, Option<LuaTable<'lua>>),
+) -> LuaResult<LuaTable<'lua>> {
+    let options = kernel_start_options(opts)?;
+    match block_on(kernel_manager::start_kernel(
+        &jupyter_base_url,
+        &kernel_name,
+        &options,
+    )) {
+        Ok(kernel_start) => {
+            let response_table = lua.create_table()?;
+            response_table.set(RESEPONSE_TABLE_KEY_DATA, kernel_start.kernel_id)?;
+            response_table.set(
+                RESEPONSE_TABLE_KEY_STARTUP_ERROR,
+                kernel_start.startup_error,
+            )?;
+            response_table.set(RESEPONSE_TABLE_KEY_ENV_ERROR, kernel_start.env_error)?;
+            Ok(response_table)
+        }
+        Err(e) => to_error_table(lua, e),
+    }
+}
+
+/// Attaches a kernel started elsewhere (VS Code, Spyder, a plain ipykernel...) through its connection file.
+/// The returned id is used with the `local` endpoint.
+fn attach_kernel(lua: &Lua, connection_file: String) -> LuaResult<LuaTable<'_>> {
+    match block_on(kernel_manager::attach_kernel(&connection_file)) {
+        Ok(kernel_id) => {
+            let response_table = lua.create_table()?;
+            response_table.set(RESEPONSE_TABLE_KEY_DATA, kernel_id)?;
+            Ok(response_table)
+        }
+        Err(e) => to_error_table(lua, e),
+    }
+}
+
+/// The kernel connection files in the Jupyter runtime dir. `alive` is whether the kernel answers its heartbeat,
+/// `attached` whether it can already be used with the `local` endpoint.
+fn list_local_kernels(lua: &Lua, _: ()) -> LuaResult<LuaTable<'_>> {
+    let connection_files =
+        match block_on(async { Ok(kernel_manager::list_connection_files().await) }) {
+            Ok(connection_files) => connection_files,
+            Err(e) => return to_error_table(lua, e),
+        };
+
+    let kernels_table = lua.create_table()?;
+    for (i, (connection_file, alive)) in connection_files.iter().enumerate() {
+        let kernel_table = lua.create_table()?;
+        kernel_table.set("id", connection_file.kernel_id.as_str())?;
+        kernel_table.set(
+            "connection_file",
+            connection_file.path.to_string_lossy().to_string(),
+        )?;
+        kernel_table.set(
+            "kernel_name",
+            connection_file.connection_info.kernel_name.as_str(),
+        )?;
+        kernel_table.set("alive", *alive)?;
+        kernel_table.set(
+            "attached",
+            local::get_kernel(&connection_file.kernel_id).is_some(),
+        )?;
+        kernels_table.set(i + 1, kernel_table)?;
     }
+    let response_table = lua.create_table()?;
+    response_table.set(RESEPONSE_TABLE_KEY_DATA, kernels_table)?;
+    Ok(response_table)
 }
 
 fn interrupt_kernel(
     lua: &Lua,
-    (jupyter_base_url, kernel_name): (String, String),
+    (jupyter_base_url, kernel_id): (String, String),
 ) -> LuaResult<LuaTable<'_>> {
-    match get_jupyter_client(&jupyter_base_url) {
-        Err(e) => Ok(to_error_table(&lua, e)?),
-        Ok(jupyter_client) => {
-            match Runtime::new()
-                .unwrap()
-                .block_on(jupyter_client.interrupt_kernel(&kernel_name))
-            {
-                Ok(()) => Ok(empty_table(&lua)?),
-                Err(e) => Ok(to_error_table(&lua, e.into())?),
+    match block_on(kernel_manager::interrupt_kernel(
+        &jupyter_base_url,
+        &kernel_id,
+    )) {
+        Ok(()) => empty_table(lua),
+        Err(e) => to_error_table(lua, e),
+    }
+}
+
+fn delete_kernel(
+    lua: &Lua,
+    (jupyter_base_url, kernel_id): (String, String),
+) -> LuaResult<LuaTable<'_>> {
+    match block_on(kernel_manager::shutdown_kernel(
+        &jupyter_base_url,
+        &kernel_id,
+    )) {
+        Ok(()) => empty_table(lua),
+        Err(e) => to_error_table(lua, e),
+    }
+}
+
+fn kernel_info_table<'lua>(lua: &'lua Lua, kernel_info: &KernelInfo) -> LuaResult<LuaTable<'lua>> {
+    let language_info = &kernel_info.language_info;
+    let language_info_table = lua.create_table()?;
+    language_info_table.set("name", language_info.name.as_str())?;
+    language_info_table.set("version", language_info.version.as_str())?;
+    language_info_table.set("file_extension", language_info.file_extension.as_deref())?;
+    language_info_table.set("mimetype", language_info.mimetype.as_deref())?;
+    if let Some(codemirror_mode) = &language_info.codemirror_mode {
+        language_info_table.set("codemirror_mode", json_to_lua(lua, codemirror_mode)?)?;
+    }
+
+    let info_table = lua.create_table()?;
+    info_table.set("protocol_version", kernel_info.protocol_version.as_str())?;
+    info_table.set("implementation", kernel_info.implementation.as_str())?;
+    info_table.set(
+        "implementation_version",
+        kernel_info.implementation_version.as_str(),
+    )?;
+    info_table.set("banner", kernel_info.banner.as_str())?;
+    info_table.set("language_info", language_info_table)?;
+    Ok(info_table)
+}
+
+fn kernel_model_table<'lua>(lua: &'lua Lua, kernel: &KernelModel) -> LuaResult<LuaTable<'lua>> {
+    let kernel_table = lua.create_table()?;
+    kernel_table.set("id", kernel.id.as_str())?;
+    kernel_table.set("name", kernel.name.as_str())?;
+    kernel_table.set("execution_state", kernel.execution_state.as_deref())?;
+    kernel_table.set("last_activity", kernel.last_activity.as_deref())?;
+    kernel_table.set("connections", kernel.connections)?;
+    Ok(kernel_table)
+}
+
+/// Restarts the kernel keeping its id and returns once it is ready, with its kernel info.
+fn restart_kernel(
+    lua: &Lua,
+    (jupyter_base_url, kernel_id): (String, String),
+) -> LuaResult<LuaTable<'_>> {
+    match block_on(kernel_manager::restart_kernel(
+        &jupyter_base_url,
+        &kernel_id,
+    )) {
+        Ok((kernel, kernel_info)) => {
+            let kernel_table = kernel_model_table(lua, &kernel)?;
+            kernel_table.set("kernel_info", kernel_info_table(lua, &kernel_info)?)?;
+
+            let response_table = lua.create_table()?;
+            response_table.set(RESEPONSE_TABLE_KEY_DATA, kernel_table)?;
+            Ok(response_table)
+        }
+        Err(e) => to_error_table(lua, e),
+    }
+}
+
+/// The server's view of the kernel: `execution_state` (idle, busy, starting, dead...), `last_activity` and `connections`.
+fn kernel_status(
+    lua: &Lua,
+    (jupyter_base_url, kernel_id): (String, String),
+) -> LuaResult<LuaTable<'_>> {
+    match block_on(kernel_manager::kernel_model(&jupyter_base_url, &kernel_id)) {
+        Ok(kernel) => {
+            let response_table = lua.create_table()?;
+            response_table.set(RESEPONSE_TABLE_KEY_DATA, kernel_model_table(lua, &kernel)?)?;
+            Ok(response_table)
+        }
+        Err(e) => {
+            if let JupyterRunnerError::KernelNotFound(_) = e {
+                client_cache::invalidate_kernel(&kernel_id);
             }
+            to_error_table(lua, e)
         }
     }
 }
 
-fn delete_kernel(
+fn kernel_info(
+    lua: &Lua,
+    (jupyter_base_url, kernel_id): (String, String),
+) -> LuaResult<LuaTable<'_>> {
+    match block_on(async {
+        let kernel_client = client_cache::kernel_client(&jupyter_base_url, &kernel_id).await?;
+        kernel_client.kernel_info().await
+    }) {
+        Ok(kernel_info) => {
+            let response_table = lua.create_table()?;
+            response_table.set(
+                RESEPONSE_TABLE_KEY_DATA,
+                kernel_info_table(lua, &kernel_info)?,
+            )?;
+            Ok(response_table)
+        }
+        Err(e) => to_error_table(lua, e),
+    }
+}
+
+fn completion_table<'lua>(lua: &'lua Lua, completion: &Completion) -> LuaResult<LuaTable<'lua>> {
+    let completion_table = lua.create_table()?;
+    completion_table.set("matches", completion.matches.clone())?;
+    completion_table.set("cursor_start", completion.cursor_start)?;
+    completion_table.set("cursor_end", completion.cursor_end)?;
+    if let Some(typed_matches) = &completion.typed_matches {
+        let types_table = lua.create_table()?;
+        for (i, typed_match) in typed_matches.iter().enumerate() {
+            let type_table = lua.create_table()?;
+            type_table.set("text", typed_match.text.as_str())?;
+            type_table.set("start", typed_match.start)?;
+            type_table.set("end", typed_match.end)?;
+            type_table.set("type", typed_match.match_type.as_deref())?;
+            type_table.set("signature", typed_match.signature.as_deref())?;
+            types_table.set(i + 1, type_table)?;
+        }
+        completion_table.set("types", types_table)?;
+    }
+    Ok(completion_table)
+}
+
+/// Completes `code` at `cursor_pos`, a 0-based byte offset. `cursor_start`/`cursor_end` of the reply are byte offsets too.
+/// `types` is IPython's `_jupyter_types_experimental` metadata, if the kernel sent it.
+fn complete(
     lua: &Lua,
-    (jupyter_base_url, kernel_name): (String, String),
+    (jupyter_base_url, kernel_id, code, cursor_pos): (String, String, String, usize),
 ) -> LuaResult<LuaTable<'_>> {
-    match get_jupyter_client(&jupyter_base_url) {
-        Err(e) => Ok(to_error_table(&lua, e)?),
-        Ok(jupyter_client) => {
-            match Runtime::new()
-                .unwrap()
-                .block_on(jupyter_client.delete_kernel(&kernel_name))
-            {
-                Ok(()) => Ok(empty_table(&lua)?),
-                Err(e) => Ok(to_error_table(&lua, e.into())?),
+    match block_on(async {
+        let kernel_client = client_cache::kernel_client(&jupyter_base_url, &kernel_id).await?;
+        timeout(
+            INTERACTIVE_REQUEST_TIMEOUT,
+            kernel::complete(&kernel_client.connection, &code, cursor_pos),
+        )
+        .await
+        .map_err(|_| JupyterRunnerError::RequestTimeout("complete_request".to_string()))?
+    }) {
+        Ok(completion) => {
+            let response_table = lua.create_table()?;
+            response_table.set(
+                RESEPONSE_TABLE_KEY_DATA,
+                completion_table(lua, &completion)?,
+            )?;
+            Ok(response_table)
+        }
+        Err(e) => to_error_table(lua, e),
+    }
+}
+
+/// A palette index as a number, or an RGB color as `#rrggbb`.
+fn ansi_color_value(lua: &Lua, color: AnsiColor) -> LuaResult<LuaValue<'_>> {
+    match color {
+        AnsiColor::Indexed(index) => Ok(LuaValue::Integer(index.into())),
+        AnsiColor::Rgb(r, g, b) => Ok(LuaValue::String(
+            lua.create_string(format!("#{r:02x}{g:02x}{b:02x}"))?,
+        )),
+    }
+}
+
+fn highlights_table<'lua>(lua: &'lua Lua, styled: &StyledText) -> LuaResult<LuaTable<'lua>> {
+    let highlights_table = lua.create_table()?;
+    for (i, span) in styled.spans.iter().enumerate() {
+        let span_table = lua.create_table()?;
+        span_table.set("line", span.line)?;
+        span_table.set("start", span.start)?;
+        span_table.set("end", span.end)?;
+        if let Some(fg) = span.style.fg {
+            span_table.set("fg", ansi_color_value(lua, fg)?)?;
+        }
+        if let Some(bg) = span.style.bg {
+            span_table.set("bg", ansi_color_value(lua, bg)?)?;
+        }
+        span_table.set("bold", span.style.bold)?;
+        span_table.set("italic", span.style.italic)?;
+        span_table.set("underline", span.style.underline)?;
+        highlights_table.set(i + 1, span_table)?;
+    }
+    Ok(highlights_table)
+}
+
+fn inspection_table<'lua>(lua: &'lua Lua, inspection: &Inspection) -> LuaResult<LuaTable<'lua>> {
+    let inspection_table = lua.create_table()?;
+    inspection_table.set("found", inspection.found)?;
+    if let Some(plain_text) = inspection.plain_text() {
+        inspection_table.set("text", plain_text.text.as_str())?;
+        inspection_table.set("highlights", highlights_table(lua, &plain_text)?)?;
+    }
+    inspection_table.set("markdown", inspection.markdown())?;
+    Ok(inspection_table)
+}
+
+/// Inspects the object at `cursor_pos` of `code`, a 0-based byte offset. `detail_level` is 0 (docstring) or 1 (source).
+/// `text` is `text/plain` without its ANSI codes, which are given as `highlights`: 0-based lines and byte columns.
+fn inspect(
+    lua: &Lua,
+    (jupyter_base_url, kernel_id, code, cursor_pos, detail_level): (
+        String,
+        String,
+        String,
+        usize,
+        Option<u8>,
+    ),
+) -> LuaResult<LuaTable<'_>> {
+    match block_on(async {
+        let kernel_client = client_cache::kernel_client(&jupyter_base_url, &kernel_id).await?;
+        timeout(
+            INTERACTIVE_REQUEST_TIMEOUT,
+            kernel::inspect(
+                &kernel_client.connection,
+                &code,
+                cursor_pos,
+                detail_level.unwrap_or(0),
+            ),
+        )
+        .await
+        .map_err(|_| JupyterRunnerError::RequestTimeout("inspect_request".to_string()))?
+    }) {
+        Ok(inspection) => {
+            let response_table = lua.create_table()?;
+            response_table.set(
+                RESEPONSE_TABLE_KEY_DATA,
+                inspection_table(lua, &inspection)?,
+            )?;
+            Ok(response_table)
+        }
+        Err(e) => to_error_table(lua, e),
+    }
+}
+
+/// The number of lines of the statement at the start of `lines`, the buffer lines from the cursor on.
+fn statement_range(
+    lua: &Lua,
+    (jupyter_base_url, kernel_id, lines): (String, String, Vec<String>),
+) -> LuaResult<LuaTable<'_>> {
+    match block_on(statement_range::statement_range(
+        &jupyter_base_url,
+        &kernel_id,
+        &lines,
+    )) {
+        Ok(line_count) => {
+            let response_table = lua.create_table()?;
+            response_table.set(RESEPONSE_TABLE_KEY_DATA, line_count)?;
+            Ok(response_table)
+        }
+        Err(e) => to_error_table(lua, e),
+    }
+}
+
+const DEFAULT_HISTORY_LENGTH: usize = 1000;
+
+fn history_request(
+    opts: Option<LuaTable>,
+) -> LuaResult<Result<HistoryRequest, JupyterRunnerError>> {
+    let opts = match opts {
+        Some(opts) => opts,
+        None => {
+            return Ok(Ok(HistoryRequest {
+                access: HistoryAccess::Tail {
+                    n: DEFAULT_HISTORY_LENGTH,
+                },
+                output: false,
+                raw: true,
+            }))
+        }
+    };
+    let access: Option<String> = opts.get("access")?;
+    let n: Option<usize> = opts.get("n")?;
+    let access = match access.as_deref().unwrap_or("tail") {
+        "tail" => HistoryAccess::Tail {
+            n: n.unwrap_or(DEFAULT_HISTORY_LENGTH),
+        },
+        "range" => {
+            let session: Option<i64> = opts.get("session")?;
+            let start: Option<usize> = opts.get("start")?;
+            HistoryAccess::Range {
+                session: session.unwrap_or(0),
+                start: start.unwrap_or(1),
+                stop: opts.get("stop")?,
+            }
+        }
+        "search" => {
+            let pattern: Option<String> = opts.get("pattern")?;
+            let unique: Option<bool> = opts.get("unique")?;
+            HistoryAccess::Search {
+                pattern: pattern.unwrap_or_else(|| "*".to_string()),
+                n,
+                unique: unique.unwrap_or(false),
+            }
+        }
+        other => {
+            return Ok(Err(JupyterRunnerError::InvalidOption(format!(
+                "access must be tail, range or search, not {other}"
+            ))))
+        }
+    };
+    let output: Option<bool> = opts.get("output")?;
+    let raw: Option<bool> = opts.get("raw")?;
+    Ok(Ok(HistoryRequest {
+        access,
+        output: output.unwrap_or(false),
+        raw: raw.unwrap_or(true),
+    }))
+}
+
+fn history_entry_table<'lua>(lua: &'lua Lua, entry: &HistoryEntry) -> LuaResult<LuaTable<'lua>> {
+    let entry_table = lua.create_table()?;
+    entry_table.set("session", entry.session)?;
+    entry_table.set("line", entry.line)?;
+    entry_table.set("input", entry.input.as_str())?;
+    entry_table.set("output", entry.output.as_deref())?;
+    Ok(entry_table)
+}
+
+/// The inputs the kernel ran, from any client, oldest first. `opts` is
+/// `{ access = "tail" | "range" | "search", n, session, start, stop, pattern, unique, output, raw }`,
+/// the last 1000 raw inputs without it. Sessions count backwards from the current one, 0.
+fn history<'lua>(
+    lua: &'lua Lua,
+    (jupyter_base_url, kernel_id, opts): (String, String, Option<LuaTable<'lua>>),
+) -> LuaResult<LuaTable<'lua>> {
+    let request = match history_request(opts)? {
+        Ok(request) => request,
+        Err(e) => return to_error_table(lua, e),
+    };
+    match block_on(async {
+        let kernel_client = client_cache::kernel_client(&jupyter_base_url, &kernel_id).await?;
+        timeout(
+            INTERACTIVE_REQUEST_TIMEOUT,
+            kernel::history(&kernel_client.connection, &request),
+        )
+        .await
+        .map_err(|_| JupyterRunnerError::RequestTimeout("history_request".to_string()))?
+    }) {
+        Ok(entries) => {
+            let entries_table = lua.create_table()?;
+            for (i, entry) in entries.iter().enumerate() {
+                entries_table.set(i + 1, history_entry_table(lua, entry)?)?;
+            }
+            let response_table = lua.create_table()?;
+            response_table.set(RESEPONSE_TABLE_KEY_DATA, entries_table)?;
+            Ok(response_table)
+        }
+        Err(e) => to_error_table(lua, e),
+    }
+}
+
+fn session_table<'lua>(lua: &'lua Lua, session: &SessionModel) -> LuaResult<LuaTable<'lua>> {
+    let session_table = lua.create_table()?;
+    session_table.set("id", session.id.as_str())?;
+    session_table.set("path", session.path.as_str())?;
+    session_table.set("name", session.name.as_str())?;
+    session_table.set("type", session.session_type.as_str())?;
+    if let Some(kernel) = &session.kernel {
+        session_table.set("kernel", kernel_model_table(lua, kernel)?)?;
+    }
+    Ok(session_table)
+}
+
+fn session_response_table(
+    lua: &Lua,
+    result: Result<SessionModel, JupyterRunnerError>,
+) -> LuaResult<LuaTable<'_>> {
+    match result {
+        Ok(session) => {
+            let response_table = lua.create_table()?;
+            response_table.set(RESEPONSE_TABLE_KEY_DATA, session_table(lua, &session)?)?;
+            Ok(response_table)
+        }
+        Err(e) => to_error_table(lua, e),
+    }
+}
+
+fn list_sessions(lua: &Lua, jupyter_base_url: String) -> LuaResult<LuaTable<'_>> {
+    match block_on(async {
+        let server_client = client_cache::server_client(&jupyter_base_url)?;
+        server_client.list_sessions().await
+    }) {
+        Ok(sessions) => {
+            let sessions_table = lua.create_table()?;
+            for (i, session) in sessions.iter().enumerate() {
+                sessions_table.set(i + 1, session_table(lua, session)?)?;
             }
+            let response_table = lua.create_table()?;
+            response_table.set(RESEPONSE_TABLE_KEY_DATA, sessions_table)?;
+            Ok(response_table)
         }
+        Err(e) => to_error_table(lua, e),
     }
 }
 
-fn get_jupyter_client(jupyter_base_url: &str) -> Result<JupyterClient, JupyterRunnerError> {
-    let client = JupyterClient::new(jupyter_base_url, None, None)?;
-    Ok(client)
+/// Creates a session at `path` running a new kernel of `kernel_name`.
+fn create_session(
+    lua: &Lua,
+    (jupyter_base_url, path, kernel_name, session_type): (String, String, String, Option<String>),
+) -> LuaResult<LuaTable<'_>> {
+    let result = block_on(async {
+        let server_client = client_cache::server_client(&jupyter_base_url)?;
+        let name = path.rsplit('/').next().unwrap_or(&path);
+        server_client
+            .create_session(
+                &path,
+                name,
+                session_type
+                    .as_deref()
+                    .unwrap_or(server::DEFAULT_SESSION_TYPE),
+                SessionKernel::Name(kernel_name),
+            )
+            .await
+    });
+    session_response_table(lua, result)
+}
+
+/// Binds the already running `kernel_id` to `path`, e.g. the selected kernel to the current buffer.
+fn bind_session(
+    lua: &Lua,
+    (jupyter_base_url, path, kernel_id, session_type): (String, String, String, Option<String>),
+) -> LuaResult<LuaTable<'_>> {
+    let result = block_on(async {
+        let server_client = client_cache::server_client(&jupyter_base_url)?;
+        let name = path.rsplit('/').next().unwrap_or(&path);
+        server_client
+            .create_session(
+                &path,
+                name,
+                session_type
+                    .as_deref()
+                    .unwrap_or(server::DEFAULT_SESSION_TYPE),
+                SessionKernel::Id(kernel_id),
+            )
+            .await
+    });
+    session_response_table(lua, result)
 }
 
-async fn get_kernel_client_by_id(
-    jupyter_base_url: &str,
-    kernel_id: &str,
-) -> Result<Option<(KernelApiClient, Kernel)>, JupyterRunnerError> {
-    let jupyter_client = get_jupyter_client(jupyter_base_url)?;
+/// Returns the session bound to `path`, starting a `kernel_name` kernel for it if it has none.
+/// This is how a buffer reattaches to its kernel, including one started from JupyterLab.
+/// The server starts the kernel with the session, so the options of `start_kernel` do not apply, and the kernel
+/// is not tracked as started by the plugin.
+fn session_for_path(
+    lua: &Lua,
+    (jupyter_base_url, path, kernel_name, session_type): (
+        String,
+        String,
+        Option<String>,
+        Option<String>,
+    ),
+) -> LuaResult<LuaTable<'_>> {
+    let result = block_on(async {
+        let server_client = client_cache::server_client(&jupyter_base_url)?;
+        server_client
+            .session_for_path(
+                &path,
+                kernel_name.as_deref(),
+                session_type
+                    .as_deref()
+                    .unwrap_or(server::DEFAULT_SESSION_TYPE),
+            )
+            .await
+    });
+    session_response_table(lua, result)
+}
 
-    let kernel = jupyter_client.get_running_kernel(kernel_id).await?;
+fn rename_session(
+    lua: &Lua,
+    (jupyter_base_url, session_id, path, name): (String, String, Option<String>, Option<String>),
+) -> LuaResult<LuaTable<'_>> {
+    let result = block_on(async {
+        let server_client = client_cache::server_client(&jupyter_base_url)?;
+        server_client
+            .rename_session(&session_id, path.as_deref(), name.as_deref())
+            .await
+    });
+    session_response_table(lua, result)
+}
 
-    match kernel {
-        None => Err(JupyterRunnerError::KernelNotFound(kernel_id.to_string())),
-        Some(kernel) => Ok(Some((jupyter_client.new_kernel_client(&kernel)?, kernel))),
+/// Deletes the session, which also shuts down its kernel.
+fn delete_session(
+    lua: &Lua,
+    (jupyter_base_url, session_id): (String, String),
+) -> LuaResult<LuaTable<'_>> {
+    match block_on(async {
+        let server_client = client_cache::server_client(&jupyter_base_url)?;
+        if let Some(kernel) = server_client
+            .get_session(&session_id)
+            .await?
+            .and_then(|session| session.kernel)
+        {
+            client_cache::invalidate_kernel(&kernel.id);
+        }
+        server_client.delete_session(&session_id).await
+    }) {
+        Ok(()) => empty_table(lua),
+        Err(e) => to_error_table(lua, e),
     }
 }
 
+/// The running kernels, sorted by name, as `{ id, name, execution_state, last_activity, connections, session_paths,
+/// started_at }`. The fields the server does not report are nil, and `started_at` is only known for the kernels
+/// the plugin started.
 fn list_running_kernels(lua: &Lua, jupyter_base_url: String) -> LuaResult<LuaTable<'_>> {
-    let jupyter_client = match get_jupyter_client(&jupyter_base_url) {
-        Err(e) => return Ok(to_error_table(&lua, e.into())?),
-        Ok(jupyter_client) => jupyter_client,
-    };
+    match block_on(kernel_manager::running_kernels(&jupyter_base_url)) {
+        Err(e) => to_error_table(lua, e),
+        Ok(running_kernels) => {
+            client_cache::retain_kernels(
+                &running_kernels
+                    .iter()
+                    .map(|running_kernel| running_kernel.kernel.id.to_string())
+                    .collect::<HashSet<String>>(),
+            );
 
-    match Runtime::new()
-        .unwrap()
-        .block_on(jupyter_client.get_running_kernels())
-    {
-        Err(e) => Ok(to_error_table(&lua, e.into())?),
-        Ok(kernels) => {
-            let kernel_table = lua.create_table()?;
-            for each in kernels.into_iter() {
-                kernel_table.set(each.id, each.name)?;
+            let kernels_table = lua.create_table()?;
+            for (i, running_kernel) in running_kernels.iter().enumerate() {
+                let kernel_table = kernel_model_table(lua, &running_kernel.kernel)?;
+                kernel_table.set("session_paths", running_kernel.session_paths.clone())?;
+                kernel_table.set("started_at", running_kernel.started_at.as_deref())?;
+                kernels_table.set(i + 1, kernel_table)?;
             }
 
             let response_table = lua.create_table()?;
-            response_table.set(RESEPONSE_TABLE_KEY_DATA, kernel_table)?;
+            response_table.set(RESEPONSE_TABLE_KEY_DATA, kernels_table)?;
             Ok(response_table)
         }
     }
 }
 
+/// The kernelspec names of the server, or of this machine on the `local` endpoint.
 fn list_kernel_names(lua: &Lua, jupyter_base_url: String) -> LuaResult<LuaTable<'_>> {
-    let jupyter_client = match get_jupyter_client(&jupyter_base_url) {
-        Err(e) => return Ok(to_error_table(&lua, e.into())?),
-        Ok(jupyter_client) => jupyter_client,
-    };
-
-    match Runtime::new()
-        .unwrap()
-        .block_on(jupyter_client.get_kernel_specs())
-    {
-        Err(e) => Ok(to_error_table(&lua, e.into())?),
-        Ok(kernels) => {
-            let mut kernel_names = kernels
-                .kernelspecs
-                .values()
+    match block_on(async {
+        if local::is_local_endpoint(&jupyter_base_url) {
+            return Ok(local::find_kernel_specs()
                 .into_iter()
-                .map(|kernel| kernel.name.to_string())
-                .collect::<Vec<String>>();
+                .map(|kernel_spec| kernel_spec.name)
+                .collect::<Vec<String>>());
+        }
+        let server_client = client_cache::server_client(&jupyter_base_url)?;
+        Ok(server_client
+            .get_kernel_specs()
+            .await?
+            .kernelspecs
+            .into_values()
+            .map(|kernel_spec| kernel_spec.name)
+            .collect())
+    }) {
+        Err(e) => to_error_table(lua, e),
+        Ok(mut kernel_names) => {
             kernel_names.sort();
 
             let response_table = lua.create_table()?;
@@ -165,99 +854,630 @@ fn list_kernel_names(lua: &Lua, jupyter_base_url: String) -> LuaResult<LuaTable<
     }
 }
 
-fn run_code(
-    lua: &Lua,
-    (jupyter_base_url, kernel_id, code): (String, String, String),
-) -> LuaResult<LuaTable<'_>> {
-    let (kernel_client, kernel) = match Runtime::new()
-        .unwrap()
-        .block_on(get_kernel_client_by_id(&jupyter_base_url, &kernel_id))
-    {
-        Err(e) => return Ok(to_error_table(&lua, e.into())?),
-        Ok(kernel_client) => match kernel_client {
-            None => {
-                return Ok(to_error_table(
-                    &lua,
-                    JupyterRunnerError::KernelNotFound(code.to_string()),
-                )?)
-            }
-            Some(kernel_client) => kernel_client,
-        },
+fn kernel_spec_table<'lua>(
+    lua: &'lua Lua,
+    kernel_spec: &NamedKernelSpec,
+) -> LuaResult<LuaTable<'lua>> {
+    let spec = &kernel_spec.spec;
+    let kernel_spec_table = lua.create_table()?;
+    kernel_spec_table.set("name", kernel_spec.name.as_str())?;
+    kernel_spec_table.set("display_name", spec.display_name.as_str())?;
+    kernel_spec_table.set("language", spec.language.as_str())?;
+    kernel_spec_table.set("argv", spec.argv.clone())?;
+    kernel_spec_table.set("env", spec.env.clone())?;
+    kernel_spec_table.set("interrupt_mode", spec.interrupt_mode.as_deref())?;
+    kernel_spec_table.set(
+        "metadata",
+        json_to_lua(lua, &serde_json::Value::Object(spec.metadata.clone()))?,
+    )?;
+    Ok(kernel_spec_table)
+}
+
+/// The kernelspecs of the server, or of this machine on the `local` endpoint, as
+/// `{ default, kernel_specs = { { name, display_name, language, argv, env, interrupt_mode, metadata } } }`
+/// sorted by name. `opts.language` keeps the kernels of that language only.
+fn list_kernel_specs<'lua>(
+    lua: &'lua Lua,
+    (jupyter_base_url, opts): (String, Option<LuaTable<'lua>>),
+) -> LuaResult<LuaTable<'lua>> {
+    let language: Option<String> = match &opts {
+        Some(opts) => opts.get("language")?,
+        None => None,
     };
+    match block_on(kernel_manager::kernel_specs(
+        &jupyter_base_url,
+        language.as_deref(),
+    )) {
+        Ok(kernel_specs) => {
+            let kernel_specs_table = lua.create_table()?;
+            for (i, kernel_spec) in kernel_specs.kernel_specs.iter().enumerate() {
+                kernel_specs_table.set(i + 1, kernel_spec_table(lua, kernel_spec)?)?;
+            }
+            let data_table = lua.create_table()?;
+            data_table.set("default", kernel_specs.default)?;
+            data_table.set("kernel_specs", kernel_specs_table)?;
+
+            let response_table = lua.create_table()?;
+            response_table.set(RESEPONSE_TABLE_KEY_DATA, data_table)?;
+            Ok(response_table)
+        }
+        Err(e) => to_error_table(lua, e),
+    }
+}
 
-    let code = if let Ok(parsable_kernel) = ParsableKernel::try_from_str(&kernel.name) {
-        let parsed_code = match parsable_kernel {
-            ParsableKernel::Rust => match RustParser.parse(&code) {
-                Ok(parsed_code) => parsed_code,
-                Err(e) => return Ok(to_error_table(&lua, e.into())?),
-            },
-            ParsableKernel::Python3 => {
-                return Ok(to_error_table(
-                    &lua,
-                    ParserError::UnsuppotedKernel("python".to_string()).into(),
-                )?)
+fn json_to_lua<'lua>(lua: &'lua Lua, value: &serde_json::Value) -> LuaResult<LuaValue<'lua>> {
+    use serde_json::Value;
+    Ok(match value {
+        Value::Null => LuaValue::Nil,
+        Value::Bool(b) => LuaValue::Boolean(*b),
+        Value::Number(n) => match n.as_i64() {
+            Some(i) => LuaValue::Integer(i as LuaInteger),
+            None => LuaValue::Number(n.as_f64().unwrap_or_default()),
+        },
+        Value::String(s) => LuaValue::String(lua.create_string(s)?),
+        Value::Array(values) => {
+            let table = lua.create_table()?;
+            for (i, each) in values.iter().enumerate() {
+                table.set(i + 1, json_to_lua(lua, each)?)?;
             }
-        };
-        match parsed_code {
-            Some(cell_sources) => cell_sources.as_one_line_code(),
-            None => return Ok(empty_table(&lua)?),
+            LuaValue::Table(table)
         }
-    } else {
-        code
+        Value::Object(map) => {
+            let table = lua.create_table()?;
+            for (k, each) in map.iter() {
+                table.set(k.as_str(), json_to_lua(lua, each)?)?;
+            }
+            LuaValue::Table(table)
+        }
+    })
+}
+
+fn kernel_error_table<'lua>(lua: &'lua Lua, error: &KernelError) -> LuaResult<LuaTable<'lua>> {
+    let error_table = lua.create_table()?;
+    error_table.set("ename", error.ename.as_str())?;
+    error_table.set("evalue", error.evalue.as_str())?;
+    error_table.set("traceback", error.traceback.clone())?;
+    error_table.set("execution_count", error.execution_count)?;
+    Ok(error_table)
+}
+
+/// Sets `data` (the whole bundle), its `metadata` and `mime_type`, the representation to show by the configured priority.
+fn set_mime_bundle<'lua>(
+    lua: &'lua Lua,
+    output_table: &LuaTable<'lua>,
+    data: &MimeBundle,
+    metadata: &serde_json::Map<String, serde_json::Value>,
+) -> LuaResult<()> {
+    let data_table = lua.create_table()?;
+    for (mime_type, value) in data.iter() {
+        // JSON values are handed over as tables, as joining them as lines would lose them.
+        match kernel::representation_text(mime_type, value) {
+            Some(text) => data_table.set(mime_type.as_str(), text)?,
+            None => data_table.set(mime_type.as_str(), json_to_lua(lua, value)?)?,
+        }
+    }
+    output_table.set("data", data_table)?;
+    output_table.set("metadata", json_to_lua(lua, &metadata.clone().into())?)?;
+    output_table.set(
+        "mime_type",
+        kernel::select_mime_type(data, &kernel::mime_priority()),
+    )?;
+    Ok(())
+}
+
+fn output_table<'lua>(lua: &'lua Lua, output: &ExecutionOutput) -> LuaResult<LuaTable<'lua>> {
+    let output_table = lua.create_table()?;
+    output_table.set("output_type", output.output_type())?;
+    match output {
+        ExecutionOutput::Stream { name, text } => {
+            output_table.set("name", name.as_str())?;
+            output_table.set("text", text.as_str())?;
+        }
+        ExecutionOutput::DisplayData { data, metadata } => {
+            set_mime_bundle(lua, &output_table, data, metadata)?;
+        }
+        ExecutionOutput::ExecuteResult {
+            execution_count,
+            data,
+            metadata,
+        } => {
+            output_table.set("execution_count", *execution_count)?;
+            set_mime_bundle(lua, &output_table, data, metadata)?;
+        }
+        ExecutionOutput::Error(error) => {
+            output_table.set("ename", error.ename.as_str())?;
+            output_table.set("evalue", error.evalue.as_str())?;
+            output_table.set("traceback", error.traceback.clone())?;
+        }
+    }
+    Ok(output_table)
+}
+
+const RESEPONSE_TABLE_KEY_TIMEOUT: &str = "timeout";
+
+/// The global timeout options overridden by `opts`: `{ timeout_ms, interrupt_grace_ms, restart }`.
+/// A `timeout_ms` of 0 lets the execution run forever.
+fn timeout_options(opts: Option<LuaTable>) -> LuaResult<TimeoutOptions> {
+    let mut options = execution::timeout_options();
+    if let Some(opts) = opts {
+        let timeout_ms: Option<u64> = opts.get("timeout_ms")?;
+        let interrupt_grace_ms: Option<u64> = opts.get("interrupt_grace_ms")?;
+        let restart: Option<bool> = opts.get("restart")?;
+        if let Some(timeout_ms) = timeout_ms {
+            options.timeout = Some(Duration::from_millis(timeout_ms)).filter(|t| !t.is_zero());
+        }
+        if let Some(interrupt_grace_ms) = interrupt_grace_ms {
+            options.interrupt_grace = Duration::from_millis(interrupt_grace_ms);
+        }
+        if let Some(restart) = restart {
+            options.restart = restart;
+        }
+    }
+    Ok(options)
+}
+
+/// Sets the timeout of executions that do not set their own. See `timeout_options` for `opts`.
+fn set_execution_timeout<'lua>(lua: &'lua Lua, opts: LuaTable<'lua>) -> LuaResult<LuaTable<'lua>> {
+    execution::set_timeout_options(timeout_options(Some(opts))?);
+    empty_table(lua)
+}
+
+fn outputs_table<'lua>(lua: &'lua Lua, outputs: &[ExecutionOutput]) -> LuaResult<LuaTable<'lua>> {
+    let outputs_table = lua.create_table()?;
+    for (i, output) in outputs.iter().enumerate() {
+        outputs_table.set(i + 1, output_table(lua, output)?)?;
+    }
+    Ok(outputs_table)
+}
+
+/// Runs the code and returns every output of the execution, in order, once the kernel is idle again.
+/// The code is queued behind the executions of the kernel like `run_code_async`, and this blocks until it has run.
+/// An exception raised by the code is returned under `kernel_error`, while `error` is kept for failures to reach the kernel.
+/// On a timeout, `data` has the outputs until then and `timeout` what became of the kernel:
+/// `interrupted`, `busy` or `restarted`.
+fn run_code<'lua>(
+    lua: &'lua Lua,
+    (jupyter_base_url, kernel_id, code, opts): (String, String, String, Option<LuaTable<'lua>>),
+) -> LuaResult<LuaTable<'lua>> {
+    let options = ExecutionOptions {
+        timeout_options: timeout_options(opts)?,
+        stop_on_error: false,
     };
+    match block_on(execution::run_queued(
+        jupyter_base_url,
+        kernel_id,
+        code,
+        options,
+    )) {
+        Ok((reply, outputs)) => {
+            let response_table = lua.create_table()?;
+            response_table.set(RESEPONSE_TABLE_KEY_DATA, outputs_table(lua, &outputs)?)?;
+            if let Some(kernel_error) = reply.and_then(|reply| reply.error) {
+                response_table.set(
+                    RESEPONSE_TABLE_KEY_KERNEL_ERROR,
+                    kernel_error_table(lua, &kernel_error)?,
+                )?;
+            }
+            Ok(response_table)
+        }
+        Err(e) => {
+            let timed_out = match &e {
+                JupyterRunnerError::Timeout {
+                    outcome, outputs, ..
+                } => Some((*outcome, outputs_table(lua, outputs)?)),
+                _ => None,
+            };
+            let response_table = to_error_table(lua, e)?;
+            if let Some((outcome, outputs_table)) = timed_out {
+                response_table.set(RESEPONSE_TABLE_KEY_DATA, outputs_table)?;
+                response_table.set(RESEPONSE_TABLE_KEY_TIMEOUT, outcome.as_str())?;
+            }
+            Ok(response_table)
+        }
+    }
+}
+
+const CALLBACK_REGISTRY_NAME: &str = "run_jupyter.execution_callbacks";
+const CALLBACK_KEY_ON_OUTPUT: &str = "on_output";
+const CALLBACK_KEY_ON_DONE: &str = "on_done";
+const CALLBACK_KEY_ON_INPUT: &str = "on_input";
+
+fn execution_callbacks(lua: &Lua) -> LuaResult<LuaTable<'_>> {
+    let callbacks: Option<LuaTable> = lua.named_registry_value(CALLBACK_REGISTRY_NAME)?;
+    if let Some(callbacks) = callbacks {
+        return Ok(callbacks);
+    }
+    let callbacks = lua.create_table()?;
+    lua.set_named_registry_value(CALLBACK_REGISTRY_NAME, callbacks.clone())?;
+    Ok(callbacks)
+}
+
+/// url, kernel id, code, on_output, on_done, on_input and opts.
+type RunCodeAsyncArgs<'lua> = (
+    String,
+    String,
+    String,
+    Option<LuaFunction<'lua>>,
+    Option<LuaFunction<'lua>>,
+    Option<LuaFunction<'lua>>,
+    Option<LuaTable<'lua>>,
+);
 
-    let response = match Runtime::new()
-        .unwrap()
-        .block_on(kernel_client.run_code(code.into(), None))
+/// Queues the code on the kernel without blocking and returns its execution handle.
+/// The executions of a kernel run one at a time, in the order they were queued.
+/// `on_output`, `on_done` and `on_input` are only ever called from `poll_events`, on the caller's thread.
+/// `on_input({ prompt, password })` is called when the code reads stdin, to be answered with `send_input`.
+/// `opts` overrides the global timeout options, and with `stop_on_error` the executions queued after this one
+/// are cancelled if it fails. A timed out execution ends in the `timed_out` state, with `timeout` telling
+/// what became of the kernel.
+fn run_code_async<'lua>(
+    lua: &'lua Lua,
+    (jupyter_base_url, kernel_id, code, on_output, on_done, on_input, opts): RunCodeAsyncArgs<'lua>,
+) -> LuaResult<LuaTable<'lua>> {
+    let stop_on_error: Option<bool> = match &opts {
+        Some(opts) => opts.get("stop_on_error")?,
+        None => None,
+    };
+    let options = ExecutionOptions {
+        timeout_options: timeout_options(opts)?,
+        stop_on_error: stop_on_error.unwrap_or(false),
+    };
+    let execution_id = match execution::spawn_execution(jupyter_base_url, kernel_id, code, options)
     {
-        Ok(response) => response,
-        Err(e) => return Ok(to_error_table(&lua, e.into())?),
+        Ok(execution_id) => execution_id,
+        Err(e) => return to_error_table(lua, e),
     };
 
-    let contents = match response.as_content() {
-        Ok(contents) => contents,
-        Err(e) => return Ok(to_error_table(&lua, e.into())?),
-    };
+    let callback_table = lua.create_table()?;
+    callback_table.set(CALLBACK_KEY_ON_OUTPUT, on_output)?;
+    callback_table.set(CALLBACK_KEY_ON_DONE, on_done)?;
+    callback_table.set(CALLBACK_KEY_ON_INPUT, on_input)?;
+    execution_callbacks(lua)?.set(execution_id, callback_table)?;
 
-    let result = match contents {
-        None => empty_table(&lua)?,
-        Some(contents) => {
-            let content_data = match contents {
-                KernelContent::DisplayData(display_data) => Some(display_data.data),
-                KernelContent::ExecuteResultContent(result_content) => Some(result_content.data),
-                _ => None,
-            };
+    let response_table = lua.create_table()?;
+    response_table.set(RESEPONSE_TABLE_KEY_DATA, execution_id)?;
+    Ok(response_table)
+}
 
-            match content_data {
-                Some(data) => {
-                    let response_table = lua.create_table()?;
-                    if let Some(image) = data.image_png {
-                        response_table.set(RESEPONSE_TABLE_KEY_PNG, image)?;
-                    } else if let Some(text_plain) = data.text_plain {
-                        response_table.set(RESEPONSE_TABLE_KEY_TEXT, text_plain)?;
+/// Delivers the queued execution events to their callbacks and returns how many executions are still running.
+/// A callback that raises does not keep the other events from being delivered. The errors are raised afterwards.
+fn poll_events(lua: &Lua, _: ()) -> LuaResult<usize> {
+    let callbacks = execution_callbacks(lua)?;
+    let errors: Vec<String> = execution::drain_events()
+        .into_iter()
+        .filter_map(|event| deliver_event(lua, &callbacks, event).err())
+        .map(|e| e.to_string())
+        .collect();
+    if !errors.is_empty() {
+        return Err(LuaError::RuntimeError(errors.join("\n")));
+    }
+    Ok(callbacks.pairs::<LuaValue, LuaValue>().count())
+}
+
+fn deliver_event<'lua>(
+    lua: &'lua Lua,
+    callbacks: &LuaTable<'lua>,
+    event: ExecutionEvent,
+) -> LuaResult<()> {
+    match event {
+        ExecutionEvent::Output {
+            execution_id,
+            output,
+        } => {
+            if let Some(callback_table) = callbacks.get::<_, Option<LuaTable>>(execution_id)? {
+                if let Some(on_output) =
+                    callback_table.get::<_, Option<LuaFunction>>(CALLBACK_KEY_ON_OUTPUT)?
+                {
+                    on_output.call::<_, ()>(output_table(lua, &output)?)?;
+                }
+            }
+        }
+        ExecutionEvent::InputRequest {
+            execution_id,
+            input_request,
+        } => {
+            let on_input = match callbacks.get::<_, Option<LuaTable>>(execution_id)? {
+                Some(callback_table) => {
+                    callback_table.get::<_, Option<LuaFunction>>(CALLBACK_KEY_ON_INPUT)?
+                }
+                None => None,
+            };
+            match on_input {
+                Some(on_input) => {
+                    let request_table = lua.create_table()?;
+                    request_table.set("prompt", input_request.prompt.as_str())?;
+                    request_table.set("password", input_request.password)?;
+                    if let Err(e) = on_input.call::<_, ()>(request_table) {
+                        // nobody will answer, as for a missing callback.
+                        execution::send_input(execution_id, String::new());
+                        return Err(e);
+                    }
+                }
+                // nobody to ask, so the code gets an empty line rather than waiting forever.
+                None => {
+                    execution::send_input(execution_id, String::new());
+                }
+            }
+        }
+        ExecutionEvent::Done {
+            execution_id,
+            state,
+            error,
+            kernel_error,
+            timeout_outcome,
+        } => {
+            if let Some(callback_table) = callbacks.get::<_, Option<LuaTable>>(execution_id)? {
+                callbacks.set(execution_id, LuaValue::Nil)?;
+                if let Some(on_done) =
+                    callback_table.get::<_, Option<LuaFunction>>(CALLBACK_KEY_ON_DONE)?
+                {
+                    let result_table = lua.create_table()?;
+                    result_table.set(RESEPONSE_TABLE_KEY_STATE, state.as_str())?;
+                    result_table.set(RESEPONSE_TABLE_KEY_ERROR, error)?;
+                    if let Some(kernel_error) = kernel_error {
+                        result_table.set(
+                            RESEPONSE_TABLE_KEY_KERNEL_ERROR,
+                            kernel_error_table(lua, &kernel_error)?,
+                        )?;
+                    }
+                    if let Some(timeout_outcome) = timeout_outcome {
+                        result_table.set(RESEPONSE_TABLE_KEY_TIMEOUT, timeout_outcome.as_str())?;
                     }
-                    response_table
+                    on_done.call::<_, ()>(result_table)?;
                 }
-                None => empty_table(&lua)?,
             }
         }
+    }
+    Ok(())
+}
+
+fn cancel_execution(lua: &Lua, execution_id: ExecutionId) -> LuaResult<LuaTable<'_>> {
+    match execution::cancel_execution(execution_id) {
+        Ok(cancelled) => {
+            let response_table = lua.create_table()?;
+            response_table.set(RESEPONSE_TABLE_KEY_DATA, cancelled)?;
+            Ok(response_table)
+        }
+        Err(e) => to_error_table(lua, e),
+    }
+}
+
+/// Answers the `input()` the execution waits on. `data` is false if it is not waiting.
+fn send_input(lua: &Lua, (execution_id, value): (ExecutionId, String)) -> LuaResult<LuaTable<'_>> {
+    let response_table = lua.create_table()?;
+    response_table.set(
+        RESEPONSE_TABLE_KEY_DATA,
+        execution::send_input(execution_id, value),
+    )?;
+    Ok(response_table)
+}
+
+/// Ended executions are not found once `poll_events` has delivered their end.
+fn execution_state(lua: &Lua, execution_id: ExecutionId) -> LuaResult<LuaTable<'_>> {
+    let response_table = lua.create_table()?;
+    match execution::execution_state(execution_id) {
+        Some(state) => response_table.set(RESEPONSE_TABLE_KEY_DATA, state.as_str())?,
+        None => response_table.set(
+            RESEPONSE_TABLE_KEY_ERROR,
+            format!("execution not found {execution_id}"),
+        )?,
+    }
+    Ok(response_table)
+}
+
+/// The executions of the kernel that have not ended, `{ id, state }` with the running one first.
+fn execution_queue(
+    lua: &Lua,
+    (jupyter_base_url, kernel_id): (String, String),
+) -> LuaResult<LuaTable<'_>> {
+    let queue_table = lua.create_table()?;
+    for (i, (execution_id, state)) in execution::queued_executions(&jupyter_base_url, &kernel_id)
+        .into_iter()
+        .enumerate()
+    {
+        let execution_table = lua.create_table()?;
+        execution_table.set("id", execution_id)?;
+        execution_table.set("state", state.as_str())?;
+        queue_table.set(i + 1, execution_table)?;
+    }
+    let response_table = lua.create_table()?;
+    response_table.set(RESEPONSE_TABLE_KEY_DATA, queue_table)?;
+    Ok(response_table)
+}
+
+/// Cancels the pending executions of the kernel without interrupting the running one. `data` is how many.
+fn clear_execution_queue(
+    lua: &Lua,
+    (jupyter_base_url, kernel_id): (String, String),
+) -> LuaResult<LuaTable<'_>> {
+    let response_table = lua.create_table()?;
+    response_table.set(
+        RESEPONSE_TABLE_KEY_DATA,
+        execution::clear_queue(&jupyter_base_url, &kernel_id),
+    )?;
+    Ok(response_table)
+}
+
+/// Turns the monitor of the kernels in use on or off. `opts` is `{ enabled, interval_ms }`, unset ones kept as they are.
+fn monitor_kernels<'lua>(lua: &'lua Lua, opts: LuaTable<'lua>) -> LuaResult<LuaTable<'lua>> {
+    let enabled: Option<bool> = opts.get("enabled")?;
+    let interval_ms: Option<u64> = opts.get("interval_ms")?;
+    let mut options = kernel_monitor::monitor_options();
+    if let Some(enabled) = enabled {
+        options.enabled = enabled;
+    }
+    if let Some(interval_ms) = interval_ms {
+        // a zero interval would have the monitor ask the server in a busy loop.
+        options.interval = Duration::from_millis(interval_ms.max(100));
+    }
+    kernel_monitor::configure(options);
+    empty_table(lua)
+}
+
+/// What the monitor found since the last call, `{ url, kernel_id, event }` in order. `event` is
+/// `died`, `restarted`, `reconnected` or `unreachable`.
+fn poll_kernel_events(lua: &Lua, _: ()) -> LuaResult<LuaTable<'_>> {
+    let events_table = lua.create_table()?;
+    for (i, kernel_event) in kernel_monitor::drain_events().into_iter().enumerate() {
+        let event_table = lua.create_table()?;
+        event_table.set("url", kernel_event.jupyter_base_url)?;
+        event_table.set("kernel_id", kernel_event.kernel_id)?;
+        event_table.set("event", kernel_event.event.as_str())?;
+        events_table.set(i + 1, event_table)?;
+    }
+    let response_table = lua.create_table()?;
+    response_table.set(RESEPONSE_TABLE_KEY_DATA, events_table)?;
+    Ok(response_table)
+}
+
+/// Sets what becomes of the kernels started from now on. `opts` is `{ on_exit, idle_timeout_ms }`:
+/// `on_exit` is `shutdown`, `leave` or `keep`, and an `idle_timeout_ms` of 0 keeps idle kernels running.
+fn set_kernel_lifecycle<'lua>(lua: &'lua Lua, opts: LuaTable<'lua>) -> LuaResult<LuaTable<'lua>> {
+    let on_exit: Option<String> = opts.get("on_exit")?;
+    let idle_timeout_ms: Option<u64> = opts.get("idle_timeout_ms")?;
+    let mut options = started_kernels::lifecycle_options();
+    if let Some(on_exit) = on_exit {
+        options.default_policy = match ShutdownPolicy::try_from_str(&on_exit) {
+            Ok(policy) => policy,
+            Err(e) => return to_error_table(lua, e),
+        };
+    }
+    if let Some(idle_timeout_ms) = idle_timeout_ms {
+        options.idle_timeout =
+            Some(Duration::from_millis(idle_timeout_ms)).filter(|t| !t.is_zero());
+    }
+    started_kernels::set_lifecycle_options(options);
+    empty_table(lua)
+}
+
+/// Overrides the policy of one kernel. `data` is false if the kernel was not started by the plugin.
+fn set_shutdown_policy(
+    lua: &Lua,
+    (jupyter_base_url, kernel_id, policy): (String, String, String),
+) -> LuaResult<LuaTable<'_>> {
+    let policy = match ShutdownPolicy::try_from_str(&policy) {
+        Ok(policy) => policy,
+        Err(e) => return to_error_table(lua, e),
     };
+    let response_table = lua.create_table()?;
+    response_table.set(
+        RESEPONSE_TABLE_KEY_DATA,
+        started_kernels::set_policy(&jupyter_base_url, &kernel_id, policy),
+    )?;
+    Ok(response_table)
+}
+
+/// Deletes the kernels started by the plugin whose policy is `shutdown`. `data` is how many were.
+fn shutdown_started_kernels(lua: &Lua, _: ()) -> LuaResult<LuaTable<'_>> {
+    match block_on(async { Ok(started_kernels::shutdown_on_exit().await) }) {
+        Ok(count) => {
+            let response_table = lua.create_table()?;
+            response_table.set(RESEPONSE_TABLE_KEY_DATA, count)?;
+            Ok(response_table)
+        }
+        Err(e) => to_error_table(lua, e),
+    }
+}
+
+const STARTED_KERNELS_GUARD_NAME: &str = "run_jupyter.started_kernels_guard";
+
+/// Kept in the registry, so the kernels are shut down when the Lua state closing drops it.
+struct StartedKernelsGuard;
 
-    LuaResult::Ok(result)
+impl LuaUserData for StartedKernelsGuard {}
+
+impl Drop for StartedKernelsGuard {
+    fn drop(&mut self) {
+        let _ = block_on(async { Ok(started_kernels::shutdown_on_exit().await) });
+    }
+}
+
+/// Replaces the MIME types `mime_type` of display outputs is chosen from, richest first.
+fn set_mime_priority(lua: &Lua, mime_priority: Vec<String>) -> LuaResult<LuaTable<'_>> {
+    kernel::set_mime_priority(mime_priority);
+    empty_table(lua)
 }
 
 #[mlua::lua_module]
 fn librun_jupyter(lua: &Lua) -> LuaResult<LuaTable> {
     let exports = lua.create_table()?;
 
+    exports.set("set_credentials", lua.create_function(set_credentials)?)?;
+    exports.set("discover_servers", lua.create_function(discover_servers)?)?;
+    exports.set("start_server", lua.create_function(start_server)?)?;
+    exports.set(
+        "list_spawned_servers",
+        lua.create_function(list_spawned_servers)?,
+    )?;
+    exports.set(
+        "poll_server_events",
+        lua.create_function(poll_server_events)?,
+    )?;
+    exports.set("stop_server", lua.create_function(stop_server)?)?;
+    exports.set("stop_servers", lua.create_function(stop_servers)?)?;
     exports.set("start_kernel", lua.create_function(start_kernel)?)?;
+    exports.set("attach_kernel", lua.create_function(attach_kernel)?)?;
+    exports.set(
+        "list_local_kernels",
+        lua.create_function(list_local_kernels)?,
+    )?;
     exports.set("interrupt_kernel", lua.create_function(interrupt_kernel)?)?;
     exports.set("delete_kernel", lua.create_function(delete_kernel)?)?;
+    exports.set("restart_kernel", lua.create_function(restart_kernel)?)?;
+    exports.set("kernel_status", lua.create_function(kernel_status)?)?;
+    exports.set("kernel_info", lua.create_function(kernel_info)?)?;
+    exports.set("complete", lua.create_function(complete)?)?;
+    exports.set("inspect", lua.create_function(inspect)?)?;
+    exports.set("statement_range", lua.create_function(statement_range)?)?;
+    exports.set("history", lua.create_function(history)?)?;
     exports.set(
         "list_running_kernels",
         lua.create_function(list_running_kernels)?,
     )?;
     exports.set("list_kernel_names", lua.create_function(list_kernel_names)?)?;
+    exports.set("list_kernel_specs", lua.create_function(list_kernel_specs)?)?;
+    exports.set("list_sessions", lua.create_function(list_sessions)?)?;
+    exports.set("create_session", lua.create_function(create_session)?)?;
+    exports.set("bind_session", lua.create_function(bind_session)?)?;
+    exports.set("session_for_path", lua.create_function(session_for_path)?)?;
+    exports.set("rename_session", lua.create_function(rename_session)?)?;
+    exports.set("delete_session", lua.create_function(delete_session)?)?;
     exports.set("run_code", lua.create_function(run_code)?)?;
+    exports.set("run_code_async", lua.create_function(run_code_async)?)?;
+    exports.set("poll_events", lua.create_function(poll_events)?)?;
+    exports.set("cancel_execution", lua.create_function(cancel_execution)?)?;
+    exports.set("send_input", lua.create_function(send_input)?)?;
+    exports.set("execution_state", lua.create_function(execution_state)?)?;
+    exports.set("execution_queue", lua.create_function(execution_queue)?)?;
+    exports.set(
+        "clear_execution_queue",
+        lua.create_function(clear_execution_queue)?,
+    )?;
+    exports.set("monitor_kernels", lua.create_function(monitor_kernels)?)?;
+    exports.set(
+        "poll_kernel_events",
+        lua.create_function(poll_kernel_events)?,
+    )?;
+    exports.set(
+        "set_kernel_lifecycle",
+        lua.create_function(set_kernel_lifecycle)?,
+    )?;
+    exports.set(
+        "set_shutdown_policy",
+        lua.create_function(set_shutdown_policy)?,
+    )?;
+    exports.set(
+        "shutdown_started_kernels",
+        lua.create_function(shutdown_started_kernels)?,
+    )?;
+    exports.set("set_mime_priority", lua.create_function(set_mime_priority)?)?;
+    exports.set(
+        "set_execution_timeout",
+        lua.create_function(set_execution_timeout)?,
+    )?;
+    lua.set_named_registry_value(
+        STARTED_KERNELS_GUARD_NAME,
+        lua.create_userdata(StartedKernelsGuard)?,
+    )?;
     Ok(exports)
 }
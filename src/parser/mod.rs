@@ -1,6 +1,7 @@
 pub mod comment_extractor;
 pub mod error;
 pub mod rust_parser;
+pub mod statement;
 
 pub use error::*;
 use jupyter_client::CellType;
@@ -23,6 +24,17 @@ impl ParsableKernel {
             ))),
         }
     }
+
+    /// `language` is the `language_info.name` a kernel reports, e.g. "Rust" for evcxr.
+    pub fn try_from_language(language: &str) -> Result<Self> {
+        match language.to_lowercase().as_str() {
+            "rust" => Ok(Self::Rust),
+            "python" | "python3" => Ok(Self::Python3),
+            other => Err(error::ParserError::UnsuppotedKernel(format!(
+                "Not supported language :{other}"
+            ))),
+        }
+    }
 }
 
 #[derive(Debug, PartialEq)]
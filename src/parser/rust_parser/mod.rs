@@ -7,7 +7,7 @@ extern "C" {
     fn tree_sitter_rust() -> Language;
 }
 
-fn rust_lang() -> Language {
+pub(crate) fn rust_lang() -> Language {
     unsafe { tree_sitter_rust() }
 }
 
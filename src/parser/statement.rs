@@ -0,0 +1,103 @@
+use super::rust_parser::rust_lang;
+use super::*;
+use tree_sitter::{Language, Parser};
+
+extern "C" {
+    fn tree_sitter_python() -> Language;
+}
+
+fn python_lang() -> Language {
+    unsafe { tree_sitter_python() }
+}
+
+const CLOSING_BRACKETS: [char; 3] = [')', ']', '}'];
+const PYTHON_CONTINUATION_KEYWORDS: [&str; 4] = ["else", "elif", "except", "finally"];
+
+fn starts_with_keyword(line: &str, keyword: &str) -> bool {
+    match line.strip_prefix(keyword) {
+        Some(rest) => !rest.starts_with(|c: char| c.is_alphanumeric() || c == '_'),
+        None => false,
+    }
+}
+
+pub fn indent_width(line: &str) -> usize {
+    line.len() - line.trim_start().len()
+}
+
+/// Whether `line` can not start a statement of its own and belongs to the one above it at `statement_indent`,
+/// e.g. an indented block, a closing bracket or an `else` clause.
+pub fn continues_statement(
+    kernel: Option<&ParsableKernel>,
+    statement_indent: usize,
+    line: &str,
+) -> bool {
+    if indent_width(line) > statement_indent {
+        return true;
+    }
+    let line = line.trim_start();
+    if line.starts_with(CLOSING_BRACKETS) {
+        return true;
+    }
+    match kernel {
+        Some(ParsableKernel::Python3) => PYTHON_CONTINUATION_KEYWORDS
+            .iter()
+            .any(|keyword| starts_with_keyword(line, keyword)),
+        Some(ParsableKernel::Rust) => line.starts_with('.') || starts_with_keyword(line, "else"),
+        None => false,
+    }
+}
+
+fn parses_without_error(language: Language, code: &str) -> Result<bool> {
+    let mut parser = Parser::new();
+    parser.set_language(language)?;
+    Ok(match parser.parse(code, None) {
+        Some(tree) => !tree.root_node().has_error(),
+        None => false,
+    })
+}
+
+/// Whether `code` parses as whole statements. For Rust, the last one may be an expression without `;`
+/// as evcxr shows its value.
+pub fn is_complete_statement(kernel: &ParsableKernel, code: &str) -> Result<bool> {
+    match kernel {
+        ParsableKernel::Python3 => parses_without_error(python_lang(), code),
+        ParsableKernel::Rust => Ok(parses_without_error(rust_lang(), code)?
+            || parses_without_error(rust_lang(), &format!("{code};"))?),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_continues_statement() {
+        let python = Some(&ParsableKernel::Python3);
+        assert!(continues_statement(python, 0, "    print(x)"));
+        assert!(continues_statement(python, 0, "else:"));
+        assert!(continues_statement(python, 4, "    elif x:"));
+        assert!(continues_statement(python, 0, "]"));
+        assert!(!continues_statement(python, 0, "elsewhere = 1"));
+        assert!(!continues_statement(python, 4, "print(x)"));
+
+        let rust = Some(&ParsableKernel::Rust);
+        assert!(continues_statement(rust, 0, ".map(|x| x + 1)"));
+        assert!(continues_statement(rust, 0, "} else {"));
+        assert!(!continues_statement(rust, 0, "let y = 2;"));
+
+        assert!(!continues_statement(None, 0, "else"));
+    }
+
+    #[test]
+    fn test_is_complete_statement() {
+        let python = ParsableKernel::Python3;
+        assert!(is_complete_statement(&python, "for x in y:\n    print(x)").unwrap());
+        assert!(!is_complete_statement(&python, "for x in y:").unwrap());
+        assert!(!is_complete_statement(&python, "x = (1,").unwrap());
+
+        let rust = ParsableKernel::Rust;
+        assert!(is_complete_statement(&rust, "let v = vec![\n    1,\n];").unwrap());
+        assert!(is_complete_statement(&rust, "v.iter()\n    .count()").unwrap());
+        assert!(!is_complete_statement(&rust, "fn f() {").unwrap());
+    }
+}
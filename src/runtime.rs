@@ -0,0 +1,20 @@
+use super::error::JupyterRunnerError;
+use once_cell::sync::OnceCell;
+use std::future::Future;
+use tokio::runtime::Runtime;
+
+type Result<T> = std::result::Result<T, JupyterRunnerError>;
+
+static RUNTIME: OnceCell<Runtime> = OnceCell::new();
+
+/// The process-wide runtime. It is created on first use and lives as long as the module is loaded.
+pub fn runtime() -> Result<&'static Runtime> {
+    RUNTIME.get_or_try_init(|| Runtime::new().map_err(JupyterRunnerError::RuntimeError))
+}
+
+pub fn block_on<F, T>(future: F) -> Result<T>
+where
+    F: Future<Output = Result<T>>,
+{
+    runtime()?.block_on(future)
+}
@@ -0,0 +1,96 @@
+use url::Url;
+
+pub const TOKEN_ENV_VAR: &str = "JUPYTER_TOKEN";
+const TOKEN_QUERY_KEY: &str = "token";
+
+/// How to authenticate against a Jupyter server.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Credentials {
+    pub token: Option<String>,
+    pub password: Option<String>,
+}
+
+impl Credentials {
+    /// Fills in what was not configured. The token is looked up in the configured value first,
+    /// then in the endpoint url (`?token=...`, as printed by `jupyter server list`) and last in `$JUPYTER_TOKEN`.
+    pub fn resolve(jupyter_base_url: &str, configured: Option<Credentials>) -> Self {
+        let configured = configured.unwrap_or_default();
+        let token = non_empty(configured.token)
+            .or_else(|| token_from_url(jupyter_base_url))
+            .or_else(|| non_empty(std::env::var(TOKEN_ENV_VAR).ok()));
+        Self {
+            token,
+            password: non_empty(configured.password),
+        }
+    }
+
+    pub fn authorization_header(&self) -> Option<String> {
+        self.token.as_ref().map(|token| format!("token {token}"))
+    }
+}
+
+fn non_empty(value: Option<String>) -> Option<String> {
+    value.filter(|value| !value.is_empty())
+}
+
+pub fn token_from_url(jupyter_base_url: &str) -> Option<String> {
+    let url = Url::parse(jupyter_base_url).ok()?;
+    let token = url
+        .query_pairs()
+        .find(|(key, _)| key == TOKEN_QUERY_KEY)
+        .map(|(_, value)| value.to_string());
+    non_empty(token)
+}
+
+/// The value of `name` in a `Cookie` header value such as `_xsrf=abc; username-localhost-8888=...`.
+pub fn cookie_value(cookie_header: &str, name: &str) -> Option<String> {
+    cookie_header.split(';').find_map(|pair| {
+        let (key, value) = pair.trim().split_once('=')?;
+        if key == name {
+            Some(value.to_string())
+        } else {
+            None
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_token_from_url() {
+        assert_eq!(
+            Some("abc".to_string()),
+            token_from_url("http://localhost:8888/?token=abc")
+        );
+        assert_eq!(None, token_from_url("http://localhost:8888/?token="));
+        assert_eq!(None, token_from_url("http://localhost:8888"));
+    }
+
+    #[test]
+    fn test_configured_token_wins() {
+        let credentials = Credentials::resolve(
+            "http://localhost:8888/?token=from_url",
+            Some(Credentials {
+                token: Some("configured".to_string()),
+                password: None,
+            }),
+        );
+        assert_eq!(Some("configured".to_string()), credentials.token);
+        assert_eq!(
+            Some("token configured".to_string()),
+            credentials.authorization_header()
+        );
+    }
+
+    #[test]
+    fn test_cookie_value() {
+        let cookies = "_xsrf=2|abc|def; username-localhost-8888=\"2|xyz\"";
+        assert_eq!(
+            Some("2|abc|def".to_string()),
+            cookie_value(cookies, "_xsrf")
+        );
+        assert_eq!(None, cookie_value(cookies, "missing"));
+    }
+}
@@ -0,0 +1,122 @@
+use crate::jupyter_paths;
+use serde::Deserialize;
+use std::path::Path;
+
+const SERVER_FILE_PREFIXES: &[&str] = &["jpserver-", "nbserver-"];
+
+/// The content of a `jpserver-<pid>.json` (jupyter_server) or `nbserver-<pid>.json` (notebook < 7) file.
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct ServerInfo {
+    pub url: String,
+    #[serde(default)]
+    pub token: String,
+    #[serde(default)]
+    pub pid: u32,
+    #[serde(default)]
+    pub port: Option<u16>,
+    #[serde(default)]
+    pub base_url: String,
+    #[serde(default)]
+    pub secure: bool,
+    /// Whether the server asks for a password.
+    #[serde(default)]
+    pub password: bool,
+    /// `notebook_dir` in nbserver files.
+    #[serde(default, alias = "notebook_dir")]
+    pub root_dir: String,
+}
+
+#[cfg(unix)]
+pub fn is_process_alive(pid: u32) -> bool {
+    // signal 0 only checks that the process exists. EPERM still means it does.
+    let exists = unsafe { libc::kill(pid as libc::pid_t, 0) } == 0;
+    exists || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+#[cfg(not(unix))]
+pub fn is_process_alive(_pid: u32) -> bool {
+    true
+}
+
+/// The servers listed in the runtime dir whose process is still alive.
+/// Stale files left behind by crashed servers are skipped.
+pub fn discover_servers() -> Vec<ServerInfo> {
+    let runtime_dir = match jupyter_paths::runtime_dir() {
+        Some(runtime_dir) => runtime_dir,
+        None => return vec![],
+    };
+    let mut servers: Vec<ServerInfo> = vec![];
+    for path in jupyter_paths::runtime_files(&runtime_dir, SERVER_FILE_PREFIXES) {
+        let server = match std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|content| serde_json::from_str::<ServerInfo>(&content).ok())
+        {
+            Some(server) => server,
+            None => continue,
+        };
+        if server.pid != 0 && !is_process_alive(server.pid) {
+            continue;
+        }
+        if servers.iter().all(|each| each.url != server.url) {
+            servers.push(server);
+        }
+    }
+    servers
+}
+
+/// The server whose root_dir is the deepest directory containing `path`.
+pub fn server_for_path<'a>(servers: &'a [ServerInfo], path: &Path) -> Option<&'a ServerInfo> {
+    servers
+        .iter()
+        .filter(|server| !server.root_dir.is_empty() && path.starts_with(&server.root_dir))
+        .max_by_key(|server| Path::new(&server.root_dir).components().count())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn server(url: &str, root_dir: &str) -> ServerInfo {
+        ServerInfo {
+            url: url.to_string(),
+            token: String::new(),
+            pid: 0,
+            port: None,
+            base_url: "/".to_string(),
+            secure: false,
+            password: false,
+            root_dir: root_dir.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_deserialize_nbserver_file() {
+        let server: ServerInfo = serde_json::from_str(
+            r#"{"base_url": "/", "hostname": "localhost", "notebook_dir": "/home/me", "password": false, "pid": 1234, "port": 8888, "secure": false, "sock": "", "token": "abc", "url": "http://localhost:8888/"}"#,
+        )
+        .unwrap();
+        assert_eq!("/home/me", server.root_dir);
+        assert_eq!("abc", server.token);
+        assert_eq!(Some(8888), server.port);
+    }
+
+    #[test]
+    fn test_server_for_path_prefers_deepest_root() {
+        let servers = vec![
+            server("http://localhost:8888/", "/home/me"),
+            server("http://localhost:8889/", "/home/me/project"),
+            server("http://localhost:8890/", "/srv"),
+        ];
+        assert_eq!(
+            Some("http://localhost:8889/"),
+            server_for_path(&servers, Path::new("/home/me/project/src/main.py"))
+                .map(|server| server.url.as_str())
+        );
+        assert_eq!(
+            Some("http://localhost:8888/"),
+            server_for_path(&servers, Path::new("/home/me/other.py"))
+                .map(|server| server.url.as_str())
+        );
+        assert_eq!(None, server_for_path(&servers, Path::new("/tmp/a.py")));
+    }
+}
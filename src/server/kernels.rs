@@ -0,0 +1,130 @@
+use super::ServerClient;
+use crate::error::JupyterRunnerError;
+use reqwest::Method;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+
+type Result<T> = std::result::Result<T, JupyterRunnerError>;
+
+/// A kernel as the server's `/api/kernels` endpoints describe it.
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct KernelModel {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub last_activity: Option<String>,
+    #[serde(default)]
+    pub execution_state: Option<String>,
+    #[serde(default)]
+    pub connections: Option<i64>,
+}
+
+impl ServerClient {
+    pub async fn list_kernels(&self) -> Result<Vec<KernelModel>> {
+        let request = self.request(Method::GET, "api/kernels").await?;
+        match self.send(request).await? {
+            Some(response) => Ok(response.json().await?),
+            None => Ok(vec![]),
+        }
+    }
+
+    /// Starts a kernel in `path`, a directory relative to the server's root dir. Servers that do not take
+    /// `env` in the request leave the kernel with their own environment.
+    pub async fn start_kernel(
+        &self,
+        kernel_name: &str,
+        path: Option<&str>,
+        env: &[(String, String)],
+    ) -> Result<KernelModel> {
+        let env: Map<String, Value> = env
+            .iter()
+            .map(|(key, value)| (key.clone(), Value::String(value.clone())))
+            .collect();
+        let request = self
+            .request(Method::POST, "api/kernels")
+            .await?
+            .json(&json!({
+                "name": kernel_name,
+                "path": path,
+                "env": env,
+            }));
+        match self.send(request).await? {
+            Some(response) => Ok(response.json().await?),
+            None => Err(JupyterRunnerError::KernelSpecNotFound(
+                kernel_name.to_string(),
+            )),
+        }
+    }
+
+    pub async fn get_kernel(&self, kernel_id: &str) -> Result<Option<KernelModel>> {
+        let request = self
+            .request(Method::GET, &format!("api/kernels/{kernel_id}"))
+            .await?;
+        match self.send(request).await? {
+            Some(response) => Ok(Some(response.json().await?)),
+            None => Ok(None),
+        }
+    }
+
+    pub async fn interrupt_kernel(&self, kernel_id: &str) -> Result<()> {
+        let request = self
+            .request(Method::POST, &format!("api/kernels/{kernel_id}/interrupt"))
+            .await?;
+        match self.send(request).await? {
+            Some(_) => Ok(()),
+            None => Err(JupyterRunnerError::KernelNotFound(kernel_id.to_string())),
+        }
+    }
+
+    /// Shuts the kernel down.
+    pub async fn delete_kernel(&self, kernel_id: &str) -> Result<()> {
+        let request = self
+            .request(Method::DELETE, &format!("api/kernels/{kernel_id}"))
+            .await?;
+        match self.send(request).await? {
+            Some(_) => Ok(()),
+            None => Err(JupyterRunnerError::KernelNotFound(kernel_id.to_string())),
+        }
+    }
+
+    /// Restarts the kernel process. The kernel keeps its id.
+    pub async fn restart_kernel(&self, kernel_id: &str) -> Result<KernelModel> {
+        let request = self
+            .request(Method::POST, &format!("api/kernels/{kernel_id}/restart"))
+            .await?;
+        match self.send(request).await? {
+            Some(response) => Ok(response.json().await?),
+            None => Err(JupyterRunnerError::KernelNotFound(kernel_id.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::server::Credentials;
+
+    #[test]
+    fn test_deserialize_kernel_model() {
+        let kernel: KernelModel = serde_json::from_str(
+            r#"{"id": "abc", "name": "rust", "last_activity": "2022-06-30T00:00:00.000000Z", "execution_state": "idle", "connections": 1}"#,
+        )
+        .unwrap();
+        assert_eq!("abc", kernel.id);
+        assert_eq!(Some("idle".to_string()), kernel.execution_state);
+        assert_eq!(Some(1), kernel.connections);
+    }
+
+    #[test]
+    fn test_api_url_keeps_base_path() {
+        let client = ServerClient::new(
+            "http://localhost:8888/jupyter?token=x",
+            Credentials::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            "http://localhost:8888/jupyter/api/kernels/abc/restart",
+            client.url("api/kernels/abc/restart").unwrap().as_str()
+        );
+    }
+}
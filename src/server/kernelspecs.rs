@@ -0,0 +1,71 @@
+use super::ServerClient;
+use crate::error::JupyterRunnerError;
+use crate::local::KernelSpec;
+use reqwest::Method;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+type Result<T> = std::result::Result<T, JupyterRunnerError>;
+
+/// A kernelspec as `/api/kernelspecs` describes it.
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct KernelSpecModel {
+    pub name: String,
+    pub spec: KernelSpec,
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct KernelSpecsModel {
+    /// The kernel the server starts when no name is given.
+    #[serde(default)]
+    pub default: Option<String>,
+    #[serde(default)]
+    pub kernelspecs: HashMap<String, KernelSpecModel>,
+}
+
+impl ServerClient {
+    pub async fn get_kernel_specs(&self) -> Result<KernelSpecsModel> {
+        let request = self.request(Method::GET, "api/kernelspecs").await?;
+        match self.send(request).await? {
+            Some(response) => Ok(response.json().await?),
+            None => Ok(KernelSpecsModel {
+                default: None,
+                kernelspecs: HashMap::new(),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_deserialize_kernel_specs_model() {
+        let kernel_specs: KernelSpecsModel = serde_json::from_str(
+            r#"{
+                "default": "python3",
+                "kernelspecs": {
+                    "analytics": {
+                        "name": "analytics",
+                        "spec": {
+                            "argv": ["/home/me/analytics/.venv/bin/python", "-m", "ipykernel_launcher", "-f", "{connection_file}"],
+                            "env": {},
+                            "display_name": "Python 3.11 (venv: analytics)",
+                            "language": "python",
+                            "interrupt_mode": "signal",
+                            "metadata": {"debugger": true}
+                        },
+                        "resources": {"logo-64x64": "/kernelspecs/analytics/logo-64x64.png"}
+                    }
+                }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(Some("python3".to_string()), kernel_specs.default);
+        let analytics = &kernel_specs.kernelspecs["analytics"].spec;
+        assert_eq!("Python 3.11 (venv: analytics)", analytics.display_name);
+        assert_eq!("python", analytics.language);
+        assert_eq!(Some("signal".to_string()), analytics.interrupt_mode);
+    }
+}
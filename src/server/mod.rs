@@ -0,0 +1,166 @@
+pub mod auth;
+pub mod discovery;
+pub mod kernels;
+pub mod kernelspecs;
+pub mod sessions;
+pub mod supervisor;
+
+pub use auth::*;
+pub use discovery::*;
+pub use kernels::*;
+pub use sessions::*;
+pub use supervisor::*;
+
+use crate::error::JupyterRunnerError;
+use reqwest::cookie::{CookieStore, Jar};
+use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION, COOKIE};
+use reqwest::{Method, RequestBuilder, Response, StatusCode};
+use std::sync::Arc;
+use tokio::sync::OnceCell;
+use url::Url;
+
+type Result<T> = std::result::Result<T, JupyterRunnerError>;
+
+const XSRF_COOKIE: &str = "_xsrf";
+const XSRF_HEADER: &str = "X-XSRFToken";
+
+/// REST client for the Jupyter server endpoints that `jupyter_client` does not cover.
+pub struct ServerClient {
+    base_url: Url,
+    credentials: Credentials,
+    cookie_jar: Arc<Jar>,
+    http: reqwest::Client,
+    logged_in: OnceCell<()>,
+}
+
+impl ServerClient {
+    pub fn new(jupyter_base_url: &str, credentials: Credentials) -> Result<Self> {
+        let mut base_url = Url::parse(jupyter_base_url)?;
+        if !base_url.path().ends_with('/') {
+            base_url.set_path(&format!("{}/", base_url.path()));
+        }
+        base_url.set_query(None);
+
+        let mut headers = HeaderMap::new();
+        if let Some(authorization) = credentials.authorization_header() {
+            headers.insert(
+                AUTHORIZATION,
+                HeaderValue::from_str(&authorization).map_err(|_| {
+                    JupyterRunnerError::AuthenticationFailed("invalid token".to_string())
+                })?,
+            );
+        }
+        let cookie_jar = Arc::new(Jar::default());
+        let http = reqwest::Client::builder()
+            .default_headers(headers)
+            .cookie_provider(cookie_jar.clone())
+            .build()?;
+
+        Ok(Self {
+            base_url,
+            credentials,
+            cookie_jar,
+            http,
+            logged_in: OnceCell::new(),
+        })
+    }
+
+    /// `path` is relative to the server root, e.g. `api/kernels`.
+    pub fn url(&self, path: &str) -> Result<Url> {
+        Ok(self.base_url.join(path)?)
+    }
+
+    /// Logs in on the first request if a password is configured. The session cookie is reused afterwards.
+    pub async fn request(&self, method: Method, path: &str) -> Result<RequestBuilder> {
+        self.ensure_logged_in().await?;
+        let mut request = self.http.request(method.clone(), self.url(path)?);
+        if method != Method::GET {
+            if let Some(xsrf_token) = self.xsrf_token() {
+                request = request.header(XSRF_HEADER, xsrf_token);
+            }
+        }
+        Ok(request)
+    }
+
+    /// Sends the request, turning non success statuses into errors.
+    /// A 404 is returned as None since it means the kernel or session is gone.
+    pub async fn send(&self, request: RequestBuilder) -> Result<Option<Response>> {
+        let response = request.send().await?;
+        match response.status() {
+            StatusCode::NOT_FOUND => Ok(None),
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
+                Err(JupyterRunnerError::AuthenticationFailed(format!(
+                    "{} {}",
+                    response.status(),
+                    response.url()
+                )))
+            }
+            _ => Ok(Some(response.error_for_status()?)),
+        }
+    }
+
+    pub async fn ensure_logged_in(&self) -> Result<()> {
+        let password = match &self.credentials.password {
+            Some(password) => password,
+            None => return Ok(()),
+        };
+        self.logged_in
+            .get_or_try_init(|| self.login(password))
+            .await?;
+        Ok(())
+    }
+
+    async fn login(&self, password: &str) -> Result<()> {
+        let login_url = self.url("login")?;
+        // the login page sets the _xsrf cookie the form has to echo back.
+        self.http.get(login_url.clone()).send().await?;
+
+        let xsrf_token = self.xsrf_token().unwrap_or_default();
+        self.http
+            .post(login_url)
+            .header(XSRF_HEADER, xsrf_token.as_str())
+            .form(&[("password", password), ("_xsrf", xsrf_token.as_str())])
+            .send()
+            .await?;
+
+        // a wrong password just renders the login page again, so the result is checked on an endpoint that needs auth.
+        let status = self
+            .http
+            .get(self.url("api/sessions")?)
+            .send()
+            .await?
+            .status();
+        if status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN {
+            return Err(JupyterRunnerError::AuthenticationFailed(
+                "invalid password".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    fn cookie_header(&self) -> Option<String> {
+        self.cookie_jar
+            .cookies(&self.base_url)
+            .and_then(|cookies| cookies.to_str().ok().map(str::to_string))
+    }
+
+    fn xsrf_token(&self) -> Option<String> {
+        self.cookie_header()
+            .and_then(|cookies| cookie_value(&cookies, XSRF_COOKIE))
+    }
+
+    /// Headers that authenticate the kernel websocket handshake the same way as the REST requests.
+    pub fn auth_headers(&self) -> Vec<(String, String)> {
+        let mut headers = vec![];
+        if let Some(authorization) = self.credentials.authorization_header() {
+            headers.push((AUTHORIZATION.to_string(), authorization));
+        }
+        if let Some(cookies) = self.cookie_header() {
+            headers.push((COOKIE.to_string(), cookies));
+        }
+        if let Some(xsrf_token) = self.xsrf_token() {
+            headers.push((XSRF_HEADER.to_string(), xsrf_token));
+        }
+        headers
+    }
+}
@@ -0,0 +1,172 @@
+use super::{KernelModel, ServerClient};
+use crate::error::JupyterRunnerError;
+use reqwest::Method;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+type Result<T> = std::result::Result<T, JupyterRunnerError>;
+
+/// JupyterLab opens a console on a console session at the same path, so this is what buffers bind to by default.
+pub const DEFAULT_SESSION_TYPE: &str = "console";
+
+/// A session of the server's `/api/sessions` endpoints, tying a kernel to a path.
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct SessionModel {
+    pub id: String,
+    #[serde(default)]
+    pub path: String,
+    #[serde(default)]
+    pub name: String,
+    #[serde(default, rename = "type")]
+    pub session_type: String,
+    pub kernel: Option<KernelModel>,
+}
+
+/// Which kernel a new session runs: a fresh one of the kernel spec, or one that is already running.
+pub enum SessionKernel {
+    Name(String),
+    Id(String),
+}
+
+impl ServerClient {
+    pub async fn list_sessions(&self) -> Result<Vec<SessionModel>> {
+        let request = self.request(Method::GET, "api/sessions").await?;
+        match self.send(request).await? {
+            Some(response) => Ok(response.json().await?),
+            None => Ok(vec![]),
+        }
+    }
+
+    pub async fn get_session(&self, session_id: &str) -> Result<Option<SessionModel>> {
+        let request = self
+            .request(Method::GET, &format!("api/sessions/{session_id}"))
+            .await?;
+        match self.send(request).await? {
+            Some(response) => Ok(Some(response.json().await?)),
+            None => Ok(None),
+        }
+    }
+
+    /// The server returns the existing session instead of creating one if the path already has a session.
+    pub async fn create_session(
+        &self,
+        path: &str,
+        name: &str,
+        session_type: &str,
+        kernel: SessionKernel,
+    ) -> Result<SessionModel> {
+        let kernel = match kernel {
+            SessionKernel::Name(kernel_name) => json!({ "name": kernel_name }),
+            SessionKernel::Id(kernel_id) => json!({ "id": kernel_id }),
+        };
+        let request = self
+            .request(Method::POST, "api/sessions")
+            .await?
+            .json(&json!({
+                "path": path,
+                "name": name,
+                "type": session_type,
+                "kernel": kernel,
+            }));
+        match self.send(request).await? {
+            Some(response) => Ok(response.json().await?),
+            None => Err(JupyterRunnerError::SessionNotFound(path.to_string())),
+        }
+    }
+
+    /// Moves the session to another path and/or name. The kernel keeps running.
+    pub async fn rename_session(
+        &self,
+        session_id: &str,
+        path: Option<&str>,
+        name: Option<&str>,
+    ) -> Result<SessionModel> {
+        let mut body = serde_json::Map::new();
+        if let Some(path) = path {
+            body.insert("path".to_string(), Value::from(path));
+        }
+        if let Some(name) = name {
+            body.insert("name".to_string(), Value::from(name));
+        }
+        let request = self
+            .request(Method::PATCH, &format!("api/sessions/{session_id}"))
+            .await?
+            .json(&body);
+        match self.send(request).await? {
+            Some(response) => Ok(response.json().await?),
+            None => Err(JupyterRunnerError::SessionNotFound(session_id.to_string())),
+        }
+    }
+
+    /// Deletes the session and shuts its kernel down.
+    pub async fn delete_session(&self, session_id: &str) -> Result<()> {
+        let request = self
+            .request(Method::DELETE, &format!("api/sessions/{session_id}"))
+            .await?;
+        match self.send(request).await? {
+            Some(_) => Ok(()),
+            None => Err(JupyterRunnerError::SessionNotFound(session_id.to_string())),
+        }
+    }
+
+    /// Returns the session bound to `path`, creating it with a new `kernel_name` kernel if there is none.
+    /// With no `kernel_name`, only an existing session is returned.
+    pub async fn session_for_path(
+        &self,
+        path: &str,
+        kernel_name: Option<&str>,
+        session_type: &str,
+    ) -> Result<SessionModel> {
+        let existing = self.list_sessions().await?.into_iter().find(|session| {
+            session.path == path
+                && match (&session.kernel, kernel_name) {
+                    (Some(kernel), Some(kernel_name)) => kernel.name == kernel_name,
+                    (Some(_), None) => true,
+                    (None, _) => false,
+                }
+        });
+        if let Some(session) = existing {
+            return Ok(session);
+        }
+
+        match kernel_name {
+            Some(kernel_name) => {
+                let name = path.rsplit('/').next().unwrap_or(path);
+                self.create_session(
+                    path,
+                    name,
+                    session_type,
+                    SessionKernel::Name(kernel_name.to_string()),
+                )
+                .await
+            }
+            None => Err(JupyterRunnerError::SessionNotFound(path.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_deserialize_session_model() {
+        let session: SessionModel = serde_json::from_str(
+            r#"{
+                "id": "s1",
+                "path": "src/analysis.py",
+                "name": "analysis.py",
+                "type": "console",
+                "kernel": {"id": "k1", "name": "python3", "last_activity": "2022-06-30T00:00:00.000000Z", "execution_state": "idle", "connections": 0},
+                "notebook": {"path": "src/analysis.py", "name": "analysis.py"}
+            }"#,
+        )
+        .unwrap();
+        assert_eq!("src/analysis.py", session.path);
+        assert_eq!("console", session.session_type);
+        assert_eq!(
+            Some("k1".to_string()),
+            session.kernel.map(|kernel| kernel.id)
+        );
+    }
+}
@@ -0,0 +1,358 @@
+use crate::error::JupyterRunnerError;
+use crate::runtime::runtime;
+use once_cell::sync::Lazy;
+use std::collections::{HashMap, VecDeque};
+use std::io::{BufRead, BufReader};
+use std::net::TcpListener;
+use std::process::{Child, Command, Stdio};
+use std::sync::{mpsc, Mutex};
+use std::time::{Duration, Instant};
+
+type Result<T> = std::result::Result<T, JupyterRunnerError>;
+
+const READY_TIMEOUT: Duration = Duration::from_secs(60);
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+// lines of the server log kept to explain why it failed to start
+const LOG_TAIL_LINES: usize = 20;
+
+/// How to launch the server. `port` 0 picks a free one.
+/// The server always listens on 127.0.0.1, as the REST and websocket clients only connect over tcp.
+#[derive(Debug, Clone)]
+pub struct ServerOptions {
+    pub executable: String,
+    /// `server` for `jupyter server`, `lab` for `jupyter lab`.
+    pub subcommand: String,
+    pub port: u16,
+    pub root_dir: Option<String>,
+}
+
+impl Default for ServerOptions {
+    fn default() -> Self {
+        Self {
+            executable: "jupyter".to_string(),
+            subcommand: "server".to_string(),
+            port: 0,
+            root_dir: None,
+        }
+    }
+}
+
+/// A server launched by this plugin.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpawnedServer {
+    pub url: String,
+    pub token: String,
+    pub pid: u32,
+    pub root_dir: Option<String>,
+    /// False until the server logs that it is listening.
+    pub ready: bool,
+}
+
+/// How the start of a spawned server ended.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ServerStartEvent {
+    Ready,
+    /// The server exited or did not get ready in time, with the tail of its log. It is no longer supervised.
+    Failed(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServerEvent {
+    pub url: String,
+    pub event: ServerStartEvent,
+}
+
+struct SupervisedServer {
+    server: SpawnedServer,
+    child: Child,
+}
+
+static SUPERVISED_SERVERS: Lazy<Mutex<HashMap<String, SupervisedServer>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+static SERVER_EVENTS: Lazy<Mutex<VecDeque<ServerEvent>>> =
+    Lazy::new(|| Mutex::new(VecDeque::new()));
+
+fn free_port() -> Result<u16> {
+    Ok(TcpListener::bind("127.0.0.1:0")?.local_addr()?.port())
+}
+
+fn server_args(options: &ServerOptions, port: u16, token: &str) -> Vec<String> {
+    let mut args = vec![
+        options.subcommand.clone(),
+        "--no-browser".to_string(),
+        "--ip=127.0.0.1".to_string(),
+        format!("--port={port}"),
+        // an occupied port must fail instead of silently moving to the next one
+        "--ServerApp.port_retries=0".to_string(),
+        format!("--ServerApp.token={token}"),
+    ];
+    if let Some(root_dir) = &options.root_dir {
+        args.push(format!("--ServerApp.root_dir={root_dir}"));
+    }
+    args
+}
+
+/// `Jupyter Server x.y.z is running at:` is logged once the server listens,
+/// followed by the urls. Either of them means requests can be sent.
+fn is_ready_line(line: &str, port: u16) -> bool {
+    line.contains("is running at") || line.contains(&format!("127.0.0.1:{port}/"))
+}
+
+/// Launches the server and returns without waiting for it. Whether it gets ready is reported by
+/// `drain_server_events`, and requests to it fail with `ServerStarting` until then.
+/// The server is registered so that `stop_servers` shuts it down.
+pub fn spawn_server(options: &ServerOptions) -> Result<SpawnedServer> {
+    let port = if options.port == 0 {
+        free_port()?
+    } else {
+        options.port
+    };
+    let token = uuid::Uuid::new_v4().simple().to_string();
+
+    let mut child = Command::new(&options.executable)
+        .args(server_args(options, port, &token))
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| {
+            JupyterRunnerError::ServerStartFailed(format!("{} :{e}", options.executable))
+        })?;
+
+    // the log has to be drained for the whole life of the server, otherwise it blocks on a full pipe.
+    let stderr = child.stderr.take().unwrap();
+    let (ready_sender, ready_receiver) = mpsc::channel::<std::result::Result<(), String>>();
+    std::thread::spawn(move || {
+        let mut tail: Vec<String> = vec![];
+        let mut ready_sender = Some(ready_sender);
+        for line in BufReader::new(stderr).lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(_) => break,
+            };
+            if let Some(sender) = &ready_sender {
+                if is_ready_line(&line, port) {
+                    let _ = sender.send(Ok(()));
+                    ready_sender = None;
+                } else {
+                    tail.push(line);
+                    if tail.len() > LOG_TAIL_LINES {
+                        tail.remove(0);
+                    }
+                }
+            }
+        }
+        if let Some(sender) = ready_sender {
+            let _ = sender.send(Err(tail.join("\n")));
+        }
+    });
+
+    let server = SpawnedServer {
+        url: format!("http://127.0.0.1:{port}/"),
+        token,
+        pid: child.id(),
+        root_dir: options.root_dir.clone(),
+        ready: false,
+    };
+    let url = server.url.clone();
+    let runtime = match runtime() {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(e);
+        }
+    };
+    SUPERVISED_SERVERS.lock().unwrap().insert(
+        server.url.clone(),
+        SupervisedServer {
+            server: server.clone(),
+            child,
+        },
+    );
+    runtime.spawn_blocking(move || {
+        let ready = ready_receiver
+            .recv_timeout(READY_TIMEOUT)
+            .unwrap_or_else(|_| Err("timed out".to_string()));
+        record_start(&url, ready);
+    });
+    Ok(server)
+}
+
+fn record_start(url: &str, ready: std::result::Result<(), String>) {
+    let mut supervised_servers = SUPERVISED_SERVERS.lock().unwrap();
+    // stopped while it started
+    if !supervised_servers.contains_key(url) {
+        return;
+    }
+    let event = match ready {
+        Ok(()) => {
+            if let Some(supervised) = supervised_servers.get_mut(url) {
+                supervised.server.ready = true;
+            }
+            ServerStartEvent::Ready
+        }
+        Err(log) => {
+            if let Some(mut supervised) = supervised_servers.remove(url) {
+                let _ = supervised.child.kill();
+                let _ = supervised.child.wait();
+            }
+            ServerStartEvent::Failed(log)
+        }
+    };
+    drop(supervised_servers);
+    SERVER_EVENTS.lock().unwrap().push_back(ServerEvent {
+        url: url.to_string(),
+        event,
+    });
+}
+
+/// Whether `url` is a spawned server that is not listening yet.
+pub fn is_starting(url: &str) -> bool {
+    matches!(
+        SUPERVISED_SERVERS.lock().unwrap().get(url),
+        Some(supervised) if !supervised.server.ready
+    )
+}
+
+pub fn drain_server_events() -> Vec<ServerEvent> {
+    SERVER_EVENTS.lock().unwrap().drain(..).collect()
+}
+
+pub fn spawned_servers() -> Vec<SpawnedServer> {
+    SUPERVISED_SERVERS
+        .lock()
+        .unwrap()
+        .values()
+        .map(|supervised| supervised.server.clone())
+        .collect()
+}
+
+/// Asks the server to stop, which also shuts its kernels down, and kills it if it does not exit in time.
+fn shutdown(child: &mut Child) {
+    #[cfg(unix)]
+    unsafe {
+        libc::kill(child.id() as libc::pid_t, libc::SIGTERM);
+    }
+    #[cfg(unix)]
+    {
+        let deadline = Instant::now() + SHUTDOWN_TIMEOUT;
+        while Instant::now() < deadline {
+            if let Ok(Some(_)) = child.try_wait() {
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(100));
+        }
+    }
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
+/// Returns false if the server at `url` was not started by this plugin.
+pub fn stop_server(url: &str) -> bool {
+    let supervised = SUPERVISED_SERVERS.lock().unwrap().remove(url);
+    match supervised {
+        Some(mut supervised) => {
+            shutdown(&mut supervised.child);
+            true
+        }
+        None => false,
+    }
+}
+
+pub fn stop_servers() {
+    let supervised: Vec<SupervisedServer> = SUPERVISED_SERVERS
+        .lock()
+        .unwrap()
+        .drain()
+        .map(|(_, supervised)| supervised)
+        .collect();
+    for mut supervised in supervised {
+        shutdown(&mut supervised.child);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_server_args() {
+        let options = ServerOptions {
+            root_dir: Some("/home/me/project".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            vec![
+                "server",
+                "--no-browser",
+                "--ip=127.0.0.1",
+                "--port=8890",
+                "--ServerApp.port_retries=0",
+                "--ServerApp.token=abc",
+                "--ServerApp.root_dir=/home/me/project",
+            ],
+            server_args(&options, 8890, "abc")
+        );
+    }
+
+    #[test]
+    fn test_is_ready_line() {
+        assert!(is_ready_line(
+            "[I 2022-07-01 10:00:00.000 ServerApp] Jupyter Server 1.18.0 is running at:",
+            8890
+        ));
+        assert!(is_ready_line(
+            "[I 2022-07-01 10:00:00.000 ServerApp]  or http://127.0.0.1:8890/lab?token=...",
+            8890
+        ));
+        assert!(!is_ready_line(
+            "[I 2022-07-01 10:00:00.000 ServerApp] jupyterlab | extension was successfully loaded.",
+            8890
+        ));
+    }
+
+    #[test]
+    fn test_spawn_server_reports_missing_executable() {
+        let options = ServerOptions {
+            executable: "run-jupyter-no-such-command".to_string(),
+            ..Default::default()
+        };
+        assert!(matches!(
+            spawn_server(&options),
+            Err(JupyterRunnerError::ServerStartFailed(_))
+        ));
+    }
+
+    #[test]
+    fn test_spawn_server_reports_exit_before_ready() {
+        let options = ServerOptions {
+            executable: "false".to_string(),
+            ..Default::default()
+        };
+        let spawned = spawn_server(&options).unwrap();
+        assert!(!spawned.ready);
+
+        let deadline = Instant::now() + Duration::from_secs(10);
+        let mut events = vec![];
+        while events.is_empty() && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(10));
+            events = drain_server_events()
+                .into_iter()
+                .filter(|server_event| server_event.url == spawned.url)
+                .collect();
+        }
+        assert!(matches!(
+            events.as_slice(),
+            [ServerEvent {
+                event: ServerStartEvent::Failed(_),
+                ..
+            }]
+        ));
+        assert!(!is_starting(&spawned.url));
+        assert!(!spawned_servers()
+            .iter()
+            .any(|server| server.url == spawned.url));
+    }
+}
@@ -0,0 +1,259 @@
+use super::error::JupyterRunnerError;
+use super::execution;
+use super::kernel_manager;
+use super::kernel_monitor::{self, KernelHealthEvent};
+use super::runtime::runtime;
+use futures_util::future::join_all;
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, timeout, Instant};
+
+type Result<T> = std::result::Result<T, JupyterRunnerError>;
+
+// how long the exit waits on each kernel, as the server may be gone already.
+const EXIT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// What becomes of a kernel started by the plugin.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ShutdownPolicy {
+    /// Deleted when neovim exits or the module is unloaded, and when idle.
+    Shutdown,
+    /// Left running on exit, but still deleted when idle while the plugin runs.
+    Leave,
+    /// Never deleted by the plugin.
+    Keep,
+}
+
+impl ShutdownPolicy {
+    pub fn try_from_str(policy: &str) -> Result<Self> {
+        match policy {
+            "shutdown" => Ok(Self::Shutdown),
+            "leave" => Ok(Self::Leave),
+            "keep" => Ok(Self::Keep),
+            other => Err(JupyterRunnerError::InvalidOption(format!(
+                "unknown shutdown policy {other}"
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LifecycleOptions {
+    /// The policy of kernels started from now on.
+    pub default_policy: ShutdownPolicy,
+    /// None keeps idle kernels running.
+    pub idle_timeout: Option<Duration>,
+}
+
+impl Default for LifecycleOptions {
+    fn default() -> Self {
+        Self {
+            default_policy: ShutdownPolicy::Shutdown,
+            idle_timeout: None,
+        }
+    }
+}
+
+struct StartedKernel {
+    policy: ShutdownPolicy,
+    started_at: SystemTime,
+    /// When code was last run, or the kernel started.
+    last_activity: Instant,
+}
+
+/// The jupyter_base_url and id of a kernel.
+type KernelKey = (String, String);
+
+static STARTED_KERNELS: Lazy<Mutex<HashMap<KernelKey, StartedKernel>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+static LIFECYCLE_OPTIONS: Lazy<Mutex<LifecycleOptions>> =
+    Lazy::new(|| Mutex::new(LifecycleOptions::default()));
+
+static IDLE_CULLER_TASK: Lazy<Mutex<Option<JoinHandle<()>>>> = Lazy::new(|| Mutex::new(None));
+
+fn key(jupyter_base_url: &str, kernel_id: &str) -> KernelKey {
+    (jupyter_base_url.to_string(), kernel_id.to_string())
+}
+
+pub fn lifecycle_options() -> LifecycleOptions {
+    *LIFECYCLE_OPTIONS.lock().unwrap()
+}
+
+/// Sets the options of the kernels started from now on, and (re)starts the idle culler if a timeout is set.
+pub fn set_lifecycle_options(options: LifecycleOptions) {
+    *LIFECYCLE_OPTIONS.lock().unwrap() = options;
+    let mut task = IDLE_CULLER_TASK.lock().unwrap();
+    if let Some(task) = task.take() {
+        task.abort();
+    }
+    if let Some(idle_timeout) = options.idle_timeout {
+        if let Ok(runtime) = runtime() {
+            *task = Some(runtime.spawn(cull_idle_kernels(idle_timeout)));
+        }
+    }
+}
+
+/// Records a kernel the plugin has started, under the default policy.
+pub fn track(jupyter_base_url: &str, kernel_id: &str) {
+    let policy = lifecycle_options().default_policy;
+    STARTED_KERNELS.lock().unwrap().insert(
+        key(jupyter_base_url, kernel_id),
+        StartedKernel {
+            policy,
+            started_at: SystemTime::now(),
+            last_activity: Instant::now(),
+        },
+    );
+}
+
+/// When the plugin started the kernel. None for kernels started elsewhere, whose start the server does not report.
+pub fn started_at(jupyter_base_url: &str, kernel_id: &str) -> Option<SystemTime> {
+    STARTED_KERNELS
+        .lock()
+        .unwrap()
+        .get(&key(jupyter_base_url, kernel_id))
+        .map(|started_kernel| started_kernel.started_at)
+}
+
+pub fn forget(kernel_id: &str) {
+    STARTED_KERNELS
+        .lock()
+        .unwrap()
+        .retain(|(_, id), _| id != kernel_id);
+}
+
+/// Returns false if the kernel was not started by the plugin, whose policy then does not apply.
+pub fn set_policy(jupyter_base_url: &str, kernel_id: &str, policy: ShutdownPolicy) -> bool {
+    match STARTED_KERNELS
+        .lock()
+        .unwrap()
+        .get_mut(&key(jupyter_base_url, kernel_id))
+    {
+        Some(started_kernel) => {
+            started_kernel.policy = policy;
+            true
+        }
+        None => false,
+    }
+}
+
+/// Marks the kernel as in use, which puts off its idle shutdown.
+pub fn touch(jupyter_base_url: &str, kernel_id: &str) {
+    if let Some(started_kernel) = STARTED_KERNELS
+        .lock()
+        .unwrap()
+        .get_mut(&key(jupyter_base_url, kernel_id))
+    {
+        started_kernel.last_activity = Instant::now();
+    }
+}
+
+/// The started kernels `should_shut_down` picks, taken out of the tracked ones.
+fn take_kernels<P>(mut should_shut_down: P) -> Vec<(KernelKey, StartedKernel)>
+where
+    P: FnMut(&KernelKey, &StartedKernel) -> bool,
+{
+    let mut started_kernels = STARTED_KERNELS.lock().unwrap();
+    let keys: Vec<KernelKey> = started_kernels
+        .iter()
+        .filter(|(key, started_kernel)| should_shut_down(key, started_kernel))
+        .map(|(key, _)| key.clone())
+        .collect();
+    keys.into_iter()
+        .filter_map(|key| {
+            let started_kernel = started_kernels.remove(&key)?;
+            Some((key, started_kernel))
+        })
+        .collect()
+}
+
+/// Deletes the started kernels whose policy is `shutdown`, and returns how many were.
+/// Called when neovim exits and when the module is unloaded.
+pub async fn shutdown_on_exit() -> usize {
+    let kernels =
+        take_kernels(|_, started_kernel| started_kernel.policy == ShutdownPolicy::Shutdown);
+    join_all(kernels.iter().map(|((jupyter_base_url, kernel_id), _)| {
+        timeout(
+            EXIT_SHUTDOWN_TIMEOUT,
+            kernel_manager::shutdown_kernel(jupyter_base_url, kernel_id),
+        )
+    }))
+    .await
+    .into_iter()
+    .filter(|shutdown| matches!(shutdown, Ok(Ok(()))))
+    .count()
+}
+
+fn is_idle(key: &KernelKey, started_kernel: &StartedKernel, idle_timeout: Duration) -> bool {
+    started_kernel.policy != ShutdownPolicy::Keep
+        && started_kernel.last_activity.elapsed() >= idle_timeout
+        // code running longer than the timeout counts as activity.
+        && execution::queued_executions(&key.0, &key.1).is_empty()
+}
+
+async fn cull_idle_kernels(idle_timeout: Duration) {
+    let check_interval = idle_timeout.min(MAX_IDLE_CHECK_INTERVAL);
+    loop {
+        sleep(check_interval).await;
+        let idle_kernels =
+            take_kernels(|key, started_kernel| is_idle(key, started_kernel, idle_timeout));
+        for (key, started_kernel) in idle_kernels {
+            let (jupyter_base_url, kernel_id) = &key;
+            match kernel_manager::shutdown_kernel(jupyter_base_url, kernel_id).await {
+                Ok(()) => kernel_monitor::push_event(
+                    jupyter_base_url,
+                    kernel_id,
+                    KernelHealthEvent::Culled,
+                ),
+                // gone already
+                Err(JupyterRunnerError::KernelNotFound(_)) => {}
+                // tried again on the next check, and still shut down on exit.
+                Err(_) => {
+                    STARTED_KERNELS
+                        .lock()
+                        .unwrap()
+                        .entry(key)
+                        .or_insert(started_kernel);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_shutdown_policy_from_str() {
+        assert_eq!(
+            ShutdownPolicy::Leave,
+            ShutdownPolicy::try_from_str("leave").unwrap()
+        );
+        assert!(ShutdownPolicy::try_from_str("delete").is_err());
+    }
+
+    #[test]
+    fn test_is_idle() {
+        let key = key("http://localhost:8888", "idle-kernel");
+        let started_kernel = StartedKernel {
+            policy: ShutdownPolicy::Leave,
+            started_at: SystemTime::now(),
+            last_activity: Instant::now(),
+        };
+        assert!(is_idle(&key, &started_kernel, Duration::ZERO));
+        assert!(!is_idle(&key, &started_kernel, Duration::from_secs(60)));
+
+        let kept_kernel = StartedKernel {
+            policy: ShutdownPolicy::Keep,
+            started_at: SystemTime::now(),
+            last_activity: Instant::now(),
+        };
+        assert!(!is_idle(&key, &kept_kernel, Duration::ZERO));
+    }
+}
@@ -0,0 +1,125 @@
+use super::client_cache;
+use super::error::JupyterRunnerError;
+use super::kernel::{self, CodeCompleteness};
+use super::parser::statement::{continues_statement, indent_width, is_complete_statement};
+use super::parser::ParsableKernel;
+use std::time::Duration;
+use tokio::time::timeout;
+
+type Result<T> = std::result::Result<T, JupyterRunnerError>;
+
+// a kernel busy executing answers only once it is done, so the parsers decide instead.
+const IS_COMPLETE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The line counts at which the statement starting at `lines[0]` may end: after each non-blank line
+/// that the next non-blank line does not continue.
+fn candidate_ends(kernel: Option<&ParsableKernel>, lines: &[String]) -> Vec<usize> {
+    let statement_indent = match lines.first() {
+        Some(first_line) => indent_width(first_line),
+        None => return vec![],
+    };
+    let non_blank_lines: Vec<usize> = lines
+        .iter()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, _)| i)
+        .collect();
+    non_blank_lines
+        .iter()
+        .enumerate()
+        .filter_map(|(i, line)| match non_blank_lines.get(i + 1) {
+            Some(next_line)
+                if continues_statement(kernel, statement_indent, &lines[*next_line]) =>
+            {
+                None
+            }
+            _ => Some(line + 1),
+        })
+        .collect()
+}
+
+/// The number of lines of the smallest complete statement at the start of `lines`, the buffer from the cursor on.
+/// The kernel is asked with `is_complete_request` as the range grows. The tree-sitter parsers decide for kernels
+/// that answer `unknown` or not in time, and the indentation alone for languages without one.
+pub async fn statement_range(
+    jupyter_base_url: &str,
+    kernel_id: &str,
+    lines: &[String],
+) -> Result<usize> {
+    match lines.first() {
+        Some(first_line) if !first_line.trim().is_empty() => {}
+        _ => return Ok(lines.len().min(1)),
+    }
+    let kernel_client = client_cache::kernel_client(jupyter_base_url, kernel_id).await?;
+    let parsable_kernel = kernel_client
+        .kernel_info()
+        .await
+        .ok()
+        .and_then(|kernel_info| {
+            ParsableKernel::try_from_language(&kernel_info.language_info.name).ok()
+        })
+        .or_else(|| ParsableKernel::try_from_str(&kernel_client.kernel_name).ok());
+    let candidate_ends = candidate_ends(parsable_kernel.as_ref(), lines);
+
+    let mut ask_kernel = true;
+    for end in candidate_ends.iter().copied() {
+        let code = lines[..end].join("\n");
+        if ask_kernel {
+            // the blank line closes indented blocks, as in a console.
+            let completeness = timeout(
+                IS_COMPLETE_TIMEOUT,
+                kernel::is_complete(&kernel_client.connection, &format!("{code}\n\n")),
+            )
+            .await
+            .unwrap_or(Ok(CodeCompleteness::Unknown))?;
+            match completeness {
+                // an invalid statement is sent as it is, for the kernel to report the error.
+                CodeCompleteness::Complete | CodeCompleteness::Invalid => return Ok(end),
+                CodeCompleteness::Incomplete { .. } => continue,
+                CodeCompleteness::Unknown => ask_kernel = false,
+            }
+        }
+        match &parsable_kernel {
+            Some(parsable_kernel) => {
+                if is_complete_statement(parsable_kernel, &code)? {
+                    return Ok(end);
+                }
+            }
+            None => return Ok(end),
+        }
+    }
+    Ok(candidate_ends.last().copied().unwrap_or(1))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn lines(code: &str) -> Vec<String> {
+        code.split('\n').map(|line| line.to_string()).collect()
+    }
+
+    #[test]
+    fn test_candidate_ends_of_python_block() {
+        let code = lines("for x in y:\n    print(x)\n\n    print(y)\nelse:\n    pass\n\nz = 1");
+        assert_eq!(
+            vec![6, 8],
+            candidate_ends(Some(&ParsableKernel::Python3), &code)
+        );
+    }
+
+    #[test]
+    fn test_candidate_ends_of_rust_expression() {
+        let code = lines("let v = vec![\n    1,\n];\nv.iter()\n.count()");
+        assert_eq!(
+            vec![3, 5],
+            candidate_ends(Some(&ParsableKernel::Rust), &code)
+        );
+    }
+
+    #[test]
+    fn test_candidate_ends_without_language() {
+        let code = lines("a\n  b\nelse\n");
+        assert_eq!(vec![2, 3], candidate_ends(None, &code));
+    }
+}